@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use dotenv::dotenv;
+use env_logger::init;
+use failure::Fallible;
+use futures::stream::StreamExt;
+use log::info;
+use pixiu::{
+    Deployment, Exchange, ExchangeHandle, Recorder, Strategy, StrategyRegistry, Venue, XTPExchange,
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use tokio::sync::broadcast::Receiver;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "deploy",
+    about = "Runs the deployment described by a config file."
+)]
+struct Args {
+    /// A .toml, .yaml or .yml file, see examples/deploy.toml.
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Fallible<()> {
+    let _ = dotenv();
+    init();
+
+    let args = Args::from_args();
+
+    let mut registry = StrategyRegistry::new();
+    registry.register("logger", QuoteLogger::new);
+    registry.register("recorder", |p: RecorderParams| {
+        Recorder::new(p.dir)
+            .market_data(&tickers(&p.sh), Venue::SH)
+            .market_data(&tickers(&p.sz), Venue::SZ)
+    });
+
+    Deployment::from_file(&args.config)?.run(&registry).await?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Tickers {
+    sh: Vec<String>,
+    sz: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecorderParams {
+    dir: PathBuf,
+    #[serde(default)]
+    sh: Vec<String>,
+    #[serde(default)]
+    sz: Vec<String>,
+}
+
+fn tickers(owned: &[String]) -> Vec<&str> {
+    owned.iter().map(String::as_str).collect()
+}
+
+/// Logs the quotes of its tickers.
+struct QuoteLogger {
+    tickers: Tickers,
+}
+
+impl QuoteLogger {
+    fn new(tickers: Tickers) -> Self {
+        QuoteLogger { tickers }
+    }
+}
+
+#[async_trait]
+impl Strategy<XTPExchange> for QuoteLogger {
+    async fn run(
+        self: Box<Self>,
+        mut rx: Receiver<<XTPExchange as Exchange>::Event>,
+        h: <XTPExchange as Exchange>::Handle,
+    ) {
+        if !self.tickers.sh.is_empty() {
+            h.subscribe_market_data(&tickers(&self.tickers.sh), Venue::SH)
+                .unwrap();
+        }
+        if !self.tickers.sz.is_empty() {
+            h.subscribe_market_data(&tickers(&self.tickers.sz), Venue::SZ)
+                .unwrap();
+        }
+
+        while let Some(msg) = rx.next().await {
+            info!("Received {:?}", msg);
+        }
+    }
+}
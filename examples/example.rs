@@ -3,20 +3,19 @@ use dotenv::dotenv;
 use env_logger::init;
 use failure::Fallible;
 use futures::stream::StreamExt;
-use log::info;
-use pixiu::{Exchange, Strategy, XTPExchange};
+use log::{error, info};
+use pixiu::{Exchange, ExchangeHandle, Strategy, Venue, XTPExchange};
 use std::net::SocketAddrV4;
 use std::thread::sleep;
 use std::time::Duration;
 use structopt::StructOpt;
 use tokio::sync::broadcast::Receiver;
-use xtp::XTPExchangeType;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "example", about = "An example of xtp-rs usage.")]
 struct Args {
     #[structopt(short, long, default_value = "1")]
-    id: i8,
+    id: u8,
     #[structopt(short, long, env = "XTP_QUOTE_ADDR")]
     quote_addr: SocketAddrV4,
     #[structopt(short, long, env = "XTP_TRADER_ADDR")]
@@ -38,18 +37,23 @@ async fn main() -> Fallible<()> {
 
     let args = Args::from_args();
 
-    let mut exch = XTPExchange::new(
-        args.quote_addr,
-        args.trader_addr,
-        &args.username,
-        &args.password,
-        &args.key,
-    );
+    let mut exch = XTPExchange::builder(args.quote_addr, args.trader_addr)
+        .credentials(&args.username, &args.password)
+        .software_key(&args.key)
+        .quote_client_id(args.id)
+        .trader_client_id(args.id)
+        .log_path(&args.path)
+        .build();
 
     exch.register(MyStrategy::new(1));
     exch.register(MyStrategy::new(2));
 
-    tokio::spawn(exch.run());
+    exch.connect().await?;
+    tokio::spawn(async move {
+        if let Err(e) = exch.run().await {
+            error!("Exchange stopped: {}", e);
+        }
+    });
     sleep(Duration::from_secs(1000));
 
     Ok(())
@@ -81,10 +85,8 @@ impl Strategy<XTPExchange> for MyStrategy {
         info!("MyStrategy {} running", self.id);
         let codes_sh = ["600036"];
         let codes_sz = ["000001"];
-        h.subscribe_market_data(&codes_sh, XTPExchangeType::SH)
-            .unwrap();
-        h.subscribe_market_data(&codes_sz, XTPExchangeType::SZ)
-            .unwrap();
+        h.subscribe_market_data(&codes_sh, Venue::SH).unwrap();
+        h.subscribe_market_data(&codes_sz, Venue::SZ).unwrap();
 
         self.rx = Some(rx);
         self.h = Some(h);
@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use env_logger::init;
+use failure::Fallible;
+use futures::stream::StreamExt;
+use log::info;
+use pixiu::{
+    Event, Exchange, ExchangeHandle, OrderRequest, QuoteEvent, RandomWalk, ReplayExchange, Side,
+    SimExchange, Strategy, Venue,
+};
+use std::env;
+use tokio::sync::broadcast::Receiver;
+
+/// Buys 100 shares whenever the price ticks down. Runs on generated quotes,
+/// or on the recordings of the directory given as argument, and would run
+/// unchanged on `XTPExchange`.
+struct DipBuyer;
+
+#[async_trait]
+impl<E> Strategy<E> for DipBuyer
+where
+    E: Exchange<Event = Event> + 'static,
+{
+    async fn run(self: Box<Self>, mut rx: Receiver<Event>, h: E::Handle) {
+        h.subscribe_market_data(&["600036"], Venue::SH).unwrap();
+
+        let mut last_price = None;
+        while let Some(msg) = rx.next().await {
+            match msg {
+                Ok(Event::MarketData(QuoteEvent::Quote(quote))) => {
+                    if last_price.map_or(false, |last| quote.last_price < last) {
+                        let req = OrderRequest::limit(
+                            &quote.instrument.ticker,
+                            Venue::SH,
+                            Side::Buy,
+                            quote.last_price.to_f64(),
+                            100,
+                        );
+                        if let Err(e) = h.insert_order(&req) {
+                            info!("Order refused: {}", e);
+                        }
+                    }
+                    last_price = Some(quote.last_price);
+                }
+                Ok(Event::Fill(fill)) => {
+                    info!("Filled {} @ {}", fill.quantity, fill.price);
+                }
+                _ => {}
+            }
+        }
+        let portfolio = h.portfolio();
+        info!(
+            "Done, realized {} unrealized {}",
+            portfolio.realized_pnl(),
+            portfolio.unrealized_pnl()
+        );
+    }
+}
+
+#[tokio::main(basic_scheduler)]
+async fn main() -> Fallible<()> {
+    init();
+
+    if let Some(dir) = env::args().nth(1) {
+        let mut exch = ReplayExchange::open(dir, None, None)?;
+        exch.register(DipBuyer);
+        exch.run().await?;
+    } else {
+        let quotes = RandomWalk::new("600036", Venue::SH, 20200102, 35., 1000).seed(42);
+        let mut exch = SimExchange::new(quotes);
+        exch.register(DipBuyer);
+        exch.run().await?;
+    }
+
+    Ok(())
+}
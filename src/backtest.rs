@@ -0,0 +1,169 @@
+mod report;
+
+pub use self::report::{BacktestReport, EquityPoint};
+use crate::exchanges::replay::{recorded_events, recording_files};
+use crate::exchanges::sim::{
+    millis_of_day, FeeSchedule, FillModel, OnSim, SimExchange, SimExchangeHandle,
+};
+use crate::exchanges::xtp::trading_day;
+use crate::instruments::InstrumentMaster;
+use crate::market::Quote;
+use crate::risk::RiskLimits;
+use crate::{Event, Exchange, Result, Strategy};
+use async_trait::async_trait;
+use log::info;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Runs strategies over historical quotes as fast as they keep up, and
+/// reports how they did. It is a `SimExchange` where resting orders wait
+/// behind the quantity displayed at their price, fills pay A-share fees and
+/// buys are limited by the cash. A strategy implemented for every exchange
+/// runs unchanged on it.
+///
+/// Time is the one of the quotes: timers and the equity curve follow their
+/// exchange timestamps, not the wall clock. Use a basic scheduler runtime
+/// for reproducible results.
+pub struct Backtest {
+    sim: SimExchange,
+    initial_capital: f64,
+    sample_interval: Duration,
+}
+
+impl Backtest {
+    /// A backtest over `quotes`, in order.
+    pub fn new<I>(quotes: I) -> Self
+    where
+        I: IntoIterator<Item = Quote>,
+        I::IntoIter: Send + 'static,
+    {
+        Backtest::with_sim(SimExchange::new(quotes))
+    }
+
+    /// A backtest over the recordings in `dir`, or only the trading days
+    /// (YYYYMMDD) within `from` and `to` inclusive.
+    pub fn from_recordings<P: AsRef<Path>>(
+        dir: P,
+        from: Option<u32>,
+        to: Option<u32>,
+    ) -> io::Result<Self> {
+        let events = recorded_events(recording_files(dir, from, to)?);
+        Ok(Backtest::with_sim(SimExchange::from_events(events)))
+    }
+
+    fn with_sim(mut sim: SimExchange) -> Self {
+        sim.set_fill_model(FillModel {
+            queue_position: true,
+            ..FillModel::default()
+        });
+        sim.set_fees(FeeSchedule::a_share());
+        Backtest {
+            sim,
+            initial_capital: 1_000_000.,
+            sample_interval: Duration::from_secs(60),
+        }
+    }
+
+    /// Starting cash, defaults to 1,000,000.
+    pub fn set_initial_capital(&mut self, capital: f64) {
+        self.initial_capital = capital;
+    }
+
+    /// Spacing of the equity curve in market time, defaults to a minute.
+    /// Also the period of the returns the Sharpe ratio is computed from.
+    pub fn set_sample_interval(&mut self, interval: Duration) {
+        self.sample_interval = interval;
+    }
+
+    /// Queue position without slippage by default.
+    pub fn set_fill_model(&mut self, fill_model: FillModel) {
+        self.sim.set_fill_model(fill_model);
+    }
+
+    /// `FeeSchedule::a_share()` by default.
+    pub fn set_fees(&mut self, fees: FeeSchedule) {
+        self.sim.set_fees(fees);
+    }
+
+    pub fn set_risk_limits(&mut self, limits: RiskLimits) {
+        self.sim.set_risk_limits(limits);
+    }
+
+    /// Static data for the lot and tick size checks, see
+    /// `SimExchange::set_instruments`.
+    pub fn set_instruments(&mut self, instruments: Arc<InstrumentMaster>) {
+        self.sim.set_instruments(instruments);
+    }
+
+    pub fn set_timer_interval(&mut self, interval: Duration) {
+        self.sim.set_timer_interval(interval);
+    }
+
+    pub fn set_strategy_capacity(&mut self, capacity: usize) {
+        self.sim.set_strategy_capacity(capacity);
+    }
+
+    /// Run the strategies over all the quotes and wait for them to finish.
+    pub async fn run_with_report(mut self) -> Result<BacktestReport> {
+        self.sim.set_initial_capital(self.initial_capital);
+
+        // One point per interval of each trading day, the last one seen.
+        let interval = self.sample_interval.as_millis().max(1) as i64;
+        let samples = Arc::new(Mutex::new(vec![]));
+        let sampled = samples.clone();
+        let mut last_bucket = None;
+        self.sim.set_observer(move |engine| {
+            let time = engine.time();
+            let bucket = (trading_day(time), millis_of_day(time) / interval);
+            let point = EquityPoint {
+                time,
+                equity: engine.equity(),
+            };
+            let mut samples = sampled.lock().unwrap();
+            match samples.last_mut() {
+                Some(last) if last_bucket == Some(bucket) => *last = point,
+                _ => samples.push(point),
+            }
+            last_bucket = Some(bucket);
+        });
+
+        let engine = self.sim.engine();
+        self.sim.run().await?;
+
+        let engine = engine.lock().unwrap();
+        let equity_curve = mem::replace(&mut *samples.lock().unwrap(), vec![]);
+        Ok(BacktestReport::new(
+            self.initial_capital,
+            &engine,
+            equity_curve,
+            self.sample_interval,
+        ))
+    }
+}
+
+#[async_trait]
+impl Exchange for Backtest {
+    type Event = Event;
+    type Handle = SimExchangeHandle;
+
+    async fn connect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Run the backtest and log its report, see `run_with_report`.
+    async fn run(self) -> Result<()> {
+        let report = self.run_with_report().await?;
+        info!("{}", report);
+        Ok(())
+    }
+
+    fn register<S>(&mut self, s: S)
+    where
+        S: Strategy<Backtest> + Send + Sync + 'static,
+    {
+        self.sim.register(OnSim::<Backtest>(Box::new(s)))
+    }
+}
@@ -0,0 +1,254 @@
+use crate::exchanges::sim::{session_millis, MatchingEngine};
+use crate::exchanges::xtp::trading_day;
+use crate::Fill;
+use std::fmt;
+use std::time::Duration;
+
+const TRADING_DAYS_PER_YEAR: f64 = 252.;
+const TRADING_MILLIS_PER_DAY: i64 = 4 * 3_600_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EquityPoint {
+    /// YYYYMMDDHHMMSSsss of the latest quote in the interval.
+    pub time: i64,
+    /// Cash plus the market value of the positions.
+    pub equity: f64,
+}
+
+/// How a backtest went. Amounts are in the currency of the quotes, net of
+/// fees unless said otherwise.
+#[derive(Debug, Clone)]
+pub struct BacktestReport {
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    /// `final_equity / initial_capital - 1`.
+    pub total_return: f64,
+    /// Realized PnL of the positions, before fees.
+    pub realized_pnl: f64,
+    pub fees: f64,
+    /// Largest fall of the equity from a previous high, and that fall
+    /// relative to the high.
+    pub max_drawdown: f64,
+    pub max_drawdown_pct: f64,
+    /// Annualized Sharpe ratio of the returns over each sample interval of
+    /// trading time, without a risk free rate. Nights and lunch breaks do
+    /// not count, intervals without quotes return nothing. `None` without
+    /// enough points or with flat equity.
+    pub sharpe: Option<f64>,
+    /// Traded amount over both sides, and that amount relative to the
+    /// initial capital.
+    pub turnover: f64,
+    pub turnover_ratio: f64,
+    pub equity_curve: Vec<EquityPoint>,
+    pub trades: Vec<Fill>,
+}
+
+impl BacktestReport {
+    pub(crate) fn new(
+        initial_capital: f64,
+        engine: &MatchingEngine,
+        equity_curve: Vec<EquityPoint>,
+        sample_interval: Duration,
+    ) -> Self {
+        let final_equity = engine.equity();
+        let equities: Vec<f64> = std::iter::once(initial_capital)
+            .chain(equity_curve.iter().map(|p| p.equity))
+            .collect();
+        let (max_drawdown, max_drawdown_pct) = drawdown(&equities);
+        let turnover = engine
+            .trades()
+            .iter()
+            .map(|t| t.price * t.quantity as f64)
+            .sum::<f64>();
+
+        BacktestReport {
+            initial_capital,
+            final_equity,
+            total_return: ratio(final_equity, initial_capital) - 1.,
+            realized_pnl: engine.portfolio().realized_pnl(),
+            fees: engine.fees_paid(),
+            max_drawdown,
+            max_drawdown_pct,
+            sharpe: sharpe(
+                &resample(initial_capital, &equity_curve, sample_interval),
+                sample_interval,
+            ),
+            turnover,
+            turnover_ratio: ratio(turnover, initial_capital),
+            equity_curve,
+            trades: engine.trades().to_vec(),
+        }
+    }
+}
+
+fn ratio(a: f64, b: f64) -> f64 {
+    if b == 0. {
+        0.
+    } else {
+        a / b
+    }
+}
+
+fn drawdown(equities: &[f64]) -> (f64, f64) {
+    let mut peak = std::f64::MIN;
+    let mut max_drawdown = 0.;
+    let mut max_drawdown_pct = 0.;
+    for &equity in equities {
+        peak = peak.max(equity);
+        let drawdown = peak - equity;
+        if drawdown > max_drawdown {
+            max_drawdown = drawdown;
+        }
+        let pct = ratio(drawdown, peak);
+        if pct > max_drawdown_pct {
+            max_drawdown_pct = pct;
+        }
+    }
+    (max_drawdown, max_drawdown_pct)
+}
+
+// The equity at the end of each interval of trading time, from the one
+// before the first point, which starts at the initial capital. Intervals
+// without a point keep the equity of the previous one.
+fn resample(initial_capital: f64, curve: &[EquityPoint], sample_interval: Duration) -> Vec<f64> {
+    let interval = (sample_interval.as_millis() as i64).max(1);
+    let mut equities = vec![initial_capital];
+    let mut days = 0;
+    let mut last_day = None;
+    let mut last_bucket = None;
+    for point in curve {
+        let day = trading_day(point.time);
+        if last_day.map_or(false, |last| last != day) {
+            days += 1;
+        }
+        last_day = Some(day);
+        let bucket = (days * TRADING_MILLIS_PER_DAY + session_millis(point.time)) / interval;
+        match last_bucket {
+            Some(last) if bucket <= last => {
+                *equities.last_mut().unwrap() = point.equity;
+                continue;
+            }
+            Some(last) => {
+                let previous = *equities.last().unwrap();
+                equities.extend((last + 1..bucket).map(|_| previous));
+            }
+            None => {}
+        }
+        equities.push(point.equity);
+        last_bucket = Some(bucket);
+    }
+    equities
+}
+
+fn sharpe(equities: &[f64], sample_interval: Duration) -> Option<f64> {
+    let returns: Vec<f64> = equities
+        .windows(2)
+        .filter(|w| w[0] != 0.)
+        .map(|w| w[1] / w[0] - 1.)
+        .collect();
+    if returns.len() < 2 {
+        return None;
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.);
+    if variance <= 0. {
+        return None;
+    }
+    let interval = (sample_interval.as_millis() as f64).max(1.);
+    let periods_per_year = TRADING_DAYS_PER_YEAR * TRADING_MILLIS_PER_DAY as f64 / interval;
+    Some(mean / variance.sqrt() * periods_per_year.sqrt())
+}
+
+impl fmt::Display for BacktestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Initial capital  {:.2}", self.initial_capital)?;
+        writeln!(f, "Final equity     {:.2}", self.final_equity)?;
+        writeln!(f, "Total return     {:.4}%", self.total_return * 100.)?;
+        writeln!(f, "Realized PnL     {:.2}", self.realized_pnl)?;
+        writeln!(f, "Fees             {:.2}", self.fees)?;
+        writeln!(
+            f,
+            "Max drawdown     {:.2} ({:.4}%)",
+            self.max_drawdown,
+            self.max_drawdown_pct * 100.
+        )?;
+        match self.sharpe {
+            Some(sharpe) => writeln!(f, "Sharpe           {:.3}", sharpe)?,
+            None => writeln!(f, "Sharpe           n/a")?,
+        }
+        writeln!(
+            f,
+            "Turnover         {:.2} ({:.2}x)",
+            self.turnover, self.turnover_ratio
+        )?;
+        write!(f, "Trades           {}", self.trades.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn curve(points: &[(i64, f64)]) -> Vec<EquityPoint> {
+        points
+            .iter()
+            .map(|&(time, equity)| EquityPoint { time, equity })
+            .collect()
+    }
+
+    #[test]
+    fn drawdown_is_the_largest_fall_from_a_high() {
+        let (max_drawdown, max_drawdown_pct) = drawdown(&[100., 120., 90., 110., 60., 130.]);
+        assert!((max_drawdown - 60.).abs() < 1e-9);
+        assert!((max_drawdown_pct - 0.5).abs() < 1e-9);
+        assert_eq!(drawdown(&[100., 110., 120.]), (0., 0.));
+    }
+
+    #[test]
+    fn flat_equity_has_no_sharpe() {
+        assert_eq!(sharpe(&[100., 100., 100., 100.], MINUTE), None);
+        assert_eq!(sharpe(&[100., 101.], MINUTE), None);
+    }
+
+    #[test]
+    fn the_night_is_not_an_interval() {
+        let overnight = curve(&[
+            (20200102145800000, 101.),
+            (20200102145900000, 100.),
+            (20200103093000000, 102.),
+            (20200103093100000, 103.),
+        ]);
+        let same_day = curve(&[
+            (20200102145600000, 101.),
+            (20200102145700000, 100.),
+            (20200102145800000, 102.),
+            (20200102145900000, 103.),
+        ]);
+        let equities = resample(100., &overnight, MINUTE);
+        assert_eq!(equities, vec![100., 101., 100., 102., 103.]);
+        assert_eq!(equities, resample(100., &same_day, MINUTE));
+        assert!(sharpe(&equities, MINUTE).is_some());
+    }
+
+    #[test]
+    fn quiet_intervals_keep_the_equity() {
+        let quiet = curve(&[
+            (20200102093000000, 101.),
+            (20200102093010000, 102.),
+            (20200102093300000, 104.),
+            (20200102113000000, 103.),
+            (20200102130000000, 105.),
+        ]);
+        assert_eq!(
+            resample(100., &quiet, MINUTE),
+            vec![100., 102., 102., 102., 104.]
+                .into_iter()
+                .chain(std::iter::repeat(104.).take(116))
+                .chain(vec![105.])
+                .collect::<Vec<_>>()
+        );
+    }
+}
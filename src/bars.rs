@@ -0,0 +1,669 @@
+//! OHLCV bars built from depth snapshots or tick-by-tick trades, following
+//! the A-share trading sessions.
+//!
+//! Bars are aligned on the start of the continuous sessions, 09:30 and
+//! 13:00. The opening call auction goes into the first bar of the morning,
+//! the closing call auction into the last bar of the afternoon, and anything
+//! stamped during the lunch break into the last bar of the morning. The
+//! last bar of a session closes some seconds after the session ends, to
+//! catch the snapshots published with the auction results. Intervals without
+//! trades produce no bar.
+
+use crate::exchanges::sim::millis_of_day;
+use crate::exchanges::xtp::{trading_day, StrategyId};
+use crate::market::{Price, Venue};
+use crate::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const OPENING_AUCTION: i64 = hms(9, 15);
+const SESSIONS: [(i64, i64); 2] = [(hms(9, 30), hms(11, 30)), (hms(13, 0), hms(15, 0))];
+// How long the last bar of a session waits for late snapshots.
+const SESSION_GRACE: i64 = 10_000;
+const MILLIS_PER_DAY: i64 = 24 * 3_600_000;
+
+const fn hms(hours: i64, minutes: i64) -> i64 {
+    (hours * 60 + minutes) * 60_000
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    pub venue: Venue,
+    pub ticker: String,
+    pub interval: Duration,
+    /// YYYYMMDDHHMMSSsss, the bar covers `[start, end)`.
+    pub start: i64,
+    pub end: i64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: i64,
+    pub turnover: f64,
+}
+
+/// What bars are built from. Depth snapshots are cheaper to subscribe to,
+/// tick-by-tick trades give the exact high and low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BarSource {
+    Depth,
+    TickByTick,
+}
+
+impl Default for BarSource {
+    fn default() -> Self {
+        BarSource::Depth
+    }
+}
+
+// The bar being built for one ticker.
+struct Series {
+    bar: Option<Bar>,
+    // When the current bar closes even without a later event.
+    close_at: i64,
+    // End of the last bar closed, events before it are late.
+    closed_until: i64,
+    // Trading day, cumulative volume and turnover of the latest snapshot.
+    last_depth: Option<(u32, i64, f64)>,
+}
+
+/// Builds bars of one interval for any number of tickers. Feed it either
+/// depth snapshots or trades for a given ticker, not both.
+pub struct BarBuilder {
+    interval: Duration,
+    series: HashMap<(Venue, String), Series>,
+}
+
+impl BarBuilder {
+    pub fn new(interval: Duration) -> Self {
+        BarBuilder {
+            interval,
+            series: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Add a depth snapshot, with the cumulative volume and turnover of the
+    /// day. Returns the bar it closed, if any.
+    pub fn on_depth(
+        &mut self,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        last_price: f64,
+        volume: i64,
+        turnover: f64,
+    ) -> Option<Bar> {
+        let day = trading_day(data_time);
+        let series = self.series_mut(venue, ticker);
+        let (traded, amount) = match series.last_depth {
+            Some((last_day, last_volume, last_turnover)) if last_day == day => {
+                (volume - last_volume, turnover - last_turnover)
+            }
+            // Only count what traded before the first snapshot seen when
+            // that is the opening auction.
+            _ if millis_of_day(data_time) < SESSIONS[0].0 => (volume, turnover),
+            _ => (0, 0.),
+        };
+        if traded <= 0 || last_price <= 0. {
+            series.last_depth = Some((day, volume, turnover));
+            return None;
+        }
+        // Leave what late snapshots traded to the next bar.
+        if self.is_late(venue, ticker, data_time) {
+            return None;
+        }
+        self.series_mut(venue, ticker).last_depth = Some((day, volume, turnover));
+        self.add(venue, ticker, data_time, last_price, traded, amount)
+    }
+
+    /// Add a trade. Returns the bar it closed, if any.
+    pub fn on_trade(
+        &mut self,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        price: f64,
+        quantity: i64,
+        amount: f64,
+    ) -> Option<Bar> {
+        // Shenzhen reports cancellations as trades without a price.
+        if price <= 0. || quantity <= 0 {
+            return None;
+        }
+        if self.is_late(venue, ticker, data_time) {
+            return None;
+        }
+        self.add(venue, ticker, data_time, price, quantity, amount)
+    }
+
+    /// Close the bars that ended by `time`, e.g. the exchange time of the
+    /// latest event of any ticker.
+    pub fn close_until(&mut self, time: i64) -> Vec<Bar> {
+        let mut closed = vec![];
+        for series in self.series.values_mut() {
+            if series.bar.is_some() && series.close_at <= time {
+                closed.extend(close(series));
+            }
+        }
+        closed
+    }
+
+    /// Close every bar, at the end of the data.
+    pub fn flush(&mut self) -> Vec<Bar> {
+        self.series.values_mut().filter_map(close).collect()
+    }
+
+    /// Stop building bars for a ticker, dropping its current bar.
+    pub fn remove(&mut self, venue: Venue, ticker: &str) {
+        self.series.remove(&(venue, ticker.to_string()));
+    }
+
+    fn series_mut(&mut self, venue: Venue, ticker: &str) -> &mut Series {
+        self.series
+            .entry((venue, ticker.to_string()))
+            .or_insert_with(|| Series {
+                bar: None,
+                close_at: 0,
+                closed_until: 0,
+                last_depth: None,
+            })
+    }
+
+    // Whether the bar of `data_time` was already closed.
+    fn is_late(&mut self, venue: Venue, ticker: &str, data_time: i64) -> bool {
+        let closed_until = self.series_mut(venue, ticker).closed_until;
+        window(data_time, self.interval).map_or(false, |(_, end, _)| end <= closed_until)
+    }
+
+    fn add(
+        &mut self,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        price: f64,
+        quantity: i64,
+        amount: f64,
+    ) -> Option<Bar> {
+        let interval = self.interval;
+        let (start, end, close_at) = window(data_time, interval)?;
+        let price = Price::from_f64(price);
+        let series = self.series_mut(venue, ticker);
+        let closed = if series.bar.as_ref().map_or(false, |bar| bar.start != start) {
+            close(series)
+        } else {
+            None
+        };
+
+        match &mut series.bar {
+            Some(bar) => {
+                bar.high = bar.high.max(price);
+                bar.low = bar.low.min(price);
+                bar.close = price;
+                bar.volume += quantity;
+                bar.turnover += amount;
+            }
+            None => {
+                series.close_at = close_at;
+                series.bar = Some(Bar {
+                    venue,
+                    ticker: ticker.to_string(),
+                    interval,
+                    start,
+                    end,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume: quantity,
+                    turnover: amount,
+                });
+            }
+        }
+        closed
+    }
+}
+
+fn close(series: &mut Series) -> Option<Bar> {
+    let bar = series.bar.take()?;
+    series.closed_until = bar.end;
+    Some(bar)
+}
+
+// Start, end and closing time of the bar `data_time` falls in, `None`
+// before the opening auction.
+fn window(data_time: i64, interval: Duration) -> Option<(i64, i64, i64)> {
+    let interval = (interval.as_millis() as i64).max(1);
+    let day = trading_day(data_time);
+    let millis = millis_of_day(data_time);
+    if millis < OPENING_AUCTION {
+        return None;
+    }
+    let (morning, afternoon) = (SESSIONS[0], SESSIONS[1]);
+    let millis = if millis < morning.0 {
+        morning.0
+    } else if millis >= morning.1 && millis < afternoon.0 {
+        morning.1 - 1
+    } else if millis >= afternoon.1 {
+        afternoon.1 - 1
+    } else {
+        millis
+    };
+    let (open, close) = if millis < morning.1 {
+        morning
+    } else {
+        afternoon
+    };
+    let start = open + (millis - open) / interval * interval;
+    let end = (start + interval).min(close);
+    let close_at = if end == close {
+        end + SESSION_GRACE
+    } else {
+        end
+    };
+    Some((
+        timestamp(day, start),
+        timestamp(day, end),
+        timestamp(day, close_at),
+    ))
+}
+
+fn timestamp(day: u32, millis: i64) -> i64 {
+    let millis = millis.max(0).min(MILLIS_PER_DAY - 1);
+    i64::from(day) * 1_000_000_000
+        + millis / 3_600_000 * 10_000_000
+        + millis / 60_000 % 60 * 100_000
+        + millis / 1000 % 60 * 1000
+        + millis % 1000
+}
+
+// The subscribers of a ticker's bars from one source, by interval.
+type TickerKey = (BarSource, Venue, String);
+type Intervals = HashMap<Duration, HashSet<StrategyId>>;
+
+/// Bars built for the strategies, who subscribes to what, and the market
+/// clock closing bars when their ticker goes quiet. Shared by the handles
+/// and the router of an exchange.
+#[derive(Default)]
+pub(crate) struct BarHub {
+    inner: Mutex<HubState>,
+}
+
+#[derive(Default)]
+struct HubState {
+    builders: HashMap<(BarSource, Duration), BarBuilder>,
+    subscribers: HashMap<TickerKey, Intervals>,
+    // Latest exchange time, and when it was seen.
+    clock: Option<(i64, Instant)>,
+}
+
+impl BarHub {
+    /// Returns true when the ticker had no bar subscriber from `source`, so
+    /// its market data needs subscribing to. Bars are at least a millisecond
+    /// long, the resolution of exchange time.
+    pub fn subscribe(
+        &self,
+        strategy: StrategyId,
+        source: BarSource,
+        interval: Duration,
+        venue: Venue,
+        ticker: &str,
+    ) -> Result<bool> {
+        if interval < Duration::from_millis(1) {
+            return Err(Error::Api(format!("Invalid bar interval {:?}", interval)));
+        }
+        let mut state = self.inner.lock().unwrap();
+        let key = (source, venue, ticker.to_string());
+        let first = !state.subscribers.contains_key(&key);
+        state
+            .builders
+            .entry((source, interval))
+            .or_insert_with(|| BarBuilder::new(interval));
+        state
+            .subscribers
+            .entry(key)
+            .or_insert_with(HashMap::new)
+            .entry(interval)
+            .or_insert_with(HashSet::new)
+            .insert(strategy);
+        Ok(first)
+    }
+
+    /// Returns true when that was the last bar subscriber of the ticker from
+    /// `source`, so its market data can be unsubscribed from.
+    pub fn unsubscribe(
+        &self,
+        strategy: StrategyId,
+        source: BarSource,
+        interval: Duration,
+        venue: Venue,
+        ticker: &str,
+    ) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        let key = (source, venue, ticker.to_string());
+        let intervals = match state.subscribers.get_mut(&key) {
+            Some(intervals) => intervals,
+            None => return false,
+        };
+        let removed = match intervals.get_mut(&interval) {
+            Some(strategies) => strategies.remove(&strategy) && strategies.is_empty(),
+            None => false,
+        };
+        if !removed {
+            return false;
+        }
+        intervals.remove(&interval);
+        let last = intervals.is_empty();
+        if last {
+            state.subscribers.remove(&key);
+        }
+        if let Some(builder) = state.builders.get_mut(&(source, interval)) {
+            builder.remove(venue, ticker);
+        }
+        last
+    }
+
+    pub fn on_depth(
+        &self,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        last_price: f64,
+        volume: i64,
+        turnover: f64,
+    ) -> Vec<(Bar, Vec<StrategyId>)> {
+        self.feed(BarSource::Depth, venue, ticker, data_time, |b| {
+            b.on_depth(venue, ticker, data_time, last_price, volume, turnover)
+        })
+    }
+
+    pub fn on_trade(
+        &self,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        price: f64,
+        quantity: i64,
+        amount: f64,
+    ) -> Vec<(Bar, Vec<StrategyId>)> {
+        self.feed(BarSource::TickByTick, venue, ticker, data_time, |b| {
+            b.on_trade(venue, ticker, data_time, price, quantity, amount)
+        })
+    }
+
+    /// Close the bars that ended by `time`.
+    pub fn close_until(&self, time: i64) -> Vec<(Bar, Vec<StrategyId>)> {
+        let mut state = self.inner.lock().unwrap();
+        let closed: Vec<(BarSource, Bar)> = state
+            .builders
+            .iter_mut()
+            .flat_map(|(&(source, _), b)| {
+                b.close_until(time)
+                    .into_iter()
+                    .map(move |bar| (source, bar))
+            })
+            .collect();
+        state.with_subscribers(closed)
+    }
+
+    /// Close the bars that ended by the exchange time estimated from the
+    /// latest event and the time elapsed since, so bars of quiet tickers
+    /// close without waiting for their next event.
+    pub fn on_timer(&self, now: Instant) -> Vec<(Bar, Vec<StrategyId>)> {
+        let clock = self.inner.lock().unwrap().clock;
+        match clock {
+            Some((time, seen)) => {
+                let elapsed = now.saturating_duration_since(seen).as_millis() as i64;
+                self.close_until(timestamp(trading_day(time), millis_of_day(time) + elapsed))
+            }
+            None => vec![],
+        }
+    }
+
+    /// Close every bar, at the end of the data.
+    pub fn flush(&self) -> Vec<(Bar, Vec<StrategyId>)> {
+        let mut state = self.inner.lock().unwrap();
+        let closed: Vec<(BarSource, Bar)> = state
+            .builders
+            .iter_mut()
+            .flat_map(|(&(source, _), b)| b.flush().into_iter().map(move |bar| (source, bar)))
+            .collect();
+        state.with_subscribers(closed)
+    }
+
+    fn feed<F>(
+        &self,
+        source: BarSource,
+        venue: Venue,
+        ticker: &str,
+        data_time: i64,
+        mut add: F,
+    ) -> Vec<(Bar, Vec<StrategyId>)>
+    where
+        F: FnMut(&mut BarBuilder) -> Option<Bar>,
+    {
+        let mut state = self.inner.lock().unwrap();
+        if state.clock.map_or(true, |(time, _)| data_time > time) {
+            state.clock = Some((data_time, Instant::now()));
+        }
+        let subscribed: Vec<Duration> =
+            match state.subscribers.get(&(source, venue, ticker.to_string())) {
+                Some(intervals) => intervals.keys().cloned().collect(),
+                None => return vec![],
+            };
+        let mut closed = vec![];
+        for interval in subscribed {
+            if let Some(builder) = state.builders.get_mut(&(source, interval)) {
+                closed.extend(add(builder).map(|bar| (source, bar)));
+            }
+        }
+        state.with_subscribers(closed)
+    }
+}
+
+impl HubState {
+    fn with_subscribers(&self, bars: Vec<(BarSource, Bar)>) -> Vec<(Bar, Vec<StrategyId>)> {
+        bars.into_iter()
+            .map(|(source, bar)| {
+                let key = (source, bar.venue, bar.ticker.clone());
+                let strategies = self
+                    .subscribers
+                    .get(&key)
+                    .and_then(|intervals| intervals.get(&bar.interval))
+                    .map(|s| s.iter().cloned().collect())
+                    .unwrap_or_default();
+                (bar, strategies)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SH: Venue = Venue::SH;
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    // 2020-01-02 at HH:MM:SS.sss.
+    fn at(hours: i64, minutes: i64, seconds: i64, millis: i64) -> i64 {
+        20200102_000000_000 + hours * 10_000_000 + minutes * 100_000 + seconds * 1000 + millis
+    }
+
+    fn trade(builder: &mut BarBuilder, time: i64, price: f64, quantity: i64) -> Option<Bar> {
+        builder.on_trade(SH, "600036", time, price, quantity, price * quantity as f64)
+    }
+
+    #[test]
+    fn windows_follow_the_sessions() {
+        assert_eq!(window(at(9, 14, 59, 999), MINUTE), None);
+        // Opening auction into the first bar of the morning.
+        assert_eq!(
+            window(at(9, 25, 0, 0), MINUTE),
+            Some((at(9, 30, 0, 0), at(9, 31, 0, 0), at(9, 31, 0, 0)))
+        );
+        assert_eq!(
+            window(at(9, 30, 0, 0), MINUTE),
+            Some((at(9, 30, 0, 0), at(9, 31, 0, 0), at(9, 31, 0, 0)))
+        );
+        // The last bar of the morning waits for late snapshots, and takes
+        // whatever is stamped during the lunch break.
+        let last_morning = Some((at(11, 29, 0, 0), at(11, 30, 0, 0), at(11, 30, 10, 0)));
+        assert_eq!(window(at(11, 29, 59, 999), MINUTE), last_morning);
+        assert_eq!(window(at(11, 30, 0, 0), MINUTE), last_morning);
+        assert_eq!(window(at(12, 59, 59, 999), MINUTE), last_morning);
+        assert_eq!(
+            window(at(13, 0, 0, 0), MINUTE),
+            Some((at(13, 0, 0, 0), at(13, 1, 0, 0), at(13, 1, 0, 0)))
+        );
+        // Closing auction into the last bar of the afternoon.
+        let last_afternoon = Some((at(14, 59, 0, 0), at(15, 0, 0, 0), at(15, 0, 10, 0)));
+        assert_eq!(window(at(14, 59, 30, 0), MINUTE), last_afternoon);
+        assert_eq!(window(at(15, 0, 2, 0), MINUTE), last_afternoon);
+    }
+
+    #[test]
+    fn last_bar_of_a_session_is_cut_short() {
+        let seven = Duration::from_secs(7 * 60);
+        assert_eq!(
+            window(at(11, 25, 0, 0), seven),
+            Some((at(11, 22, 0, 0), at(11, 29, 0, 0), at(11, 29, 0, 0)))
+        );
+        assert_eq!(
+            window(at(11, 29, 30, 0), seven),
+            Some((at(11, 29, 0, 0), at(11, 30, 0, 0), at(11, 30, 10, 0)))
+        );
+        assert_eq!(
+            window(at(13, 0, 0, 0), seven),
+            Some((at(13, 0, 0, 0), at(13, 7, 0, 0), at(13, 7, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn trades_make_ohlcv_bars() {
+        let mut builder = BarBuilder::new(MINUTE);
+        assert_eq!(trade(&mut builder, at(9, 25, 0, 0), 10., 100), None);
+        assert_eq!(trade(&mut builder, at(9, 30, 30, 0), 10.2, 200), None);
+        assert_eq!(trade(&mut builder, at(9, 30, 40, 0), 9.9, 100), None);
+        // Shenzhen cancellations carry no price.
+        assert_eq!(trade(&mut builder, at(9, 30, 50, 0), 0., 100), None);
+
+        let bar = trade(&mut builder, at(9, 31, 0, 0), 10.1, 100).unwrap();
+        assert_eq!((bar.start, bar.end), (at(9, 30, 0, 0), at(9, 31, 0, 0)));
+        assert_eq!(bar.open, Price::from_f64(10.));
+        assert_eq!(bar.high, Price::from_f64(10.2));
+        assert_eq!(bar.low, Price::from_f64(9.9));
+        assert_eq!(bar.close, Price::from_f64(9.9));
+        assert_eq!(bar.volume, 400);
+
+        let bar = builder.flush().pop().unwrap();
+        assert_eq!(bar.start, at(9, 31, 0, 0));
+        assert_eq!(bar.volume, 100);
+    }
+
+    #[test]
+    fn morning_closes_after_the_grace_period() {
+        let mut builder = BarBuilder::new(MINUTE);
+        trade(&mut builder, at(11, 29, 50, 0), 10., 100);
+        trade(&mut builder, at(11, 30, 3, 0), 10.1, 50);
+        assert!(builder.close_until(at(11, 30, 5, 0)).is_empty());
+
+        let closed = builder.close_until(at(11, 30, 10, 0));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].end, at(11, 30, 0, 0));
+        assert_eq!(closed[0].volume, 150);
+
+        // Too late for the closed bar, and not carried into the afternoon.
+        assert_eq!(trade(&mut builder, at(11, 30, 12, 0), 10.2, 70), None);
+        assert_eq!(trade(&mut builder, at(13, 0, 0, 0), 10.3, 30), None);
+        let bar = builder.flush().pop().unwrap();
+        assert_eq!(bar.start, at(13, 0, 0, 0));
+        assert_eq!(bar.open, Price::from_f64(10.3));
+        assert_eq!(bar.volume, 30);
+    }
+
+    #[test]
+    fn closing_auction_joins_the_last_bar() {
+        let mut builder = BarBuilder::new(MINUTE);
+        trade(&mut builder, at(14, 59, 59, 0), 10., 100);
+        trade(&mut builder, at(15, 0, 2, 0), 10.05, 900);
+        assert!(builder.close_until(at(15, 0, 9, 999)).is_empty());
+
+        let closed = builder.close_until(at(15, 0, 10, 0));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(
+            (closed[0].start, closed[0].end),
+            (at(14, 59, 0, 0), at(15, 0, 0, 0))
+        );
+        assert_eq!(closed[0].close, Price::from_f64(10.05));
+        assert_eq!(closed[0].volume, 1000);
+    }
+
+    #[test]
+    fn depth_counts_volume_deltas() {
+        let mut builder = BarBuilder::new(MINUTE);
+        let mut depth = |time: i64, price: f64, volume: i64| {
+            builder.on_depth(SH, "600036", time, price, volume, price * volume as f64)
+        };
+        // What traded in the opening auction goes into the first bar.
+        assert_eq!(depth(at(9, 25, 3, 0), 10., 1000), None);
+        assert_eq!(depth(at(9, 30, 3, 0), 10.1, 1500), None);
+        assert_eq!(depth(at(9, 31, 0, 0), 10.1, 1500), None);
+
+        let bar = depth(at(9, 31, 3, 0), 10.2, 1600).unwrap();
+        assert_eq!(bar.start, at(9, 30, 0, 0));
+        assert_eq!(bar.volume, 1500);
+        assert_eq!(bar.close, Price::from_f64(10.1));
+    }
+
+    #[test]
+    fn hub_routes_bars_to_their_subscribers() {
+        let hub = BarHub::default();
+        let five = Duration::from_secs(300);
+        assert!(hub
+            .subscribe(1, BarSource::TickByTick, MINUTE, SH, "600036")
+            .unwrap());
+        assert!(!hub
+            .subscribe(2, BarSource::TickByTick, five, SH, "600036")
+            .unwrap());
+        assert!(hub
+            .subscribe(1, BarSource::Depth, MINUTE, SH, "600036")
+            .unwrap());
+
+        assert!(hub
+            .on_trade(SH, "600036", at(9, 30, 0, 0), 10., 100, 1000.)
+            .is_empty());
+        assert!(hub
+            .on_trade(SH, "601398", at(9, 30, 0, 0), 5., 100, 500.)
+            .is_empty());
+        let closed = hub.on_trade(SH, "600036", at(9, 31, 0, 0), 10., 100, 1000.);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].0.interval, MINUTE);
+        assert_eq!(closed[0].1, vec![1]);
+
+        assert!(!hub.unsubscribe(1, BarSource::TickByTick, MINUTE, SH, "600036"));
+        assert!(hub.unsubscribe(2, BarSource::TickByTick, five, SH, "600036"));
+        assert!(hub
+            .on_trade(SH, "600036", at(9, 40, 0, 0), 10., 100, 1000.)
+            .is_empty());
+    }
+
+    #[test]
+    fn intervals_below_a_millisecond_are_rejected() {
+        let hub = BarHub::default();
+        for &interval in &[Duration::from_secs(0), Duration::from_micros(999)] {
+            match hub.subscribe(1, BarSource::Depth, interval, SH, "600036") {
+                Err(Error::Api(_)) => {}
+                other => panic!("{:?} accepted: {:?}", interval, other),
+            }
+        }
+        assert!(hub
+            .subscribe(1, BarSource::Depth, Duration::from_millis(1), SH, "600036")
+            .unwrap());
+    }
+}
@@ -0,0 +1,439 @@
+mod registry;
+
+pub use self::registry::StrategyRegistry;
+use crate::exchanges::xtp::{
+    seconds, KillSwitchConfig, QueueConfig, ReconnectPolicy, XTPConfig, XTPExchange,
+    DEFAULT_REQUEST_TIMEOUT,
+};
+use crate::risk::RiskLimits;
+use crate::{Error, Exchange, Result};
+use futures::future::try_join_all;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Exchange connections and the strategies running on them, as read from a
+/// TOML or YAML file, e.g.
+///
+/// ```toml
+/// [[exchanges]]
+/// name = "main"
+/// risk = { max_order_quantity = 10000, max_loss = 50000.0 }
+/// reconnect = { initial_backoff = 0.5, max_backoff = 30, max_attempts = 20 }
+/// request_timeout = 5
+///
+/// [exchanges.xtp.quote]
+/// addr = "10.0.0.1:6002"
+/// username = "${XTP_USERNAME}"
+/// password = "${XTP_PASSWORD}"
+///
+/// [exchanges.xtp.trader]
+/// addr = "10.0.0.2:6001"
+/// username = "${XTP_USERNAME}"
+/// password = "${XTP_PASSWORD}"
+/// key = "${XTP_KEY}"
+///
+/// [exchanges.queues]
+/// quote = { capacity = 8192, policy = "conflate_latest" }
+/// trader = { capacity = 4096, policy = "block" }
+///
+/// [[strategies]]
+/// name = "grid-600036"
+/// kind = "grid"
+/// params = { ticker = "600036", step = 0.05 }
+/// ```
+///
+/// Any string written as `${NAME}`, a credential, an address or a strategy
+/// param alike, is replaced by the environment variable `NAME` when the file
+/// is loaded.
+///
+/// Exchanges logged in with the same account need distinct `client_id`s,
+/// `build` rejects the deployment otherwise.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Deployment {
+    pub exchanges: Vec<ExchangeSpec>,
+    #[serde(default)]
+    pub strategies: Vec<StrategySpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExchangeSpec {
+    /// What strategies refer to the exchange by.
+    pub name: String,
+    pub xtp: XTPConfig,
+    #[serde(default)]
+    pub risk: RiskLimits,
+    #[serde(default)]
+    pub kill_switch: KillSwitchConfig,
+    #[serde(default)]
+    pub queues: QueueConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectPolicy,
+    /// Seconds, see `XTPExchange::set_request_timeout`.
+    #[serde(default = "default_request_timeout", deserialize_with = "seconds")]
+    pub request_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StrategySpec {
+    /// Only used in error messages.
+    pub name: String,
+    /// What the factory is registered under in the `StrategyRegistry`.
+    pub kind: String,
+    /// May be left out when there is a single exchange.
+    pub exchange: Option<String>,
+    #[serde(default = "no_params")]
+    pub params: Value,
+}
+
+fn no_params() -> Value {
+    Value::Object(Map::new())
+}
+
+fn default_request_timeout() -> Duration {
+    DEFAULT_REQUEST_TIMEOUT
+}
+
+impl Deployment {
+    /// The format is told by the extension, `.toml`, `.yaml` or `.yml`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let invalid = |msg: String| Error::Config(format!("{}: {}", path.display(), msg));
+        let text = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+
+        // Read as a plain tree first, so that the variables are resolved
+        // wherever they are.
+        let mut value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&text).map_err(|e| invalid(e.to_string()))?
+            }
+            _ => return Err(invalid("Unknown format".to_string())),
+        };
+        resolve_env(&mut value)?;
+        serde_json::from_value(value).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The exchanges in the order of `exchanges`, with their strategies
+    /// registered.
+    pub fn build(&self, registry: &StrategyRegistry<XTPExchange>) -> Result<Vec<XTPExchange>> {
+        self.check_client_ids()?;
+        let mut exchanges: Vec<XTPExchange> = self
+            .exchanges
+            .iter()
+            .map(|spec| {
+                let mut exchange = XTPExchange::with_config(spec.xtp.clone());
+                exchange.set_risk_limits(spec.risk.clone());
+                exchange.set_kill_switch_config(spec.kill_switch.clone());
+                exchange.set_queue_config(spec.queues.clone());
+                exchange.set_reconnect_policy(spec.reconnect.clone());
+                exchange.set_request_timeout(spec.request_timeout);
+                exchange
+            })
+            .collect();
+
+        for strategy in &self.strategies {
+            let index = self.exchange_of(strategy)?;
+            registry.instantiate(&mut exchanges[index], strategy)?;
+        }
+        Ok(exchanges)
+    }
+
+    // XTP logs out the older of two sessions of an account with the same
+    // client id, so exchanges sharing an account must set distinct ids.
+    fn check_client_ids(&self) -> Result<()> {
+        let mut quote = HashSet::new();
+        let mut trader = HashSet::new();
+        for exchange in &self.exchanges {
+            let xtp = &exchange.xtp;
+            let sessions = vec![
+                (
+                    &mut quote,
+                    "quote",
+                    &xtp.quote.username,
+                    xtp.quote.client_id,
+                ),
+                (
+                    &mut trader,
+                    "trader",
+                    &xtp.trader.username,
+                    xtp.trader.client_id,
+                ),
+            ];
+            for (seen, session, username, client_id) in sessions {
+                if !seen.insert((username.clone(), client_id)) {
+                    return Err(Error::Config(format!(
+                        "Exchange {} reuses the {} client id {} of account {}",
+                        exchange.name, session, client_id, username
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn exchange_of(&self, strategy: &StrategySpec) -> Result<usize> {
+        match &strategy.exchange {
+            Some(name) => self
+                .exchanges
+                .iter()
+                .position(|exchange| &exchange.name == name)
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "Strategy {} runs on unknown exchange {}",
+                        strategy.name, name
+                    ))
+                }),
+            None if self.exchanges.len() == 1 => Ok(0),
+            None => Err(Error::Config(format!(
+                "Strategy {} must name its exchange",
+                strategy.name
+            ))),
+        }
+    }
+
+    /// Build the deployment and run every exchange until one of them fails.
+    pub async fn run(&self, registry: &StrategyRegistry<XTPExchange>) -> Result<()> {
+        let exchanges = self.build(registry)?;
+        try_join_all(exchanges.into_iter().map(|exchange| exchange.run())).await?;
+        Ok(())
+    }
+}
+
+// Replace every string `${NAME}` in `value` with the environment variable
+// `NAME`.
+fn resolve_env(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(s) if s.starts_with("${") && s.ends_with('}') => {
+            let name = &s[2..s.len() - 1];
+            if name.is_empty() {
+                return Err(Error::Config(format!(
+                    "{} names no environment variable",
+                    s
+                )));
+            }
+            *s = env::var(name)
+                .map_err(|_| Error::Config(format!("Environment variable {} is not set", name)))?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                resolve_env(item)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                resolve_env(item)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::xtp::BackpressurePolicy;
+
+    fn deployment(trader_client_ids: &[u8]) -> Deployment {
+        let mut text = String::new();
+        for (i, client_id) in trader_client_ids.iter().enumerate() {
+            text += &format!(
+                r#"
+                [[exchanges]]
+                name = "exchange-{}"
+
+                [exchanges.xtp.quote]
+                addr = "10.0.0.1:6002"
+                username = "alice"
+                password = "secret"
+                client_id = {}
+
+                [exchanges.xtp.trader]
+                addr = "10.0.0.2:6001"
+                username = "alice"
+                password = "secret"
+                key = "key"
+                client_id = {}
+                "#,
+                i,
+                i + 1,
+                client_id
+            );
+        }
+        toml::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn distinct_client_ids_pass() {
+        assert!(deployment(&[1]).check_client_ids().is_ok());
+        assert!(deployment(&[1, 2]).check_client_ids().is_ok());
+    }
+
+    #[test]
+    fn shared_client_id_of_an_account_is_rejected() {
+        match deployment(&[1, 1]).check_client_ids() {
+            Err(Error::Config(msg)) => assert!(msg.contains("exchange-1")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    fn write(name: &str, text: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("pixiu-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_toml() {
+        env::set_var("PIXIU_TEST_TOML_PASSWORD", "from-env");
+        let path = write(
+            "deploy.toml",
+            r#"
+            [[exchanges]]
+            name = "main"
+            request_timeout = 2.5
+            reconnect = { initial_backoff = 0.5, max_attempts = 3 }
+
+            [exchanges.queues]
+            quote = { capacity = 16, policy = "conflate_latest" }
+
+            [exchanges.xtp.quote]
+            addr = "10.0.0.1:6002"
+            username = "alice"
+            password = "${PIXIU_TEST_TOML_PASSWORD}"
+
+            [exchanges.xtp.trader]
+            addr = "10.0.0.2:6001"
+            username = "alice"
+            password = "${PIXIU_TEST_TOML_PASSWORD}"
+            key = "key"
+
+            [[strategies]]
+            name = "grid"
+            kind = "grid"
+            params = { ticker = "600036" }
+            "#,
+        );
+        let deployment = Deployment::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let exchange = &deployment.exchanges[0];
+        assert_eq!(exchange.xtp.quote.password, "from-env");
+        assert_eq!(exchange.xtp.trader.password, "from-env");
+        assert_eq!(exchange.request_timeout, Duration::from_millis(2500));
+        assert_eq!(
+            exchange.reconnect.initial_backoff,
+            Duration::from_millis(500)
+        );
+        assert_eq!(exchange.reconnect.max_backoff, Duration::from_secs(60));
+        assert_eq!(exchange.reconnect.max_attempts, Some(3));
+        assert_eq!(exchange.queues.quote.capacity, 16);
+        assert_eq!(
+            exchange.queues.quote.policy,
+            BackpressurePolicy::ConflateLatest
+        );
+        assert_eq!(exchange.queues.trader.policy, BackpressurePolicy::Block);
+        assert_eq!(deployment.strategies[0].params["ticker"], "600036");
+    }
+
+    #[test]
+    fn reads_yaml() {
+        env::set_var("PIXIU_TEST_YAML_TICKER", "000001");
+        let path = write(
+            "deploy.yaml",
+            r#"
+exchanges:
+  - name: main
+    xtp:
+      quote: { addr: "10.0.0.1:6002", username: alice, password: secret }
+      trader: { addr: "10.0.0.2:6001", username: alice, password: secret, key: key }
+strategies:
+  - name: grid
+    kind: grid
+    params: { tickers: ["${PIXIU_TEST_YAML_TICKER}"] }
+"#,
+        );
+        let deployment = Deployment::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let exchange = &deployment.exchanges[0];
+        assert_eq!(exchange.xtp.trader.key, "key");
+        assert_eq!(exchange.request_timeout, DEFAULT_REQUEST_TIMEOUT);
+        assert_eq!(exchange.queues.strategy_capacity, 1024);
+        assert_eq!(deployment.strategies[0].params["tickers"][0], "000001");
+    }
+
+    #[test]
+    fn unknown_extension_is_rejected() {
+        let path = write("deploy.json", "{}");
+        let result = Deployment::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::Config(msg)) => assert!(msg.contains("Unknown format")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolves_variables_anywhere() {
+        env::set_var("PIXIU_TEST_ADDR", "10.0.0.3:6002");
+        let mut value = serde_json::json!({
+            "addr": "${PIXIU_TEST_ADDR}",
+            "nested": [{ "addr": "${PIXIU_TEST_ADDR}" }],
+            "literal": "$PIXIU_TEST_ADDR",
+            "port": 6002,
+        });
+        resolve_env(&mut value).unwrap();
+        assert_eq!(value["addr"], "10.0.0.3:6002");
+        assert_eq!(value["nested"][0]["addr"], "10.0.0.3:6002");
+        assert_eq!(value["literal"], "$PIXIU_TEST_ADDR");
+        assert_eq!(value["port"], 6002);
+    }
+
+    #[test]
+    fn unresolvable_variables_are_rejected() {
+        env::remove_var("PIXIU_TEST_UNSET");
+        for text in &["${PIXIU_TEST_UNSET}", "${}"] {
+            let mut value = Value::String(text.to_string());
+            match resolve_env(&mut value) {
+                Err(Error::Config(_)) => {}
+                other => panic!("{} resolved to {:?}", text, other),
+            }
+        }
+    }
+
+    fn strategy(exchange: Option<&str>) -> StrategySpec {
+        StrategySpec {
+            name: "grid".to_string(),
+            kind: "grid".to_string(),
+            exchange: exchange.map(str::to_string),
+            params: no_params(),
+        }
+    }
+
+    #[test]
+    fn strategies_find_their_exchange() {
+        let single = deployment(&[1]);
+        assert_eq!(single.exchange_of(&strategy(None)).unwrap(), 0);
+        assert_eq!(
+            single.exchange_of(&strategy(Some("exchange-0"))).unwrap(),
+            0
+        );
+
+        let pair = deployment(&[1, 2]);
+        assert_eq!(pair.exchange_of(&strategy(Some("exchange-1"))).unwrap(), 1);
+        match pair.exchange_of(&strategy(None)) {
+            Err(Error::Config(msg)) => assert!(msg.contains("must name")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+        match pair.exchange_of(&strategy(Some("backup"))) {
+            Err(Error::Config(msg)) => assert!(msg.contains("backup")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::StrategySpec;
+use crate::{Error, Exchange, Result, Strategy};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+type Factory<E> = Box<dyn Fn(&mut E, Value) -> Result<()> + Send + Sync>;
+
+/// Strategy constructors by the `kind` a deployment refers to them with.
+pub struct StrategyRegistry<E> {
+    factories: HashMap<String, Factory<E>>,
+}
+
+impl<E: Exchange> StrategyRegistry<E> {
+    pub fn new() -> Self {
+        StrategyRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Make `kind` build strategies with `factory`, which gets the `params`
+    /// of the strategy deserialized as `P`. Replaces an earlier factory of
+    /// the same kind.
+    pub fn register<P, S, F>(&mut self, kind: &str, factory: F)
+    where
+        P: DeserializeOwned,
+        S: Strategy<E> + Send + Sync + 'static,
+        F: Fn(P) -> S + Send + Sync + 'static,
+    {
+        let build = move |exchange: &mut E, params: Value| {
+            let params = serde_json::from_value(params)
+                .map_err(|e| Error::Config(format!("Invalid params: {}", e)))?;
+            exchange.register(factory(params));
+            Ok(())
+        };
+        self.factories.insert(kind.to_string(), Box::new(build));
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Build the strategy described by `spec` and register it on `exchange`.
+    pub(crate) fn instantiate(&self, exchange: &mut E, spec: &StrategySpec) -> Result<()> {
+        let factory = self.factories.get(&spec.kind).ok_or_else(|| {
+            Error::Config(format!(
+                "Strategy {} has unknown kind {}",
+                spec.name, spec.kind
+            ))
+        })?;
+        factory(exchange, spec.params.clone()).map_err(|e| match e {
+            Error::Config(msg) => Error::Config(format!("Strategy {}: {}", spec.name, msg)),
+            e => e,
+        })
+    }
+}
+
+impl<E: Exchange> Default for StrategyRegistry<E> {
+    fn default() -> Self {
+        StrategyRegistry::new()
+    }
+}
@@ -0,0 +1,30 @@
+use crate::risk::RiskRejection;
+use failure::Fail;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Authentication failed: {}", _0)]
+    Auth(String),
+    #[fail(display = "Network failure: {}", _0)]
+    Network(String),
+    #[fail(display = "Invalid software key: {}", _0)]
+    InvalidKey(String),
+    #[fail(display = "API init failed: {}", _0)]
+    ApiInit(String),
+    #[fail(display = "Exchange is not connected")]
+    NotConnected,
+    #[fail(display = "API call failed: {}", _0)]
+    Api(String),
+    #[fail(display = "XTP error {}: {}", error_id, error_msg)]
+    Rsp { error_id: i32, error_msg: String },
+    #[fail(display = "Request {} timed out", _0)]
+    Timeout(i32),
+    #[fail(display = "Order rejected by risk check: {}", _0)]
+    Risk(RiskRejection),
+    #[fail(display = "Kill switch engaged: {}", _0)]
+    KillSwitch(String),
+    #[fail(display = "Invalid configuration: {}", _0)]
+    Config(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
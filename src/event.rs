@@ -0,0 +1,43 @@
+use crate::exchanges::xtp::{ClientOrderId, ConnectionState, OrderSnapshot, QuoteEvent};
+use crate::market::Venue;
+use crate::portfolio::{Position, Side};
+use tokio::time::Instant;
+
+/// A fill of an order.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    /// `None` for orders not placed through pixiu.
+    pub client_id: Option<ClientOrderId>,
+    pub exec_id: String,
+    pub ticker: String,
+    pub venue: Venue,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: i64,
+    /// Commission and taxes charged for this fill, when the exchange says.
+    pub fee: Option<f64>,
+    pub trade_time: i64,
+}
+
+/// Everything a strategy receives, whichever exchange it runs on, in the
+/// order the exchange delivered it.
+#[derive(Debug, Clone)]
+pub enum Event {
+    MarketData(QuoteEvent),
+    /// The order after it changed state or got filled.
+    Order(OrderSnapshot),
+    Fill(Fill),
+    /// The position of the account after a fill changed it, sent to every
+    /// strategy. Cash is not reported, see `query_asset`.
+    Position(Position),
+    /// The exchange refused to cancel an order, usually because it was done
+    /// already.
+    CancelRejected {
+        /// `None` for orders not placed through pixiu.
+        client_id: Option<ClientOrderId>,
+        error_id: i32,
+        error_msg: String,
+    },
+    Connection(ConnectionState),
+    Timer(Instant),
+}
@@ -1 +1,3 @@
+pub mod replay;
+pub mod sim;
 pub mod xtp;
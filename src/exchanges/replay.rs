@@ -0,0 +1,229 @@
+use super::sim::{FeeSchedule, FillModel, OnSim, Pace, SimExchange, SimExchangeHandle};
+use super::xtp::QuoteEvent;
+use crate::instruments::InstrumentMaster;
+use crate::recorder::{recordings, RecordReader};
+use crate::risk::RiskLimits;
+use crate::{Event, Exchange, Result, Strategy};
+use async_trait::async_trait;
+use log::error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Sends recorded market data to the strategies, oldest first, with orders
+/// filled by the `SimExchange` matching engine. Strategies get the same
+/// `Event`s as on the other exchanges, and a handle implementing the same
+/// `ExchangeHandle`, so a strategy implemented for every exchange runs
+/// unchanged on the recordings and live:
+///
+/// ```ignore
+/// impl<E: Exchange<Event = Event>> Strategy<E> for MyStrategy
+/// ```
+pub struct ReplayExchange {
+    sim: SimExchange,
+}
+
+impl ReplayExchange {
+    /// Replay every recording in `dir`, or only the trading days (YYYYMMDD)
+    /// within `from` and `to` inclusive.
+    pub fn open<P: AsRef<Path>>(dir: P, from: Option<u32>, to: Option<u32>) -> io::Result<Self> {
+        Ok(ReplayExchange::from_files(recording_files(dir, from, to)?))
+    }
+
+    /// Replay the recordings at `paths`, in that order.
+    pub fn from_files(paths: Vec<PathBuf>) -> Self {
+        ReplayExchange {
+            sim: SimExchange::from_events(recorded_events(paths)),
+        }
+    }
+
+    /// `Pace::Original` reproduces the timing of the recording,
+    /// `Pace::AsFastAsPossible` (the default) its order only, and does not
+    /// wait for strategies that fall behind.
+    pub fn set_pace(&mut self, pace: Pace) {
+        self.sim.set_pace(pace);
+    }
+
+    pub fn set_timer_interval(&mut self, interval: Duration) {
+        self.sim.set_timer_interval(interval);
+    }
+
+    pub fn set_strategy_capacity(&mut self, capacity: usize) {
+        self.sim.set_strategy_capacity(capacity);
+    }
+
+    pub fn set_risk_limits(&mut self, limits: RiskLimits) {
+        self.sim.set_risk_limits(limits);
+    }
+
+    pub fn set_instruments(&mut self, instruments: Arc<InstrumentMaster>) {
+        self.sim.set_instruments(instruments);
+    }
+
+    pub fn set_fill_model(&mut self, fill_model: FillModel) {
+        self.sim.set_fill_model(fill_model);
+    }
+
+    pub fn set_fees(&mut self, fees: FeeSchedule) {
+        self.sim.set_fees(fees);
+    }
+
+    pub fn set_initial_capital(&mut self, capital: f64) {
+        self.sim.set_initial_capital(capital);
+    }
+}
+
+/// The recordings in `dir` of the trading days within `from` and `to`.
+pub(crate) fn recording_files<P: AsRef<Path>>(
+    dir: P,
+    from: Option<u32>,
+    to: Option<u32>,
+) -> io::Result<Vec<PathBuf>> {
+    Ok(recordings(dir)?
+        .into_iter()
+        .filter(|&(day, _)| from.map_or(true, |from| day >= from))
+        .filter(|&(day, _)| to.map_or(true, |to| day <= to))
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// The market data of the recordings at `paths`, read as it is consumed.
+/// Files and records that cannot be read are logged and skipped.
+pub(crate) fn recorded_events(paths: Vec<PathBuf>) -> impl Iterator<Item = QuoteEvent> + Send {
+    paths
+        .into_iter()
+        .filter_map(|path| match RecordReader::open(&path) {
+            Ok(reader) => Some(reader),
+            Err(e) => {
+                error!("Skipping {}: {}", path.display(), e);
+                None
+            }
+        })
+        .flatten()
+        .filter_map(|record| match record {
+            Ok(record) => Some(record.data.into()),
+            Err(e) => {
+                error!("Skipping record: {}", e);
+                None
+            }
+        })
+}
+
+#[async_trait]
+impl Exchange for ReplayExchange {
+    type Event = Event;
+    type Handle = SimExchangeHandle;
+
+    async fn connect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Replay everything, then close the strategies' channels and return once
+    /// they are done.
+    async fn run(self) -> Result<()> {
+        self.sim.run().await
+    }
+
+    fn register<S>(&mut self, s: S)
+    where
+        S: Strategy<ReplayExchange> + Send + Sync + 'static,
+    {
+        self.sim.register(OnSim::<ReplayExchange>(Box::new(s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::{Quote, Venue};
+    use crate::recorder::tests::full_quote;
+    use crate::recorder::{record_of, RecordWriter};
+    use crate::ExchangeHandle;
+    use std::env;
+    use std::fs;
+    use std::sync::Mutex;
+    use tokio::sync::broadcast::{Receiver, RecvError};
+
+    fn record(dir: &Path, quotes: &[Quote]) {
+        let mut writer = RecordWriter::new(dir).unwrap();
+        for quote in quotes {
+            let record = record_of(&QuoteEvent::Quote(quote.clone()), 1).unwrap();
+            writer.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_the_recorded_days_in_full() {
+        let dir = env::temp_dir().join(format!("pixiu-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let quotes = vec![
+            full_quote("600036", 20200102093000000),
+            full_quote("600036", 20200102093003000),
+            full_quote("600036", 20200103093000000),
+        ];
+        record(&dir, &quotes);
+
+        let replayed = |from, to| -> Vec<Quote> {
+            recorded_events(recording_files(&dir, from, to).unwrap())
+                .map(|event| match event {
+                    QuoteEvent::Quote(quote) => quote,
+                    other => panic!("{:?}", other),
+                })
+                .collect()
+        };
+        assert_eq!(replayed(None, None), quotes);
+        assert_eq!(replayed(Some(20200103), None), quotes[2..].to_vec());
+        assert_eq!(replayed(None, Some(20200102)), quotes[..2].to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Data times of the quotes it got, and how many events it lost.
+    #[derive(Default)]
+    struct Received {
+        data_times: Vec<i64>,
+        lost: u64,
+    }
+
+    struct Collect(Arc<Mutex<Received>>);
+
+    #[async_trait]
+    impl Strategy<ReplayExchange> for Collect {
+        async fn run(self: Box<Self>, mut rx: Receiver<Event>, h: SimExchangeHandle) {
+            h.subscribe_market_data(&["600036"], Venue::SH).unwrap();
+            loop {
+                match rx.recv().await {
+                    Ok(Event::MarketData(QuoteEvent::Quote(quote))) => {
+                        self.0.lock().unwrap().data_times.push(quote.data_time)
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(n)) => self.0.lock().unwrap().lost += n,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn strategies_get_every_event() {
+        let dir = env::temp_dir().join(format!("pixiu-replay-run-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let quotes: Vec<Quote> = (0..50)
+            .map(|i| full_quote("600036", 20200102093000000 + i * 1000))
+            .collect();
+        record(&dir, &quotes);
+
+        let received = Arc::new(Mutex::new(Received::default()));
+        let mut replay = ReplayExchange::open(&dir, None, None).unwrap();
+        // Far fewer than the quotes, and the timer ticks between them.
+        replay.set_strategy_capacity(4);
+        replay.register(Collect(received.clone()));
+        replay.run().await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.lost, 0);
+        let data_times: Vec<i64> = quotes.iter().map(|q| q.data_time).collect();
+        assert_eq!(received.data_times, data_times);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
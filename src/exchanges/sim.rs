@@ -0,0 +1,517 @@
+mod feed;
+mod matching;
+mod pace;
+mod router;
+
+pub use self::feed::RandomWalk;
+pub(crate) use self::feed::{millis_of_day, session_millis};
+pub(crate) use self::matching::MatchingEngine;
+pub use self::matching::{FeeSchedule, FillModel};
+pub use self::pace::Pace;
+use self::pace::{Pacer, SimClock};
+use self::router::SimRouter;
+use super::xtp::{
+    ClientOrderId, ConnectionState, OrderRequest, OrderSnapshot, QuoteEvent, Session, StrategyId,
+    SubscriptionKind,
+};
+use crate::bars::{BarHub, BarSource};
+use crate::instruments::InstrumentMaster;
+use crate::market::{InstrumentInfo, Quote, Venue};
+use crate::portfolio::{Portfolio, Position};
+use crate::risk::{RiskEngine, RiskLimits};
+use crate::{Error, Event, Exchange, ExchangeHandle, Result, Strategy};
+use async_trait::async_trait;
+use log::error;
+use std::future::Future;
+use std::iter;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::oneshot;
+
+/// An exchange living in memory: quotes come from an iterator instead of the
+/// XTP gateway, and a matching engine fills orders against them. Strategies
+/// get the same `Event`s as with `XTPExchange`, and a handle implementing the
+/// same `ExchangeHandle`, so they can be tested end to end without a gateway.
+pub struct SimExchange {
+    // Market data events only.
+    feed: Box<dyn Iterator<Item = QuoteEvent> + Send>,
+    strategies: Vec<Box<dyn Strategy<SimExchange> + Send + Sync>>,
+    engine: Arc<Mutex<MatchingEngine>>,
+    router: Arc<SimRouter>,
+    risk: Arc<RiskEngine>,
+    instruments: Arc<InstrumentMaster>,
+    bars: Arc<BarHub>,
+    pace: Pace,
+    timer_interval: Duration,
+    strategy_capacity: usize,
+    // Sees the engine after each quote was matched.
+    observer: Option<Box<dyn FnMut(&MatchingEngine) + Send>>,
+}
+
+#[derive(Clone)]
+pub struct SimExchangeHandle {
+    strategy_id: StrategyId,
+    engine: Arc<Mutex<MatchingEngine>>,
+    router: Arc<SimRouter>,
+    risk: Arc<RiskEngine>,
+    instruments: Arc<InstrumentMaster>,
+    bars: Arc<BarHub>,
+}
+
+impl SimExchangeHandle {
+    fn subscribe(&self, kind: SubscriptionKind, tickers: &[&str], venue: Venue) -> Result<()> {
+        for ticker in tickers {
+            self.router
+                .subscribe(self.strategy_id, kind, venue, Some(ticker));
+        }
+        Ok(())
+    }
+
+    fn unsubscribe(&self, kind: SubscriptionKind, tickers: &[&str], venue: Venue) -> Result<()> {
+        for ticker in tickers {
+            self.router
+                .unsubscribe(self.strategy_id, kind, venue, Some(ticker));
+        }
+        Ok(())
+    }
+
+    /// Exchange time of the latest quote, YYYYMMDDHHMMSSsss.
+    pub fn now(&self) -> i64 {
+        self.engine.lock().unwrap().time()
+    }
+}
+
+impl ExchangeHandle for SimExchangeHandle {
+    fn subscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::MarketData, tickers, venue)
+    }
+
+    fn unsubscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::MarketData, tickers, venue)
+    }
+
+    fn subscribe_all_market_data(&self, venue: Venue) -> Result<()> {
+        self.router
+            .subscribe(self.strategy_id, SubscriptionKind::MarketData, venue, None);
+        Ok(())
+    }
+
+    fn unsubscribe_all_market_data(&self, venue: Venue) -> Result<()> {
+        self.router
+            .unsubscribe(self.strategy_id, SubscriptionKind::MarketData, venue, None);
+        Ok(())
+    }
+
+    fn subscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::TickByTick, tickers, venue)
+    }
+
+    fn unsubscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::TickByTick, tickers, venue)
+    }
+
+    fn subscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::OrderBook, tickers, venue)
+    }
+
+    fn unsubscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::OrderBook, tickers, venue)
+    }
+
+    fn subscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()> {
+        for ticker in tickers {
+            self.bars
+                .subscribe(self.strategy_id, source, interval, venue, ticker)?;
+        }
+        Ok(())
+    }
+
+    fn unsubscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()> {
+        for ticker in tickers {
+            self.bars
+                .unsubscribe(self.strategy_id, source, interval, venue, ticker);
+        }
+        Ok(())
+    }
+
+    /// The most recent quote of a ticker, subscribed or not.
+    fn last_snapshot(&self, ticker: &str, venue: Venue) -> Option<Quote> {
+        self.engine.lock().unwrap().quote(ticker, venue).cloned()
+    }
+
+    fn instrument(&self, ticker: &str, venue: Venue) -> Option<InstrumentInfo> {
+        self.instruments.get(venue, ticker)
+    }
+
+    /// Submit an order after it passed the risk checks. It is matched against
+    /// the latest quote before this returns.
+    fn insert_order(&self, req: &OrderRequest) -> Result<ClientOrderId> {
+        let mut engine = self.engine.lock().unwrap();
+        let quote = engine.quote(&req.ticker, req.venue);
+        let instrument = self.instrument(&req.ticker, req.venue);
+        let check = req.order_check(
+            engine.portfolio().position(req.venue, &req.ticker),
+            &engine.open_orders(),
+            quote.map(|q| q.last_price.to_f64()),
+            quote
+                .filter(|q| !q.upper_limit_price.is_zero())
+                .map(|q| (q.lower_limit_price.to_f64(), q.upper_limit_price.to_f64())),
+            instrument.as_ref(),
+        );
+        self.risk.check(&check).map_err(Error::Risk)?;
+
+        let (client_id, reports) = engine.insert(self.strategy_id, req)?;
+        self.risk.order_sent();
+        // Delivered under the lock, so reports never overtake each other.
+        self.router.deliver(reports);
+        Ok(client_id)
+    }
+
+    fn cancel_order(&self, client_id: ClientOrderId) -> Result<()> {
+        let mut engine = self.engine.lock().unwrap();
+        let reports = engine.cancel(client_id)?;
+        self.router.deliver(reports);
+        Ok(())
+    }
+
+    fn order(&self, client_id: ClientOrderId) -> Option<OrderSnapshot> {
+        self.engine.lock().unwrap().order(client_id)
+    }
+
+    fn orders(&self) -> Vec<OrderSnapshot> {
+        self.engine.lock().unwrap().orders()
+    }
+
+    fn open_orders(&self) -> Vec<OrderSnapshot> {
+        self.engine.lock().unwrap().open_orders()
+    }
+
+    fn portfolio(&self) -> Portfolio {
+        self.engine.lock().unwrap().portfolio().clone()
+    }
+
+    fn position(&self, ticker: &str, venue: Venue) -> Option<Position> {
+        self.engine
+            .lock()
+            .unwrap()
+            .portfolio()
+            .position(venue, ticker)
+            .cloned()
+    }
+}
+
+impl SimExchange {
+    /// An exchange replaying `quotes`, scripted or generated by e.g.
+    /// `RandomWalk`, in order.
+    pub fn new<I>(quotes: I) -> SimExchange
+    where
+        I: IntoIterator<Item = Quote>,
+        I::IntoIter: Send + 'static,
+    {
+        SimExchange::from_events(quotes.into_iter().map(QuoteEvent::Quote))
+    }
+
+    /// An exchange sending the market data events of `feed` in order.
+    pub(crate) fn from_events<I>(feed: I) -> SimExchange
+    where
+        I: Iterator<Item = QuoteEvent> + Send + 'static,
+    {
+        SimExchange {
+            feed: Box::new(feed),
+            strategies: vec![],
+            engine: Arc::new(Mutex::new(MatchingEngine::default())),
+            router: Arc::new(SimRouter::default()),
+            risk: Arc::new(RiskEngine::default()),
+            instruments: Arc::new(InstrumentMaster::default()),
+            bars: Arc::new(BarHub::default()),
+            pace: Pace::default(),
+            timer_interval: Duration::from_secs(1),
+            strategy_capacity: 1024,
+            observer: None,
+        }
+    }
+
+    /// How fast market data is sent, `Pace::AsFastAsPossible` by default.
+    /// Use a basic scheduler runtime for reproducible runs at that pace.
+    pub fn set_pace(&mut self, pace: Pace) {
+        self.pace = pace;
+    }
+
+    /// How often strategies receive `Event::Timer`, in the simulated time
+    /// of the market data, defaults to one second.
+    pub fn set_timer_interval(&mut self, interval: Duration) {
+        self.timer_interval = interval;
+    }
+
+    /// How many events a strategy may fall behind before it loses the
+    /// oldest, 1024 by default. See `Pace::AsFastAsPossible`.
+    pub fn set_strategy_capacity(&mut self, capacity: usize) {
+        self.strategy_capacity = capacity;
+    }
+
+    /// Pre-trade limits applied to every order sent through the handles.
+    pub fn set_risk_limits(&mut self, limits: RiskLimits) {
+        self.risk = Arc::new(RiskEngine::new(limits));
+    }
+
+    /// Static data the risk engine checks lot and tick sizes against, e.g.
+    /// the master of an `XTPExchangeHandle`. Empty by default, which skips
+    /// those checks.
+    pub fn set_instruments(&mut self, instruments: Arc<InstrumentMaster>) {
+        self.instruments = instruments;
+    }
+
+    /// How orders resting in the book get filled, see `FillModel`.
+    pub fn set_fill_model(&mut self, fill_model: FillModel) {
+        self.engine.lock().unwrap().set_fill_model(fill_model);
+    }
+
+    /// Trading costs charged on each fill, nothing by default.
+    pub fn set_fees(&mut self, fees: FeeSchedule) {
+        self.engine.lock().unwrap().set_fees(fees);
+    }
+
+    /// Starting cash. Once set, buys the cash cannot pay for are rejected.
+    pub fn set_initial_capital(&mut self, capital: f64) {
+        self.engine.lock().unwrap().set_capital(capital);
+    }
+
+    pub(crate) fn set_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&MatchingEngine) + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    pub(crate) fn engine(&self) -> Arc<Mutex<MatchingEngine>> {
+        self.engine.clone()
+    }
+
+    fn dispatch(&mut self, event: Event) {
+        let quote_event = match &event {
+            Event::MarketData(quote_event) => quote_event,
+            _ => return,
+        };
+        let (kind, instrument) = match quote_event.topic() {
+            Some(topic) => topic,
+            None => return,
+        };
+        let venue = instrument.venue;
+        match quote_event {
+            QuoteEvent::Quote(quote) => {
+                let mut engine = self.engine.lock().unwrap();
+                let reports = engine.on_quote(quote);
+                self.router
+                    .route_market(kind, venue, &instrument.ticker, &event);
+                self.router.deliver(reports);
+                if let Some(observer) = &mut self.observer {
+                    observer(&*engine);
+                }
+                self.router.deliver_bars(self.bars.on_depth(
+                    venue,
+                    &instrument.ticker,
+                    quote.data_time,
+                    quote.last_price.to_f64(),
+                    quote.volume,
+                    quote.turnover,
+                ));
+            }
+            QuoteEvent::Trade(trade) => {
+                self.router
+                    .route_market(kind, venue, &instrument.ticker, &event);
+                self.router.deliver_bars(self.bars.on_trade(
+                    venue,
+                    &instrument.ticker,
+                    trade.data_time,
+                    trade.price.to_f64(),
+                    trade.quantity,
+                    trade.amount,
+                ));
+            }
+            _ => self
+                .router
+                .route_market(kind, venue, &instrument.ticker, &event),
+        }
+    }
+
+    fn handle(&self, strategy_id: StrategyId) -> SimExchangeHandle {
+        SimExchangeHandle {
+            strategy_id,
+            engine: self.engine.clone(),
+            router: self.router.clone(),
+            risk: self.risk.clone(),
+            instruments: self.instruments.clone(),
+            bars: self.bars.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for SimExchange {
+    type Event = Event;
+    type Handle = SimExchangeHandle;
+
+    /// There is no gateway to connect to.
+    async fn connect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Send all the market data, then close the strategies' channels and
+    /// return once they are done. The data starts once every strategy first
+    /// waits, for its events usually, so the subscriptions it makes before
+    /// that see all of it.
+    async fn run(mut self) -> Result<()> {
+        let mut running = vec![];
+        let mut started = vec![];
+        for s in std::mem::replace(&mut self.strategies, vec![]) {
+            let (id, rx) = self.router.add_strategy(self.strategy_capacity);
+            let (tx, ready) = oneshot::channel();
+            running.push(tokio::spawn(Started {
+                strategy: s.run(rx, self.handle(id)),
+                ready: Some(tx),
+            }));
+            started.push(ready);
+        }
+        for &session in &[Session::Quote, Session::Trader] {
+            self.router
+                .broadcast(Event::Connection(ConnectionState::Connected(session)));
+        }
+        for ready in started {
+            let _ = ready.await;
+        }
+
+        let feed = std::mem::replace(&mut self.feed, Box::new(iter::empty()));
+        let mut pacer = Pacer::new(self.pace);
+        let mut clock = SimClock::new(self.timer_interval);
+        for quote in feed {
+            if let Some(data_time) = quote.data_time() {
+                pacer.wait(data_time).await;
+                if let Some(now) = clock.advance(data_time) {
+                    self.router.deliver_bars(self.bars.close_until(data_time));
+                    self.router.broadcast(Event::Timer(now));
+                }
+            }
+            self.dispatch(Event::MarketData(quote));
+        }
+
+        self.router.deliver_bars(self.bars.flush());
+        self.router.close();
+        for strategy in running {
+            if let Err(e) = strategy.await {
+                error!("Strategy failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn register<S>(&mut self, s: S)
+    where
+        S: Strategy<SimExchange> + Send + Sync + 'static,
+    {
+        self.strategies.push(Box::new(s))
+    }
+}
+
+// A strategy, which tells `ready` once it was first polled, i.e. once it
+// first waited or returned.
+struct Started<F> {
+    strategy: F,
+    ready: Option<oneshot::Sender<()>>,
+}
+
+impl<F: Future + Unpin> Future for Started<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let poll = Pin::new(&mut self.strategy).poll(cx);
+        if let Some(ready) = self.ready.take() {
+            let _ = ready.send(());
+        }
+        poll
+    }
+}
+
+/// Runs a strategy written for another exchange built on the simulation,
+/// which shares its events and handle.
+pub(crate) struct OnSim<E>(pub Box<dyn Strategy<E> + Send + Sync>);
+
+#[async_trait]
+impl<E> Strategy<SimExchange> for OnSim<E>
+where
+    E: Exchange<Event = Event, Handle = SimExchangeHandle> + 'static,
+{
+    async fn run(self: Box<Self>, rx: Receiver<Event>, h: SimExchangeHandle) {
+        self.0.run(rx, h).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::{DepthLevel, Instrument, Price, SecurityType};
+    use crate::portfolio::Side;
+    use crate::risk::RiskRejection;
+
+    fn quote() -> Quote {
+        let mut quote = Quote::new(
+            Instrument::new(Venue::SH, "600036"),
+            20200102093000000,
+            Price::from_f64(10.),
+        );
+        quote.asks = vec![DepthLevel {
+            price: Price::from_f64(10.01),
+            quantity: 1000,
+        }];
+        quote
+    }
+
+    fn buy(price: f64, quantity: i64) -> OrderRequest {
+        OrderRequest::limit("600036", Venue::SH, Side::Buy, price, quantity)
+    }
+
+    #[test]
+    fn checks_lots_and_ticks_against_the_instruments() {
+        let instruments = Arc::new(InstrumentMaster::new());
+        instruments.insert(InstrumentInfo {
+            instrument: Instrument::new(Venue::SH, "600036"),
+            name: "CMB".to_string(),
+            security_type: SecurityType::Stock,
+            pre_close_price: Price::from_f64(10.),
+            upper_limit_price: Price::from_f64(11.),
+            lower_limit_price: Price::from_f64(9.),
+            tick_size: Price::from_f64(0.01),
+            buy_lot: 100,
+            sell_lot: 1,
+        });
+        let mut sim = SimExchange::new(Vec::<Quote>::new());
+        sim.set_instruments(instruments);
+        sim.engine().lock().unwrap().on_quote(&quote());
+        let h = sim.handle(0);
+
+        assert!(h.instrument("600036", Venue::SH).is_some());
+        match h.insert_order(&buy(10.01, 150)) {
+            Err(Error::Risk(RiskRejection::LotSize { lot, .. })) => assert_eq!(lot, 100),
+            _ => panic!("odd lot accepted"),
+        }
+        match h.insert_order(&buy(10.005, 100)) {
+            Err(Error::Risk(RiskRejection::TickSize { .. })) => {}
+            _ => panic!("price off the tick accepted"),
+        }
+        assert!(h.insert_order(&buy(10.01, 100)).is_ok());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::market::{DepthLevel, Instrument, Price, Quote, Venue};
+
+// The continuous sessions in milliseconds of the day, quotes are only
+// generated within them.
+const MORNING: (i64, i64) = (hms(9, 30), hms(11, 30));
+const AFTERNOON: (i64, i64) = (hms(13, 0), hms(15, 0));
+
+const fn hms(hours: i64, minutes: i64) -> i64 {
+    (hours * 60 + minutes) * 60_000
+}
+
+/// Generates quotes of a single ticker whose last price moves at most one
+/// tick up or down between snapshots. The same seed always produces the
+/// same quotes.
+#[derive(Debug, Clone)]
+pub struct RandomWalk {
+    quote: Quote,
+    tick_size: f64,
+    /// Last price in ticks.
+    ticks: i64,
+    depth: usize,
+    level_quantity: i64,
+    interval_ms: i64,
+    remaining: usize,
+    started: bool,
+    rng: u64,
+}
+
+impl RandomWalk {
+    /// `count` quotes starting at `start_price`, one every three seconds of
+    /// continuous trading from 09:30 on `day` (YYYYMMDD), five levels of
+    /// 1000 shares a side and a 10% limit band around the start price. The
+    /// lunch break is skipped, and the walk ends early at the 15:00 close.
+    pub fn new(ticker: &str, venue: Venue, day: u32, start_price: f64, count: usize) -> Self {
+        let tick_size = 0.01;
+        let ticks = (start_price / tick_size).round() as i64;
+        let mut quote = Quote::new(
+            Instrument::new(venue, ticker),
+            timestamp(day, MORNING.0),
+            Price::from_f64(start_price),
+        );
+        quote.pre_close_price = quote.last_price;
+        quote.upper_limit_price = Price::from_f64(round_to(start_price * 1.1, tick_size));
+        quote.lower_limit_price = Price::from_f64(round_to(start_price * 0.9, tick_size));
+        RandomWalk {
+            quote,
+            tick_size,
+            ticks,
+            depth: 5,
+            level_quantity: 1000,
+            interval_ms: 3000,
+            remaining: count,
+            started: false,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        // xorshift never leaves zero.
+        self.rng = if seed == 0 { 1 } else { seed };
+        self
+    }
+
+    pub fn interval_ms(mut self, interval_ms: i64) -> Self {
+        self.interval_ms = interval_ms;
+        self
+    }
+
+    pub fn depth(mut self, depth: usize, level_quantity: i64) -> Self {
+        self.depth = depth;
+        self.level_quantity = level_quantity;
+        self
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn price(&self, ticks: i64) -> Price {
+        Price::from_f64(ticks as f64 * self.tick_size)
+    }
+
+    fn level(&self, ticks: i64) -> DepthLevel {
+        DepthLevel {
+            price: self.price(ticks),
+            quantity: self.level_quantity,
+        }
+    }
+}
+
+impl Iterator for RandomWalk {
+    type Item = Quote;
+
+    fn next(&mut self) -> Option<Quote> {
+        if self.remaining == 0 {
+            return None;
+        }
+        if self.started {
+            match next_time(self.quote.data_time, self.interval_ms) {
+                Some(time) => self.quote.data_time = time,
+                None => {
+                    self.remaining = 0;
+                    return None;
+                }
+            }
+        }
+        self.started = true;
+        self.remaining -= 1;
+
+        let lower = (self.quote.lower_limit_price.to_f64() / self.tick_size).round() as i64;
+        let upper = (self.quote.upper_limit_price.to_f64() / self.tick_size).round() as i64;
+        let step = (self.next_random() % 3) as i64 - 1;
+        self.ticks = (self.ticks + step).max(lower).min(upper);
+        let traded = (self.next_random() % 10 + 1) as i64 * 100;
+
+        let last_price = self.price(self.ticks);
+        let bids = (0..self.depth as i64)
+            .map(|i| self.ticks - i)
+            .filter(|&t| t >= lower)
+            .map(|t| self.level(t))
+            .collect();
+        let asks = (1..=self.depth as i64)
+            .map(|i| self.ticks + i)
+            .filter(|&t| t <= upper)
+            .map(|t| self.level(t))
+            .collect();
+
+        let quote = &mut self.quote;
+        if quote.open_price.is_zero() {
+            quote.open_price = last_price;
+        }
+        quote.high_price = quote.high_price.max(last_price);
+        quote.low_price = if quote.low_price.is_zero() {
+            last_price
+        } else {
+            quote.low_price.min(last_price)
+        };
+        quote.last_price = last_price;
+        quote.volume += traded;
+        quote.turnover += last_price.to_f64() * traded as f64;
+        quote.bids = bids;
+        quote.asks = asks;
+        Some(quote.clone())
+    }
+}
+
+fn round_to(price: f64, tick_size: f64) -> f64 {
+    (price / tick_size).round() * tick_size
+}
+
+/// `millis` of continuous trading after a YYYYMMDDHHMMSSsss timestamp, not
+/// counting the lunch break. `None` past the close.
+fn next_time(time: i64, millis: i64) -> Option<i64> {
+    let now = millis_of_day(time);
+    let mut next = now + millis;
+    if now < MORNING.1 && next >= MORNING.1 {
+        next += AFTERNOON.0 - MORNING.1;
+    }
+    if next >= AFTERNOON.1 {
+        return None;
+    }
+    Some(timestamp((time / 1_000_000_000) as u32, next))
+}
+
+fn timestamp(day: u32, millis: i64) -> i64 {
+    i64::from(day) * 1_000_000_000
+        + millis / 3_600_000 * 10_000_000
+        + millis / 60_000 % 60 * 100_000
+        + millis / 1000 % 60 * 1000
+        + millis % 1000
+}
+
+/// Milliseconds since midnight of a YYYYMMDDHHMMSSsss timestamp.
+pub(crate) fn millis_of_day(time: i64) -> i64 {
+    let hms = time % 1_000_000_000;
+    (hms / 10_000_000) * 3_600_000
+        + (hms / 100_000 % 100) * 60_000
+        + (hms / 1000 % 100) * 1000
+        + hms % 1000
+}
+
+/// Milliseconds of continuous trading on the day of a YYYYMMDDHHMMSSsss
+/// timestamp up to it, from zero at the open to four hours at the close.
+pub(crate) fn session_millis(time: i64) -> i64 {
+    let millis = millis_of_day(time);
+    let morning = (millis.min(MORNING.1) - MORNING.0).max(0);
+    let afternoon = (millis.min(AFTERNOON.1) - AFTERNOON.0).max(0);
+    morning + afternoon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk(interval_ms: i64, count: usize) -> Vec<Quote> {
+        RandomWalk::new("600036", Venue::SH, 20200102, 35., count)
+            .interval_ms(interval_ms)
+            .collect()
+    }
+
+    #[test]
+    fn skips_the_lunch_break() {
+        // 09:30 to 11:29 is 120 one minute steps, the next one is 13:00.
+        let times: Vec<i64> = walk(60_000, 122).iter().map(|q| q.data_time).collect();
+        assert_eq!(times[0], 20200102093000000);
+        assert_eq!(times[119], 20200102112900000);
+        assert_eq!(times[120], 20200102130000000);
+        assert_eq!(times[121], 20200102130100000);
+    }
+
+    #[test]
+    fn ends_at_the_close() {
+        let quotes = walk(60_000, 1000);
+        assert_eq!(quotes.len(), 240);
+        assert_eq!(quotes.last().unwrap().data_time, 20200102145900000);
+    }
+
+    #[test]
+    fn stays_within_the_limits() {
+        for quote in RandomWalk::new("600036", Venue::SH, 20200102, 35., 4800)
+            .interval_ms(3000)
+            .seed(7)
+        {
+            assert!(quote.last_price >= quote.lower_limit_price);
+            assert!(quote.last_price <= quote.upper_limit_price);
+            if let Some(ask) = quote.best_ask() {
+                assert!(quote.bids.iter().all(|l| l.price < ask.price));
+            }
+            assert!(quote.data_time < 20200102150000000);
+        }
+    }
+
+    #[test]
+    fn counts_only_continuous_trading() {
+        assert_eq!(session_millis(20200102091500000), 0);
+        assert_eq!(session_millis(20200102093001500), 1500);
+        assert_eq!(session_millis(20200102120000000), hms(2, 0));
+        assert_eq!(session_millis(20200102130100000), hms(2, 1));
+        assert_eq!(session_millis(20200102150300000), hms(4, 0));
+    }
+}
@@ -0,0 +1,855 @@
+use crate::exchanges::xtp::{
+    trading_day, ClientOrderId, OrderRequest, OrderSnapshot, OrderState, PriceType, StrategyId,
+};
+use crate::market::{DepthLevel, Price, Quote, Venue};
+use crate::portfolio::{Portfolio, Side};
+use crate::{Error, Event, Fill, Result};
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+
+/// Reports to deliver, each to the strategy owning the order but positions,
+/// which go to every strategy.
+pub(crate) type Reports = Vec<(StrategyId, Event)>;
+
+// Orders that are not plain limit orders sweep at most this many levels,
+// like XTP's best five.
+const MARKET_DEPTH: usize = 5;
+
+/// How orders get filled beyond crossing the book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FillModel {
+    /// Passive orders wait behind the quantity displayed at their price when
+    /// they arrived, and fill only once traded volume has gone through it.
+    /// Without it they fill as soon as the book crosses them. Orders priced
+    /// beyond the displayed depth start at the front of the queue.
+    pub queue_position: bool,
+    /// Ticks by which aggressive fills are worsened, up to the limit price.
+    pub slippage_ticks: u32,
+    pub tick_size: f64,
+}
+
+impl Default for FillModel {
+    fn default() -> Self {
+        FillModel {
+            queue_position: false,
+            slippage_ticks: 0,
+            tick_size: 0.01,
+        }
+    }
+}
+
+/// Trading costs as fractions of the traded amount. The default charges
+/// nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FeeSchedule {
+    /// Broker commission, charged on both sides.
+    pub commission_rate: f64,
+    /// Smallest commission charged on an order.
+    pub min_commission: f64,
+    /// Charged on sells only.
+    pub stamp_duty_rate: f64,
+    /// Charged on both sides.
+    pub transfer_fee_rate: f64,
+}
+
+impl FeeSchedule {
+    /// Typical retail A-share costs: 0.03% commission with a 5 CNY minimum,
+    /// 0.1% stamp duty and 0.002% transfer fee.
+    pub fn a_share() -> Self {
+        FeeSchedule {
+            commission_rate: 0.0003,
+            min_commission: 5.,
+            stamp_duty_rate: 0.001,
+            transfer_fee_rate: 0.00002,
+        }
+    }
+
+    /// Commission of an order that traded `amount` so far.
+    fn commission(&self, amount: f64) -> f64 {
+        if amount <= 0. {
+            return 0.;
+        }
+        (amount * self.commission_rate).max(self.min_commission)
+    }
+
+    fn taxes(&self, side: Side, amount: f64) -> f64 {
+        let stamp_duty = match side {
+            Side::Sell => amount * self.stamp_duty_rate,
+            Side::Buy => 0.,
+        };
+        stamp_duty + amount * self.transfer_fee_rate
+    }
+}
+
+// Volume traded since the previous quote, and at what price. The resting
+// orders share it in time priority, `credited` is what they already got.
+struct Traded {
+    volume: i64,
+    last_price: Price,
+    credited: i64,
+}
+
+// The latest quote of a ticker, and what is left of its depth after the
+// fills it already gave. The next quote restores the depth.
+struct Book {
+    quote: Quote,
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+}
+
+struct SimOrder {
+    owner: StrategyId,
+    venue: Venue,
+    side: Side,
+    price_type: PriceType,
+    snapshot: OrderSnapshot,
+    // What a buy sets aside per share while open: its limit price, or the
+    // upper limit for orders without one.
+    reserve_price: f64,
+    traded_amount: f64,
+    commission: f64,
+    // Quantity ahead of the order at its price, once it rests.
+    queue_ahead: Option<i64>,
+}
+
+/// Fills orders against the depth of the latest quotes. An order takes the
+/// quantity shown at each level it crosses, at that level's price, and
+/// rests for the next quotes with whatever is left. Orders that are not
+/// plain limit orders cancel what the book cannot fill right away.
+#[derive(Default)]
+pub(crate) struct MatchingEngine {
+    books: HashMap<(Venue, String), Book>,
+    // Client ids increase, so iterating gives time priority.
+    orders: BTreeMap<ClientOrderId, SimOrder>,
+    next_client_id: ClientOrderId,
+    next_exec_id: u64,
+    portfolio: Portfolio,
+    time: i64,
+    fill_model: FillModel,
+    fees: FeeSchedule,
+    // Buys beyond the cash are rejected once a capital is set.
+    capital: Option<f64>,
+    cash: f64,
+    fees_paid: f64,
+    trades: Vec<Fill>,
+}
+
+impl MatchingEngine {
+    pub fn set_fill_model(&mut self, fill_model: FillModel) {
+        self.fill_model = fill_model;
+    }
+
+    pub fn set_fees(&mut self, fees: FeeSchedule) {
+        self.fees = fees;
+    }
+
+    pub fn set_capital(&mut self, capital: f64) {
+        self.capital = Some(capital);
+        self.cash = capital;
+    }
+
+    pub fn on_quote(&mut self, quote: &Quote) -> Reports {
+        let venue = quote.instrument.venue;
+        let ticker = &quote.instrument.ticker;
+        let key = (venue, ticker.clone());
+        // Volume traded since the previous quote, and at what price.
+        let mut traded = self
+            .books
+            .get(&key)
+            .filter(|book| trading_day(book.quote.data_time) == trading_day(quote.data_time))
+            .map(|book| Traded {
+                volume: quote.volume - book.quote.volume,
+                last_price: quote.last_price,
+                credited: 0,
+            })
+            .filter(|traded| traded.volume > 0);
+
+        self.time = quote.data_time;
+        self.portfolio.roll_day(trading_day(quote.data_time));
+        self.portfolio
+            .on_price(venue, ticker, quote.last_price.to_f64());
+        self.books.insert(
+            key,
+            Book {
+                quote: quote.clone(),
+                bids: quote.bids.clone(),
+                asks: quote.asks.clone(),
+            },
+        );
+
+        let resting: Vec<ClientOrderId> = self
+            .orders
+            .iter()
+            .filter(|(_, order)| {
+                !order.snapshot.state.is_terminal()
+                    && order.venue == venue
+                    && &order.snapshot.ticker == ticker
+            })
+            .map(|(&client_id, _)| client_id)
+            .collect();
+        let mut reports = vec![];
+        for client_id in resting {
+            self.fill(client_id, traded.as_mut(), &mut reports);
+        }
+        reports
+    }
+
+    pub fn insert(
+        &mut self,
+        owner: StrategyId,
+        req: &OrderRequest,
+    ) -> Result<(ClientOrderId, Reports)> {
+        let (venue, side) = (req.venue, req.side);
+        if venue == Venue::Unknown {
+            return Err(Error::Api(format!("Unsupported venue {:?}", venue)));
+        }
+        if req.quantity <= 0 {
+            return Err(Error::Api(format!("Invalid quantity {}", req.quantity)));
+        }
+
+        let reserve_price = match req.price_type {
+            PriceType::Limit => req.price,
+            _ => self.quote(&req.ticker, venue).map_or(0., |quote| {
+                if quote.upper_limit_price.is_zero() {
+                    quote.last_price.to_f64()
+                } else {
+                    quote.upper_limit_price.to_f64()
+                }
+            }),
+        };
+
+        self.next_client_id += 1;
+        let client_id = self.next_client_id;
+        let mut order = SimOrder {
+            owner,
+            venue,
+            side,
+            price_type: req.price_type,
+            snapshot: OrderSnapshot {
+                client_id: Some(client_id),
+                xtp_id: u64::from(client_id),
+                ticker: req.ticker.clone(),
+                venue,
+                side,
+                price: req.price,
+                quantity: req.quantity,
+                filled_quantity: 0,
+                leaves_quantity: req.quantity,
+                avg_price: 0.,
+                state: OrderState::New,
+            },
+            reserve_price,
+            traded_amount: 0.,
+            commission: 0.,
+            queue_ahead: None,
+        };
+
+        let rejected = self.rejects(&order);
+        if rejected {
+            order.snapshot.state = OrderState::Rejected;
+            order.snapshot.leaves_quantity = 0;
+        }
+        let mut reports = vec![(owner, Event::Order(order.snapshot.clone()))];
+        self.orders.insert(client_id, order);
+
+        if !rejected {
+            self.fill(client_id, None, &mut reports);
+            if req.price_type != PriceType::Limit {
+                self.cancel_open(client_id, &mut reports);
+            }
+        }
+        Ok((client_id, reports))
+    }
+
+    pub fn cancel(&mut self, client_id: ClientOrderId) -> Result<Reports> {
+        let state = match self.orders.get(&client_id) {
+            Some(order) => order.snapshot.state,
+            None => return Err(Error::Api(format!("Unknown client order id {}", client_id))),
+        };
+        if state.is_terminal() {
+            return Err(Error::Api(format!(
+                "Order {} is already {:?}",
+                client_id, state
+            )));
+        }
+        let mut reports = vec![];
+        self.cancel_open(client_id, &mut reports);
+        Ok(reports)
+    }
+
+    pub fn order(&self, client_id: ClientOrderId) -> Option<OrderSnapshot> {
+        self.orders
+            .get(&client_id)
+            .map(|order| order.snapshot.clone())
+    }
+
+    pub fn orders(&self) -> Vec<OrderSnapshot> {
+        self.orders
+            .values()
+            .map(|order| order.snapshot.clone())
+            .collect()
+    }
+
+    pub fn open_orders(&self) -> Vec<OrderSnapshot> {
+        self.orders
+            .values()
+            .filter(|order| !order.snapshot.state.is_terminal())
+            .map(|order| order.snapshot.clone())
+            .collect()
+    }
+
+    pub fn quote(&self, ticker: &str, venue: Venue) -> Option<&Quote> {
+        self.books
+            .get(&(venue, ticker.to_string()))
+            .map(|book| &book.quote)
+    }
+
+    pub fn portfolio(&self) -> &Portfolio {
+        &self.portfolio
+    }
+
+    /// Exchange time of the latest quote, YYYYMMDDHHMMSSsss.
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    pub fn cash(&self) -> f64 {
+        self.cash
+    }
+
+    /// Cash plus the market value of the positions.
+    pub fn equity(&self) -> f64 {
+        self.cash
+            + self
+                .portfolio
+                .positions()
+                .map(|p| p.market_value())
+                .sum::<f64>()
+    }
+
+    pub fn fees_paid(&self) -> f64 {
+        self.fees_paid
+    }
+
+    pub fn trades(&self) -> &[Fill] {
+        &self.trades
+    }
+
+    // What the broker or the exchange would refuse outright. Open orders
+    // hold on to the cash and the shares they may still trade.
+    fn rejects(&self, order: &SimOrder) -> bool {
+        let snapshot = &order.snapshot;
+        let quote = self.quote(&snapshot.ticker, order.venue);
+        if order.side == Side::Sell {
+            let sellable = self
+                .portfolio
+                .position(order.venue, &snapshot.ticker)
+                .map_or(0, |p| p.sellable_quantity);
+            let selling = self
+                .open(Side::Sell)
+                .filter(|o| o.venue == order.venue)
+                .filter(|o| o.snapshot.ticker == snapshot.ticker)
+                .map(|o| o.snapshot.leaves_quantity)
+                .sum::<i64>();
+            if snapshot.quantity > sellable - selling {
+                warn!(
+                    "Rejecting sell of {} {}, only {} sellable with {} in open orders",
+                    snapshot.quantity, snapshot.ticker, sellable, selling
+                );
+                return true;
+            }
+        }
+        if order.side == Side::Buy && self.capital.is_some() {
+            let reserved = self
+                .open(Side::Buy)
+                .map(|o| self.reserved_cash(o))
+                .sum::<f64>();
+            let cost = self.reserved_cash(order);
+            if cost > self.cash - reserved {
+                warn!(
+                    "Rejecting buy of {} {}, costs {} with {} cash and {} in open orders",
+                    snapshot.quantity, snapshot.ticker, cost, self.cash, reserved
+                );
+                return true;
+            }
+        }
+        if order.price_type == PriceType::Limit {
+            if let Some(quote) = quote.filter(|q| !q.upper_limit_price.is_zero()) {
+                let price = Price::from_f64(snapshot.price);
+                let below = price < quote.lower_limit_price;
+                let above = price > quote.upper_limit_price;
+                if below || above {
+                    warn!(
+                        "Rejecting {} at {}, outside [{}, {}]",
+                        snapshot.ticker,
+                        snapshot.price,
+                        quote.lower_limit_price,
+                        quote.upper_limit_price
+                    );
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn open(&self, side: Side) -> impl Iterator<Item = &SimOrder> {
+        self.orders
+            .values()
+            .filter(move |o| o.side == side && !o.snapshot.state.is_terminal())
+    }
+
+    // Cash a buy may still spend on what it has left, fees included.
+    fn reserved_cash(&self, order: &SimOrder) -> f64 {
+        let amount = order.reserve_price * order.snapshot.leaves_quantity as f64;
+        let commission = self.fees.commission(order.traded_amount + amount) - order.commission;
+        amount + commission + self.fees.taxes(Side::Buy, amount)
+    }
+
+    fn fill(
+        &mut self,
+        client_id: ClientOrderId,
+        traded: Option<&mut Traded>,
+        reports: &mut Reports,
+    ) {
+        let fills = self.match_order(client_id, traded);
+        if fills.is_empty() {
+            return;
+        }
+        for (price, quantity) in fills {
+            self.execute(client_id, price.to_f64(), quantity, reports);
+        }
+        if let Some(order) = self.orders.get(&client_id) {
+            reports.push((order.owner, Event::Order(order.snapshot.clone())));
+        }
+    }
+
+    // Price and quantity of each fill the order gets from the current book,
+    // and from what traded since the previous quote.
+    fn match_order(
+        &mut self,
+        client_id: ClientOrderId,
+        traded: Option<&mut Traded>,
+    ) -> Vec<(Price, i64)> {
+        let model = self.fill_model;
+        let order = match self.orders.get_mut(&client_id) {
+            Some(order) => order,
+            None => return vec![],
+        };
+        let book = match self
+            .books
+            .get_mut(&(order.venue, order.snapshot.ticker.clone()))
+        {
+            Some(book) => book,
+            None => return vec![],
+        };
+        let limit = match order.price_type {
+            PriceType::Limit => Some(Price::from_f64(order.snapshot.price)),
+            _ => None,
+        };
+        let side = order.side;
+        let mut leaves = order.snapshot.leaves_quantity;
+        let mut fills = vec![];
+
+        // Take the other side of the book as far as the order crosses it.
+        let (levels, depth) = match (side, limit) {
+            (Side::Buy, Some(_)) => (&mut book.asks, usize::max_value()),
+            (Side::Sell, Some(_)) => (&mut book.bids, usize::max_value()),
+            (Side::Buy, None) => (&mut book.asks, MARKET_DEPTH),
+            (Side::Sell, None) => (&mut book.bids, MARKET_DEPTH),
+        };
+        for level in levels.iter_mut().take(depth) {
+            let DepthLevel {
+                price,
+                quantity: available,
+            } = *level;
+            if leaves <= 0 {
+                break;
+            }
+            let crosses = match (side, limit) {
+                (_, None) => true,
+                (Side::Buy, Some(limit)) => price <= limit,
+                (Side::Sell, Some(limit)) => price >= limit,
+            };
+            if !crosses {
+                break;
+            }
+            let quantity = available.min(leaves);
+            if quantity <= 0 {
+                continue;
+            }
+            level.quantity -= quantity;
+            leaves -= quantity;
+
+            let slippage = Price::from_f64(f64::from(model.slippage_ticks) * model.tick_size);
+            let price = match (side, limit) {
+                (Side::Buy, Some(limit)) => (price + slippage).min(limit),
+                (Side::Buy, None) => price + slippage,
+                (Side::Sell, Some(limit)) => (price - slippage).max(limit),
+                (Side::Sell, None) => price - slippage,
+            };
+            fills.push((price, quantity));
+        }
+
+        // Then wait in the queue at the limit price.
+        let limit = match limit {
+            Some(limit) if model.queue_position && leaves > 0 => limit,
+            _ => return fills,
+        };
+        let own_side = match side {
+            Side::Buy => &book.bids,
+            Side::Sell => &book.asks,
+        };
+        let within_depth = own_side.last().map_or(false, |worst| match side {
+            Side::Buy => limit >= worst.price,
+            Side::Sell => limit <= worst.price,
+        });
+        let displayed = own_side
+            .iter()
+            .find(|level| level.price == limit)
+            .map_or(0, |level| level.quantity);
+
+        let mut ahead = match order.queue_ahead {
+            Some(ahead) => ahead,
+            None => {
+                order.queue_ahead = Some(if within_depth { displayed } else { 0 });
+                return fills;
+            }
+        };
+        if let Some(traded) = traded {
+            let through = match side {
+                Side::Buy => traded.last_price < limit,
+                Side::Sell => traded.last_price > limit,
+            };
+            let at = traded.last_price == limit;
+            // Trading through the price clears the queue, but only the volume
+            // beyond it, and beyond what earlier orders got, reaches us.
+            let available = if through || at {
+                (traded.volume - ahead - traded.credited).max(0)
+            } else {
+                0
+            };
+            if through {
+                ahead = 0;
+            } else if at {
+                ahead = (ahead - traded.volume).max(0);
+            }
+            let quantity = available.min(leaves);
+            if quantity > 0 {
+                traded.credited += quantity;
+                fills.push((limit, quantity));
+            }
+        }
+        // Whatever left the level without trading was cancelled, assume
+        // it was ahead of us.
+        if within_depth {
+            ahead = ahead.min(displayed);
+        }
+        order.queue_ahead = Some(ahead);
+        fills
+    }
+
+    fn execute(
+        &mut self,
+        client_id: ClientOrderId,
+        price: f64,
+        quantity: i64,
+        reports: &mut Reports,
+    ) {
+        let order = match self.orders.get_mut(&client_id) {
+            Some(order) => order,
+            None => return,
+        };
+        let amount = price * quantity as f64;
+        order.traded_amount += amount;
+        let snapshot = &mut order.snapshot;
+        snapshot.filled_quantity += quantity;
+        snapshot.leaves_quantity -= quantity;
+        snapshot.avg_price = order.traded_amount / snapshot.filled_quantity as f64;
+        snapshot.state = if snapshot.leaves_quantity == 0 {
+            OrderState::Filled
+        } else {
+            OrderState::PartiallyFilled
+        };
+
+        let commission = self.fees.commission(order.traded_amount);
+        let fee = commission - order.commission + self.fees.taxes(order.side, amount);
+        order.commission = commission;
+        self.fees_paid += fee;
+        self.cash += match order.side {
+            Side::Buy => -amount - fee,
+            Side::Sell => amount - fee,
+        };
+
+        self.portfolio
+            .on_fill(order.venue, &snapshot.ticker, order.side, quantity, price);
+        self.next_exec_id += 1;
+        let trade = Fill {
+            client_id: Some(client_id),
+            exec_id: self.next_exec_id.to_string(),
+            ticker: snapshot.ticker.clone(),
+            venue: snapshot.venue,
+            side: snapshot.side,
+            price,
+            quantity,
+            fee: Some(fee),
+            trade_time: self.time,
+        };
+        self.trades.push(trade.clone());
+        reports.push((order.owner, Event::Fill(trade)));
+        if let Some(position) = self.portfolio.position(order.venue, &snapshot.ticker) {
+            reports.push((order.owner, Event::Position(position.clone())));
+        }
+    }
+
+    fn cancel_open(&mut self, client_id: ClientOrderId, reports: &mut Reports) {
+        if let Some(order) = self.orders.get_mut(&client_id) {
+            if order.snapshot.state.is_terminal() {
+                return;
+            }
+            order.snapshot.state = OrderState::Cancelled;
+            order.snapshot.leaves_quantity = 0;
+            reports.push((order.owner, Event::Order(order.snapshot.clone())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::Instrument;
+
+    const MORNING: i64 = 20200102093000000;
+    const NEXT_MORNING: i64 = 20200103093000000;
+
+    fn levels(levels: &[(f64, i64)]) -> Vec<DepthLevel> {
+        levels
+            .iter()
+            .map(|&(price, quantity)| DepthLevel {
+                price: Price::from_f64(price),
+                quantity,
+            })
+            .collect()
+    }
+
+    fn quote(data_time: i64, bids: &[(f64, i64)], asks: &[(f64, i64)]) -> Quote {
+        let mut quote = Quote::new(
+            Instrument::new(Venue::SH, "600036"),
+            data_time,
+            Price::from_f64(10.),
+        );
+        quote.bids = levels(bids);
+        quote.asks = levels(asks);
+        quote
+    }
+
+    fn order(side: Side, price: f64, quantity: i64) -> OrderRequest {
+        OrderRequest::limit("600036", Venue::SH, side, price, quantity)
+    }
+
+    fn fills(reports: &Reports) -> Vec<(f64, i64)> {
+        reports
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Fill(fill) => Some((fill.price, fill.quantity)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossing_order_sweeps_the_levels() {
+        let mut engine = MatchingEngine::default();
+        engine.on_quote(&quote(
+            MORNING,
+            &[(9.99, 100)],
+            &[(10.01, 300), (10.02, 500), (10.03, 500)],
+        ));
+        let (id, reports) = engine.insert(0, &order(Side::Buy, 10.02, 600)).unwrap();
+        assert_eq!(fills(&reports), vec![(10.01, 300), (10.02, 300)]);
+        // Each fill is followed by the position it left.
+        let positions: Vec<i64> = reports
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Position(position) => Some(position.quantity),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(positions, vec![300, 600]);
+
+        let snapshot = engine.order(id).unwrap();
+        assert_eq!(snapshot.state, OrderState::Filled);
+        assert_eq!(snapshot.leaves_quantity, 0);
+        assert!((snapshot.avg_price - 10.015).abs() < 1e-9);
+        assert_eq!(
+            engine
+                .portfolio()
+                .position(Venue::SH, "600036")
+                .unwrap()
+                .quantity,
+            600
+        );
+    }
+
+    #[test]
+    fn partial_fill_rests_until_the_next_quote() {
+        let mut engine = MatchingEngine::default();
+        engine.on_quote(&quote(MORNING, &[(10., 100)], &[(10.01, 300)]));
+        let (id, reports) = engine.insert(0, &order(Side::Buy, 10.01, 500)).unwrap();
+        assert_eq!(fills(&reports), vec![(10.01, 300)]);
+        let snapshot = engine.order(id).unwrap();
+        assert_eq!(snapshot.state, OrderState::PartiallyFilled);
+        assert_eq!(snapshot.leaves_quantity, 200);
+
+        // What it took is gone until the next quote restores the depth.
+        let (_, reports) = engine.insert(0, &order(Side::Buy, 10.01, 100)).unwrap();
+        assert!(fills(&reports).is_empty());
+
+        let reports = engine.on_quote(&quote(MORNING + 3000, &[(10., 100)], &[(10.01, 250)]));
+        assert_eq!(fills(&reports), vec![(10.01, 200), (10.01, 50)]);
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Filled);
+    }
+
+    #[test]
+    fn cancel_ends_a_resting_order() {
+        let mut engine = MatchingEngine::default();
+        engine.on_quote(&quote(MORNING, &[(10., 100)], &[(10.01, 300)]));
+        let (id, _) = engine.insert(0, &order(Side::Buy, 9.98, 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::New);
+        assert_eq!(engine.open_orders().len(), 1);
+
+        let reports = engine.cancel(id).unwrap();
+        assert_eq!(reports.len(), 1);
+        let snapshot = engine.order(id).unwrap();
+        assert_eq!(snapshot.state, OrderState::Cancelled);
+        assert_eq!(snapshot.leaves_quantity, 0);
+        assert!(engine.open_orders().is_empty());
+        assert!(engine.cancel(id).is_err());
+
+        // Cancelled orders do not fill when the book comes to them.
+        let reports = engine.on_quote(&quote(MORNING + 3000, &[(9.97, 100)], &[(9.98, 300)]));
+        assert!(fills(&reports).is_empty());
+    }
+
+    #[test]
+    fn shares_bought_today_are_sold_tomorrow() {
+        let mut engine = MatchingEngine::default();
+        engine.on_quote(&quote(MORNING, &[(10., 500)], &[(10.01, 500)]));
+        engine.insert(0, &order(Side::Buy, 10.01, 100)).unwrap();
+
+        let (id, reports) = engine.insert(0, &order(Side::Sell, 10., 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Rejected);
+        assert!(fills(&reports).is_empty());
+
+        engine.on_quote(&quote(NEXT_MORNING, &[(10., 500)], &[(10.01, 500)]));
+        let (id, reports) = engine.insert(0, &order(Side::Sell, 10., 100)).unwrap();
+        assert_eq!(fills(&reports), vec![(10., 100)]);
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Filled);
+    }
+
+    #[test]
+    fn fees_come_out_of_the_cash() {
+        let mut engine = MatchingEngine::default();
+        engine.set_fees(FeeSchedule::a_share());
+        engine.set_capital(100_000.);
+        engine.on_quote(&quote(MORNING, &[(10., 500)], &[(10.01, 500)]));
+        let (_, reports) = engine.insert(0, &order(Side::Buy, 10.01, 100)).unwrap();
+        // The minimum commission, and no stamp duty on buys.
+        let fee = 5. + 1001. * 0.00002;
+        match &reports[1].1 {
+            Event::Fill(fill) => assert!((fill.fee.unwrap() - fee).abs() < 1e-9),
+            event => panic!("not a fill: {:?}", event),
+        }
+        assert!((engine.cash() - (100_000. - 1001. - fee)).abs() < 1e-9);
+
+        engine.on_quote(&quote(NEXT_MORNING, &[(10., 500)], &[(10.01, 500)]));
+        engine.insert(0, &order(Side::Sell, 10., 100)).unwrap();
+        let sell_fee = 5. + 1000. * 0.001 + 1000. * 0.00002;
+        assert!((engine.fees_paid() - (fee + sell_fee)).abs() < 1e-9);
+        assert!((engine.cash() - (100_000. - 1. - fee - sell_fee)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn buys_need_the_cash_for_their_fees() {
+        let mut engine = MatchingEngine::default();
+        engine.set_fees(FeeSchedule::a_share());
+        engine.set_capital(1005.);
+        engine.on_quote(&quote(MORNING, &[(9.99, 100)], &[(10., 500)]));
+        let (id, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Rejected);
+    }
+
+    #[test]
+    fn open_buys_hold_on_to_their_cash() {
+        let mut engine = MatchingEngine::default();
+        engine.set_capital(1500.);
+        engine.on_quote(&quote(MORNING, &[(9.99, 100)], &[(10.05, 100)]));
+        let (first, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+        assert_eq!(engine.order(first).unwrap().state, OrderState::New);
+
+        let (id, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Rejected);
+
+        engine.cancel(first).unwrap();
+        let (id, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::New);
+    }
+
+    #[test]
+    fn open_sells_hold_on_to_their_shares() {
+        let mut engine = MatchingEngine::default();
+        engine.on_quote(&quote(MORNING, &[(10., 500)], &[(10.01, 500)]));
+        engine.insert(0, &order(Side::Buy, 10.01, 100)).unwrap();
+        engine.on_quote(&quote(NEXT_MORNING, &[(10., 500)], &[(10.01, 500)]));
+
+        let (first, _) = engine.insert(0, &order(Side::Sell, 10.05, 100)).unwrap();
+        assert_eq!(engine.order(first).unwrap().state, OrderState::New);
+        let (id, _) = engine.insert(0, &order(Side::Sell, 10.05, 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::Rejected);
+
+        engine.cancel(first).unwrap();
+        let (id, _) = engine.insert(0, &order(Side::Sell, 10.05, 100)).unwrap();
+        assert_eq!(engine.order(id).unwrap().state, OrderState::New);
+    }
+
+    fn queued() -> MatchingEngine {
+        let mut engine = MatchingEngine::default();
+        engine.set_fill_model(FillModel {
+            queue_position: true,
+            ..FillModel::default()
+        });
+        let mut first = quote(MORNING, &[(10., 200)], &[(10.01, 300)]);
+        first.volume = 1000;
+        engine.on_quote(&first);
+        engine
+    }
+
+    #[test]
+    fn traded_volume_fills_the_queue_once() {
+        let mut engine = queued();
+        let (first, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+        let (second, _) = engine.insert(0, &order(Side::Buy, 10., 100)).unwrap();
+
+        // 300 traded at our price, 200 of it went to the orders ahead of
+        // both, the rest to the first order.
+        let mut next = quote(MORNING + 3000, &[(10., 100)], &[(10.01, 300)]);
+        next.volume = 1300;
+        let reports = engine.on_quote(&next);
+        assert_eq!(fills(&reports), vec![(10., 100)]);
+        assert_eq!(engine.order(first).unwrap().state, OrderState::Filled);
+        assert_eq!(engine.order(second).unwrap().state, OrderState::New);
+    }
+
+    #[test]
+    fn trading_through_fills_only_beyond_the_queue() {
+        let mut engine = queued();
+        let (id, _) = engine.insert(0, &order(Side::Buy, 10., 300)).unwrap();
+
+        let mut next = quote(MORNING + 3000, &[(9.99, 100)], &[(10.01, 300)]);
+        next.last_price = Price::from_f64(9.99);
+        next.volume = 1300;
+        let reports = engine.on_quote(&next);
+        assert_eq!(fills(&reports), vec![(10., 100)]);
+        assert_eq!(engine.order(id).unwrap().leaves_quantity, 200);
+    }
+}
@@ -0,0 +1,111 @@
+use super::feed::millis_of_day;
+use crate::exchanges::xtp::trading_day;
+use std::time::Duration;
+use tokio::task;
+use tokio::time::{self, Instant};
+
+/// How fast simulated or replayed market data reaches the strategies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// No waiting, only a yield to the strategies after each event. On a
+    /// basic scheduler runtime that lets every strategy take in the event
+    /// before the next one, unless it is awaiting something else. Nothing
+    /// holds the data back for a strategy that falls behind: once it is
+    /// more than its channel capacity behind, it loses the oldest events
+    /// and gets `RecvError::Lagged` with their number.
+    AsFastAsPossible,
+    /// Keep the time between events given by their exchange timestamps.
+    Original,
+    /// The original timing sped up by this factor.
+    Accelerated(f64),
+}
+
+impl Default for Pace {
+    fn default() -> Self {
+        Pace::AsFastAsPossible
+    }
+}
+
+/// Holds each event back until its time has come, relative to the first
+/// event of its trading day. The night between two days is skipped.
+pub(crate) struct Pacer {
+    pace: Pace,
+    // Trading day, exchange time and wall time of the first event of the day.
+    start: Option<(u32, i64, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(pace: Pace) -> Self {
+        Pacer { pace, start: None }
+    }
+
+    pub async fn wait(&mut self, data_time: i64) {
+        let speed = match self.pace {
+            Pace::Original => 1.,
+            Pace::Accelerated(speed) if speed > 0. => speed,
+            _ => return task::yield_now().await,
+        };
+        let day = trading_day(data_time);
+        let millis = millis_of_day(data_time);
+        let (_, start_millis, started_at) = match self.start {
+            Some(start) if start.0 == day => start,
+            _ => {
+                let start = (day, millis, Instant::now());
+                self.start = Some(start);
+                start
+            }
+        };
+        let elapsed = (millis - start_millis).max(0) as f64 / speed;
+        time::delay_until(started_at + Duration::from_millis(elapsed as u64)).await;
+    }
+}
+
+/// Simulated time, driven by the exchange timestamps of the events rather
+/// than the wall clock. Trading days follow each other without the night in
+/// between.
+pub(crate) struct SimClock {
+    origin: Instant,
+    interval: Duration,
+    // Trading day and exchange time of the latest event.
+    last: Option<(u32, i64)>,
+    elapsed: Duration,
+    next_tick: Duration,
+}
+
+impl SimClock {
+    pub fn new(interval: Duration) -> Self {
+        SimClock {
+            origin: Instant::now(),
+            interval,
+            last: None,
+            elapsed: Duration::from_millis(0),
+            next_tick: interval,
+        }
+    }
+
+    /// Move the clock to `data_time`, returning the timer tick due by then.
+    /// Ticks missed over a gap in the data, like the lunch break, are merged
+    /// into one.
+    pub fn advance(&mut self, data_time: i64) -> Option<Instant> {
+        let day = trading_day(data_time);
+        let millis = millis_of_day(data_time);
+        if let Some((last_day, last_millis)) = self.last {
+            if day == last_day && millis > last_millis {
+                self.elapsed += Duration::from_millis((millis - last_millis) as u64);
+            }
+        }
+        self.last = Some((day, millis));
+
+        if self.interval == Duration::from_millis(0) || self.next_tick > self.elapsed {
+            return None;
+        }
+        while self.next_tick <= self.elapsed {
+            self.next_tick += self.interval;
+        }
+        Some(self.now())
+    }
+
+    pub fn now(&self) -> Instant {
+        self.origin + self.elapsed
+    }
+}
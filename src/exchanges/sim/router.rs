@@ -0,0 +1,105 @@
+use super::matching::Reports;
+use crate::bars::Bar;
+use crate::exchanges::xtp::{QuoteEvent, StrategyId, SubscriptionKind};
+use crate::market::Venue;
+use crate::Event;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+type Topic = (StrategyId, SubscriptionKind, Venue, Option<String>);
+
+/// Delivers quotes to the strategies subscribed to the ticker and order
+/// reports to the strategy that placed the order, like the XTP router does.
+#[derive(Default)]
+pub(crate) struct SimRouter {
+    strategies: Mutex<Vec<broadcast::Sender<Event>>>,
+    // A `None` ticker subscribes to the whole exchange.
+    subscriptions: Mutex<HashSet<Topic>>,
+}
+
+impl SimRouter {
+    pub fn add_strategy(&self, capacity: usize) -> (StrategyId, broadcast::Receiver<Event>) {
+        let (tx, rx) = broadcast::channel(capacity);
+        let mut strategies = self.strategies.lock().unwrap();
+        strategies.push(tx);
+        (strategies.len() - 1, rx)
+    }
+
+    pub fn subscribe(
+        &self,
+        id: StrategyId,
+        kind: SubscriptionKind,
+        venue: Venue,
+        ticker: Option<&str>,
+    ) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert((id, kind, venue, ticker.map(str::to_string)));
+    }
+
+    pub fn unsubscribe(
+        &self,
+        id: StrategyId,
+        kind: SubscriptionKind,
+        venue: Venue,
+        ticker: Option<&str>,
+    ) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .remove(&(id, kind, venue, ticker.map(str::to_string)));
+    }
+
+    /// Send market data to the strategies subscribed to its ticker.
+    pub fn route_market(&self, kind: SubscriptionKind, venue: Venue, ticker: &str, event: &Event) {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        let strategies = self.strategies.lock().unwrap();
+        for (id, tx) in strategies.iter().enumerate() {
+            let subscribed = subscriptions.contains(&(id, kind, venue, None))
+                || subscriptions.contains(&(id, kind, venue, Some(ticker.to_string())));
+            if subscribed {
+                let _ = tx.send(event.clone());
+            }
+        }
+    }
+
+    pub fn deliver(&self, reports: Reports) {
+        let strategies = self.strategies.lock().unwrap();
+        for (id, report) in reports {
+            if let Event::Position(_) = report {
+                for tx in strategies.iter() {
+                    let _ = tx.send(report.clone());
+                }
+            } else if let Some(tx) = strategies.get(id) {
+                let _ = tx.send(report);
+            }
+        }
+    }
+
+    pub fn deliver_bars(&self, bars: Vec<(Bar, Vec<StrategyId>)>) {
+        let strategies = self.strategies.lock().unwrap();
+        for (bar, ids) in bars {
+            for id in ids {
+                if let Some(tx) = strategies.get(id) {
+                    let _ = tx.send(Event::MarketData(QuoteEvent::Bar(bar.clone())));
+                }
+            }
+        }
+    }
+
+    /// Send to every strategy, false once the router is closed.
+    pub fn broadcast(&self, event: Event) -> bool {
+        let strategies = self.strategies.lock().unwrap();
+        for tx in strategies.iter() {
+            let _ = tx.send(event.clone());
+        }
+        !strategies.is_empty()
+    }
+
+    /// Drop the senders, so the strategies see their channel closed.
+    pub fn close(&self) {
+        self.strategies.lock().unwrap().clear();
+    }
+}
@@ -1,59 +1,616 @@
+mod config;
+mod convert;
+mod correlation;
+mod event;
+mod kill_switch;
+mod order;
+mod order_manager;
+mod positions;
+mod queue;
 mod quote_event;
 mod quotespi;
+mod reconnect;
+mod router;
+mod snapshot;
+mod subscriptions;
 mod trader_event;
 mod traderspi;
 
-use self::quote_event::QuoteEvent;
+pub(crate) use self::config::seconds;
+pub use self::config::{
+    ApiLogLevel, QuoteProtocol, QuoteServerConfig, TraderServerConfig, XTPConfig,
+    XTPExchangeBuilder,
+};
+pub(crate) use self::convert::{
+    entrust_side, entrust_side_code, entrust_type, entrust_type_code, trade_flag, trade_flag_code,
+};
+use self::correlation::{QuoteQueries, RequestTimeout, TraderQueries, QUOTE_REQUEST};
+pub(crate) use self::event::XTPEvent;
+pub use self::event::{ConnectionState, Session};
+pub use self::kill_switch::KillSwitchConfig;
+use self::kill_switch::{flatten_orders, KillSwitch};
+use self::order::OrderIds;
+pub use self::order::{ClientOrderId, OrderRequest, PriceType};
+use self::order_manager::OrderManager;
+pub use self::order_manager::{OrderSnapshot, OrderState};
+pub(crate) use self::positions::trading_day;
+use self::positions::PortfolioTracker;
+pub use self::queue::{BackpressurePolicy, ChannelConfig, DroppedEvents, QueueConfig};
+use self::queue::{EventQueue, QueueReceiver};
+pub use self::quote_event::QuoteEvent;
 use self::quotespi::QSpi;
+pub use self::reconnect::ReconnectPolicy;
+use self::reconnect::Reconnector;
+use self::router::Router;
+pub use self::snapshot::DeliveryMode;
+use self::snapshot::Snapshots;
+pub(crate) use self::subscriptions::SubscriptionKind;
+use self::subscriptions::Subscriptions;
 use self::trader_event::TraderEvent;
 use self::traderspi::TSpi;
-use crate::{Exchange, Strategy};
+use crate::bars::{BarHub, BarSource};
+use crate::instruments::InstrumentMaster;
+use crate::market::{Instrument, InstrumentInfo, Price, Quote, Venue};
+use crate::portfolio::{Portfolio, Position};
+use crate::risk::{RiskEngine, RiskLimits};
+use crate::{Error, Event, Exchange, ExchangeHandle, Result, Strategy};
 use async_trait::async_trait;
-use failure::Fallible;
-use futures::stream::StreamExt;
+use log::{error, info, warn};
+use std::fs;
 use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::select;
-use tokio::sync::{broadcast, mpsc};
-use xtp::{QuoteApi, TraderApi, XTPExchangeType, XTPLogLevel, XTPProtocolType};
+use tokio::task;
+use tokio::time;
+use xtp::{
+    QuoteApi, TraderApi, XTPProtocolType, XTPQueryAssetRsp, XTPQueryOrderReq, XTPQueryOrderRsp,
+    XTPQueryStkPositionRsp, XTPQueryTradeRsp, XTPQueryTraderReq, XTPRspInfoStruct,
+};
+
+/// Index of a strategy in registration order.
+pub type StrategyId = usize;
+
+// Holds the quote subscriptions bars are built from, on behalf of the
+// strategies subscribed to the bars. No strategy has this id, so the router
+// delivers nothing to it.
+const BAR_SUBSCRIBER: StrategyId = StrategyId::max_value();
+
+// Owns the orders the exchange places itself, such as the kill switch's
+// flatten sells. No strategy has this id either, the router broadcasts the
+// reports of its orders.
+pub(crate) const SYSTEM: StrategyId = StrategyId::max_value() - 1;
+
+// How long handle queries wait for the gateway unless set otherwise.
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct XTPExchange {
-    quote_addr: SocketAddrV4,
-    trader_addr: SocketAddrV4,
-    username: String,
-    password: String,
-    key: String,
+    config: XTPConfig,
     strategies: Vec<Box<dyn Strategy<XTPExchange> + Send + Sync>>,
 
     quote_api: Option<Arc<QuoteApi>>,
     trader_api: Option<Arc<TraderApi>>,
+    session_id: Arc<AtomicU64>,
+    subscriptions: Arc<Subscriptions>,
+    order_ids: Arc<OrderIds>,
+    snapshots: Arc<Snapshots>,
+    bars: Arc<BarHub>,
+    queries: Arc<TraderQueries>,
+    quote_queries: Arc<QuoteQueries>,
+    request_timeout: RequestTimeout,
+    instruments: Arc<InstrumentMaster>,
+    order_manager: Arc<OrderManager>,
+    portfolio: Arc<PortfolioTracker>,
+    risk: Arc<RiskEngine>,
+    kill_switch: Arc<KillSwitch>,
+    kill_switch_config: KillSwitchConfig,
 
-    quote_rx: Option<mpsc::Receiver<QuoteEvent>>,
-    trader_rx: Option<mpsc::Receiver<TraderEvent>>,
-
-    strategy_tx: broadcast::Sender<QuoteEvent>,
+    quote_queue: Option<Arc<EventQueue>>,
+    trader_queue: Option<Arc<EventQueue>>,
+    quote_rx: Option<QueueReceiver>,
+    trader_rx: Option<QueueReceiver>,
+    queue_config: QueueConfig,
+    timer_interval: Duration,
+    reconnect_policy: ReconnectPolicy,
 }
 
 #[derive(Clone)]
 pub struct XTPExchangeHandle {
     quote_api: Arc<QuoteApi>,
     trader_api: Arc<TraderApi>,
+    strategy_id: StrategyId,
+    session_id: Arc<AtomicU64>,
+    subscriptions: Arc<Subscriptions>,
+    order_ids: Arc<OrderIds>,
+    snapshots: Arc<Snapshots>,
+    bars: Arc<BarHub>,
+    queries: Arc<TraderQueries>,
+    quote_queries: Arc<QuoteQueries>,
+    instruments: Arc<InstrumentMaster>,
+    order_manager: Arc<OrderManager>,
+    portfolio: Arc<PortfolioTracker>,
+    risk: Arc<RiskEngine>,
+    kill_switch: Arc<KillSwitch>,
+    kill_switch_config: KillSwitchConfig,
+    quote_queue: Arc<EventQueue>,
+    trader_queue: Arc<EventQueue>,
 }
 
 impl XTPExchangeHandle {
-    fn new(quote_api: Arc<QuoteApi>, trader_api: Arc<TraderApi>) -> Self {
+    fn new(
+        quote_api: Arc<QuoteApi>,
+        trader_api: Arc<TraderApi>,
+        session_id: Arc<AtomicU64>,
+        subscriptions: Arc<Subscriptions>,
+        order_ids: Arc<OrderIds>,
+        snapshots: Arc<Snapshots>,
+        bars: Arc<BarHub>,
+        queries: Arc<TraderQueries>,
+        quote_queries: Arc<QuoteQueries>,
+        instruments: Arc<InstrumentMaster>,
+        order_manager: Arc<OrderManager>,
+        portfolio: Arc<PortfolioTracker>,
+        risk: Arc<RiskEngine>,
+        kill_switch: Arc<KillSwitch>,
+        kill_switch_config: KillSwitchConfig,
+        quote_queue: Arc<EventQueue>,
+        trader_queue: Arc<EventQueue>,
+    ) -> Self {
         Self {
             quote_api,
             trader_api,
+            strategy_id: SYSTEM,
+            session_id,
+            subscriptions,
+            order_ids,
+            snapshots,
+            bars,
+            queries,
+            quote_queries,
+            instruments,
+            order_manager,
+            portfolio,
+            risk,
+            kill_switch,
+            kill_switch_config,
+            quote_queue,
+            trader_queue,
         }
     }
 
-    pub fn subscribe_market_data(
+    fn for_strategy(&self, strategy_id: StrategyId) -> Self {
+        Self {
+            strategy_id,
+            ..self.clone()
+        }
+    }
+
+    fn subscribe(&self, kind: SubscriptionKind, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscriptions.subscribe(
+            &*self.quote_api,
+            self.strategy_id,
+            kind,
+            tickers,
+            venue.into(),
+        )
+    }
+
+    fn unsubscribe(&self, kind: SubscriptionKind, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscriptions.unsubscribe(
+            &*self.quote_api,
+            self.strategy_id,
+            kind,
+            tickers,
+            venue.into(),
+        )
+    }
+
+    pub fn set_delivery_mode(&self, mode: DeliveryMode) {
+        self.snapshots.set_delivery_mode(self.strategy_id, mode)
+    }
+
+    /// Latest snapshots of the tickers updated since the last call, for
+    /// strategies in `DeliveryMode::Conflated`.
+    pub fn updated_snapshots(&self) -> Vec<Quote> {
+        self.snapshots.take_updated(self.strategy_id)
+    }
+
+    /// Events dropped so far by the backpressure policies of the channels
+    /// from the XTP callbacks.
+    pub fn dropped_events(&self) -> DroppedEvents {
+        DroppedEvents {
+            quote: self.quote_queue.dropped(),
+            trader: self.trader_queue.dropped(),
+        }
+    }
+
+    fn session_id(&self) -> u64 {
+        self.session_id.load(Ordering::SeqCst)
+    }
+
+    fn send_order(&self, owner: StrategyId, req: &OrderRequest) -> Result<ClientOrderId> {
+        let client_id = self.order_ids.allocate(owner);
+        let xtp_id = self
+            .trader_api
+            .insert_order(&req.to_insert_info(client_id), self.session_id());
+        if xtp_id == 0 {
+            return Err(Error::Api(format!("Insert order failed: {:?}", req)));
+        }
+        self.order_ids.set_xtp_id(client_id, xtp_id);
+        self.order_manager.on_submit(client_id, xtp_id, req);
+        Ok(client_id)
+    }
+
+    /// Overwrite the tracked positions with the broker's. Done once at
+    /// startup, call again whenever the two may have drifted apart.
+    pub async fn reconcile_positions(&self) -> Result<()> {
+        let positions = self.query_positions(None).await?;
+        self.portfolio.reconcile(&positions);
+        Ok(())
+    }
+
+    /// Block new orders from every strategy, cancel all open orders and,
+    /// if configured, flatten every position once the cancels are answered.
+    /// Errors from the individual cancels and sells are logged, the first
+    /// one is returned.
+    pub fn engage_kill_switch(&self, reason: &str) -> Result<()> {
+        if !self.kill_switch.engage(reason) {
+            return Ok(());
+        }
+        error!("Kill switch engaged: {}", reason);
+
+        let mut result = Ok(());
+        let mut cancelled = vec![];
+        for order in self.order_manager.open() {
+            if self
+                .trader_api
+                .cancel_order(order.xtp_id, self.session_id())
+                != 0
+            {
+                cancelled.push(order.xtp_id);
+            } else {
+                let e = Error::Api(format!("Cancel order {} failed", order.xtp_id));
+                error!("Kill switch: {}", e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        if self.kill_switch_config.flatten {
+            self.kill_switch.flatten_after(cancelled);
+            let flattened = self.flatten_if_due();
+            if result.is_ok() {
+                result = flattened;
+            }
+        }
+        result
+    }
+
+    // Sell what the kill switch may sell once the orders it cancelled are
+    // done or their cancels rejected. Called on engaging and after every
+    // trader report.
+    fn flatten_if_due(&self) -> Result<()> {
+        let orders = &self.order_manager;
+        let due = self.kill_switch.flatten_due(|xtp_id| {
+            orders
+                .by_xtp_id(xtp_id)
+                .map_or(false, |order| !order.state.is_terminal())
+        });
+        if !due {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for req in flatten_orders(&self.portfolio.snapshot(), &orders.open()) {
+            if let Err(e) = self.send_order(SYSTEM, &req) {
+                error!("Kill switch: {}", e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Allow orders again. Nothing cancelled or flattened is restored.
+    pub fn release_kill_switch(&self) {
+        self.kill_switch.release();
+    }
+
+    /// Why the kill switch was engaged, `None` while it is released.
+    pub fn kill_switch_engaged(&self) -> Option<String> {
+        self.kill_switch.engaged()
+    }
+
+    fn check_risk(&self, req: &OrderRequest) -> Result<()> {
+        let snapshot = self.snapshots.get(&req.ticker, req.venue);
+        let position = self.portfolio.position(req.venue, &req.ticker);
+        let instrument = self.instrument(&req.ticker, req.venue);
+        let check = req.order_check(
+            position.as_ref(),
+            &self.order_manager.open(),
+            snapshot.as_ref().map(|s| s.last_price.to_f64()),
+            snapshot
+                .as_ref()
+                .map(|s| (s.lower_limit_price.to_f64(), s.upper_limit_price.to_f64())),
+            instrument.as_ref(),
+        );
+        self.risk.check(&check).map_err(Error::Risk)
+    }
+
+    /// Positions of `ticker`, or of every ticker when `None`.
+    pub async fn query_positions(
+        &self,
+        ticker: Option<&str>,
+    ) -> Result<Vec<XTPQueryStkPositionRsp>> {
+        let request_id = self.queries.next_request_id();
+        let ticker = ticker.unwrap_or("");
+        self.queries
+            .positions
+            .request(request_id, || {
+                self.trader_api
+                    .query_position(ticker, self.session_id(), request_id)
+            })
+            .await
+    }
+
+    pub async fn query_asset(&self) -> Result<XTPQueryAssetRsp> {
+        let request_id = self.queries.next_request_id();
+        self.queries
+            .assets
+            .request(request_id, || {
+                self.trader_api.query_asset(self.session_id(), request_id)
+            })
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Api("Empty asset response".to_string()))
+    }
+
+    /// Today's orders of `ticker`, or of every ticker when `None`.
+    pub async fn query_orders(&self, ticker: Option<&str>) -> Result<Vec<XTPQueryOrderRsp>> {
+        let request_id = self.queries.next_request_id();
+        let req = XTPQueryOrderReq {
+            ticker: ticker.unwrap_or("").to_string(),
+            begin_time: 0,
+            end_time: 0,
+        };
+        self.queries
+            .orders
+            .request(request_id, || {
+                self.trader_api
+                    .query_orders(&req, self.session_id(), request_id)
+            })
+            .await
+    }
+
+    /// Today's trades of `ticker`, or of every ticker when `None`.
+    pub async fn query_trades(&self, ticker: Option<&str>) -> Result<Vec<XTPQueryTradeRsp>> {
+        let request_id = self.queries.next_request_id();
+        let req = XTPQueryTraderReq {
+            ticker: ticker.unwrap_or("").to_string(),
+            begin_time: 0,
+            end_time: 0,
+        };
+        self.queries
+            .trades
+            .request(request_id, || {
+                self.trader_api
+                    .query_trades(&req, self.session_id(), request_id)
+            })
+            .await
+    }
+
+    /// Static data of every instrument of `venue` for the trading day.
+    pub async fn query_all_tickers(&self, venue: Venue) -> Result<Vec<InstrumentInfo>> {
+        let _running = self.quote_queries.tickers_running.lock().await;
+        let infos = self
+            .quote_queries
+            .tickers
+            .request(QUOTE_REQUEST, || {
+                self.quote_api.query_all_tickers(venue.into())
+            })
+            .await?;
+        Ok(infos.iter().map(convert::instrument_info).collect())
+    }
+
+    /// Latest prices of `tickers`, which need not be subscribed.
+    pub async fn query_tickers_price_info(
         &self,
         tickers: &[&str],
-        exchange_id: XTPExchangeType,
-    ) -> Fallible<()> {
-        self.quote_api.subscribe_market_data(tickers, exchange_id)
+        venue: Venue,
+    ) -> Result<Vec<(Instrument, Price)>> {
+        let _running = self.quote_queries.prices_running.lock().await;
+        let infos = self
+            .quote_queries
+            .prices
+            .request(QUOTE_REQUEST, || {
+                self.quote_api
+                    .query_tickers_price_info(tickers, venue.into())
+            })
+            .await?;
+        Ok(infos.iter().map(convert::last_price).collect())
+    }
+
+    pub fn instruments(&self) -> Arc<InstrumentMaster> {
+        self.instruments.clone()
+    }
+
+    /// (Re)load the instrument master from the quote server, returning how
+    /// many instruments it holds. Done by `run` before strategies start, and
+    /// again on the first quote of each trading day.
+    pub async fn load_instruments(&self) -> Result<usize> {
+        for &venue in &[Venue::SH, Venue::SZ] {
+            let infos = self.query_all_tickers(venue).await?;
+            self.instruments.replace(venue, infos);
+        }
+        Ok(self.instruments.len())
+    }
+
+    pub async fn query_order_by_id(&self, client_id: ClientOrderId) -> Result<XTPQueryOrderRsp> {
+        let xtp_id = self
+            .order_ids
+            .xtp_id(client_id)
+            .ok_or_else(|| Error::Api(format!("Unknown client order id {}", client_id)))?;
+        let request_id = self.queries.next_request_id();
+        self.queries
+            .orders
+            .request(request_id, || {
+                self.trader_api
+                    .query_order_by_xtp_id(xtp_id, self.session_id(), request_id)
+            })
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Api(format!("Order {} not found", client_id)))
+    }
+}
+
+impl ExchangeHandle for XTPExchangeHandle {
+    fn subscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::MarketData, tickers, venue)
+    }
+
+    fn unsubscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::MarketData, tickers, venue)
+    }
+
+    fn subscribe_all_market_data(&self, venue: Venue) -> Result<()> {
+        self.subscriptions.subscribe_all(
+            &*self.quote_api,
+            self.strategy_id,
+            SubscriptionKind::MarketData,
+            venue.into(),
+        )
+    }
+
+    fn unsubscribe_all_market_data(&self, venue: Venue) -> Result<()> {
+        self.subscriptions.unsubscribe_all(
+            &*self.quote_api,
+            self.strategy_id,
+            SubscriptionKind::MarketData,
+            venue.into(),
+        )
+    }
+
+    fn subscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::TickByTick, tickers, venue)
+    }
+
+    fn unsubscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::TickByTick, tickers, venue)
+    }
+
+    fn subscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.subscribe(SubscriptionKind::OrderBook, tickers, venue)
+    }
+
+    fn unsubscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()> {
+        self.unsubscribe(SubscriptionKind::OrderBook, tickers, venue)
+    }
+
+    /// Receive `QuoteEvent::Bar` every `interval` for `tickers`, built from
+    /// `source`. The quotes the bars are built from are subscribed to as
+    /// needed, but only delivered when subscribed to separately.
+    fn subscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()> {
+        for ticker in tickers {
+            let first = self
+                .bars
+                .subscribe(self.strategy_id, source, interval, venue, ticker)?;
+            if first {
+                self.subscriptions.subscribe(
+                    &*self.quote_api,
+                    BAR_SUBSCRIBER,
+                    bar_subscription(source),
+                    &[*ticker],
+                    venue.into(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unsubscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()> {
+        for ticker in tickers {
+            let last = self
+                .bars
+                .unsubscribe(self.strategy_id, source, interval, venue, ticker);
+            if last {
+                self.subscriptions.unsubscribe(
+                    &*self.quote_api,
+                    BAR_SUBSCRIBER,
+                    bar_subscription(source),
+                    &[*ticker],
+                    venue.into(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The most recent depth snapshot of a subscribed ticker.
+    fn last_snapshot(&self, ticker: &str, venue: Venue) -> Option<Quote> {
+        self.snapshots.get(ticker, venue)
+    }
+
+    /// Known once the instrument master is loaded.
+    fn instrument(&self, ticker: &str, venue: Venue) -> Option<InstrumentInfo> {
+        self.instruments.get(venue, ticker)
+    }
+
+    fn insert_order(&self, req: &OrderRequest) -> Result<ClientOrderId> {
+        if let Some(reason) = self.kill_switch.engaged() {
+            return Err(Error::KillSwitch(reason));
+        }
+        self.check_risk(req)?;
+        let client_id = self.send_order(self.strategy_id, req)?;
+        self.risk.order_sent();
+        Ok(client_id)
+    }
+
+    fn cancel_order(&self, client_id: ClientOrderId) -> Result<()> {
+        let xtp_id = match self.order_ids.xtp_id(client_id) {
+            Some(xtp_id) => xtp_id,
+            None => return Err(Error::Api(format!("Unknown client order id {}", client_id))),
+        };
+        if self.trader_api.cancel_order(xtp_id, self.session_id()) == 0 {
+            return Err(Error::Api(format!("Cancel order {} failed", client_id)));
+        }
+        Ok(())
+    }
+
+    fn order(&self, client_id: ClientOrderId) -> Option<OrderSnapshot> {
+        self.order_manager.get(client_id)
+    }
+
+    /// Every order seen on the trader session today.
+    fn orders(&self) -> Vec<OrderSnapshot> {
+        self.order_manager.all()
+    }
+
+    fn open_orders(&self) -> Vec<OrderSnapshot> {
+        self.order_manager.open()
+    }
+
+    fn portfolio(&self) -> Portfolio {
+        self.portfolio.snapshot()
+    }
+
+    fn position(&self, ticker: &str, venue: Venue) -> Option<Position> {
+        self.portfolio.position(venue, ticker)
     }
 }
 
@@ -65,88 +622,354 @@ impl XTPExchange {
         password: &str,
         key: &str,
     ) -> XTPExchange {
-        let (tx, _) = broadcast::channel(10);
-
-        XTPExchange {
+        XTPExchange::with_config(XTPConfig::new(
             quote_addr,
             trader_addr,
-            username: username.to_string(),
-            password: password.to_string(),
-            key: key.to_string(),
+            username,
+            password,
+            key,
+        ))
+    }
+
+    /// For settings `new` leaves at their defaults, such as the client ids,
+    /// the quote protocol or separate quote and trader accounts.
+    pub fn builder(quote_addr: SocketAddrV4, trader_addr: SocketAddrV4) -> XTPExchangeBuilder {
+        XTPExchangeBuilder::new(quote_addr, trader_addr)
+    }
+
+    pub fn with_config(config: XTPConfig) -> XTPExchange {
+        let request_timeout = RequestTimeout::new(DEFAULT_REQUEST_TIMEOUT);
+        XTPExchange {
+            config,
             strategies: vec![],
             quote_api: None,
             trader_api: None,
+            session_id: Arc::new(AtomicU64::new(0)),
+            subscriptions: Arc::new(Subscriptions::default()),
+            order_ids: Arc::new(OrderIds::default()),
+            snapshots: Arc::new(Snapshots::default()),
+            bars: Arc::new(BarHub::default()),
+            queries: Arc::new(TraderQueries::new(&request_timeout)),
+            quote_queries: Arc::new(QuoteQueries::new(&request_timeout)),
+            request_timeout,
+            instruments: Arc::new(InstrumentMaster::default()),
+            order_manager: Arc::new(OrderManager::default()),
+            portfolio: Arc::new(PortfolioTracker::default()),
+            risk: Arc::new(RiskEngine::default()),
+            kill_switch: Arc::new(KillSwitch::default()),
+            kill_switch_config: KillSwitchConfig::default(),
+            quote_queue: None,
+            trader_queue: None,
             quote_rx: None,
             trader_rx: None,
-            strategy_tx: tx,
+            queue_config: QueueConfig::default(),
+            timer_interval: Duration::from_secs(1),
+            reconnect_policy: ReconnectPolicy::default(),
         }
     }
 
-    fn sys_init(&mut self) {
-        let mut qapi = QuoteApi::new(1, "/tmp/xtp", XTPLogLevel::Trace);
-        let (tx, rx) = mpsc::channel(10);
-        qapi.register_spi(QSpi::new(tx));
-        qapi.set_heart_beat_interval(10);
-        qapi.set_udp_buffer_size(1024);
-        qapi.login(
-            self.quote_addr,
-            &self.username,
-            &self.password,
-            XTPProtocolType::TCP,
-        )
-        .unwrap();
+    /// How often strategies receive `Event::Timer`, defaults to one second.
+    pub fn set_timer_interval(&mut self, interval: Duration) {
+        self.timer_interval = interval;
+    }
+
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+    }
+
+    /// How long handle queries wait for the gateway before failing with
+    /// `Error::Timeout`, defaults to ten seconds. Applies to the queries sent
+    /// afterwards, also once running.
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout.set(timeout);
+    }
+
+    /// Pre-trade limits applied to every order sent through the handles.
+    pub fn set_risk_limits(&mut self, limits: RiskLimits) {
+        self.risk = Arc::new(RiskEngine::new(limits));
+    }
+
+    pub fn set_kill_switch_config(&mut self, config: KillSwitchConfig) {
+        self.kill_switch_config = config;
+    }
+
+    /// Capacities and backpressure policies of the event channels, takes
+    /// effect on the next `connect`.
+    pub fn set_queue_config(&mut self, config: QueueConfig) {
+        self.queue_config = config;
+    }
+
+    async fn sys_init(&mut self) -> Result<()> {
+        self.queue_config.validate()?;
+        let (quote_queue, quote_rx) = EventQueue::new(self.queue_config.quote.clone())?;
+        let (trader_queue, trader_rx) = EventQueue::new(self.queue_config.trader.clone())?;
+
+        let config = self.config.clone();
+        let qspi = QSpi::new(
+            quote_queue.clone(),
+            self.snapshots.clone(),
+            self.quote_queries.clone(),
+        );
+        let tspi = TSpi::new(trader_queue.clone(), self.queries.clone());
+        // Logging in blocks until the gateway answers.
+        let (qapi, tapi, session_id) = task::spawn_blocking(move || init_apis(&config, qspi, tspi))
+            .await
+            .unwrap_or_else(|e| Err(Error::ApiInit(e.to_string())))?;
 
+        self.session_id.store(session_id, Ordering::SeqCst);
         self.quote_api = Some(Arc::new(qapi));
-        self.quote_rx = Some(rx);
-
-        let mut tapi = TraderApi::new(1, "/tmp/xtp", XTPLogLevel::Trace);
-        let (tx, rx) = mpsc::channel(10);
-        tapi.register_spi(TSpi::new(tx));
-        tapi.set_heart_beat_interval(10);
-        tapi.set_software_key(&self.key).unwrap(); // MUST SET KEY FIRST! BEFORE LOGIN
-        tapi.login(
-            self.trader_addr,
-            &self.username,
-            &self.password,
+        self.trader_api = Some(Arc::new(tapi));
+        self.quote_queue = Some(quote_queue);
+        self.trader_queue = Some(trader_queue);
+        self.quote_rx = Some(quote_rx);
+        self.trader_rx = Some(trader_rx);
+        Ok(())
+    }
+
+    fn handle(&self) -> Result<XTPExchangeHandle> {
+        match (
+            &self.quote_api,
+            &self.trader_api,
+            &self.quote_queue,
+            &self.trader_queue,
+        ) {
+            (Some(qapi), Some(tapi), Some(qqueue), Some(tqueue)) => Ok(XTPExchangeHandle::new(
+                qapi.clone(),
+                tapi.clone(),
+                self.session_id.clone(),
+                self.subscriptions.clone(),
+                self.order_ids.clone(),
+                self.snapshots.clone(),
+                self.bars.clone(),
+                self.queries.clone(),
+                self.quote_queries.clone(),
+                self.instruments.clone(),
+                self.order_manager.clone(),
+                self.portfolio.clone(),
+                self.risk.clone(),
+                self.kill_switch.clone(),
+                self.kill_switch_config.clone(),
+                qqueue.clone(),
+                tqueue.clone(),
+            )),
+            _ => Err(Error::NotConnected),
+        }
+    }
+
+    fn reconnector(&self) -> Result<Reconnector> {
+        match (
+            &self.quote_api,
+            &self.trader_api,
+            &self.quote_queue,
+            &self.trader_queue,
+        ) {
+            (Some(qapi), Some(tapi), Some(qqueue), Some(tqueue)) => Ok(Reconnector {
+                policy: self.reconnect_policy.clone(),
+                quote: self.config.quote.clone(),
+                trader: self.config.trader.clone(),
+                quote_api: qapi.clone(),
+                trader_api: tapi.clone(),
+                session_id: self.session_id.clone(),
+                subscriptions: self.subscriptions.clone(),
+                quote_queue: qqueue.clone(),
+                trader_queue: tqueue.clone(),
+                quote_reconnecting: Arc::new(AtomicBool::new(false)),
+                trader_reconnecting: Arc::new(AtomicBool::new(false)),
+            }),
+            _ => Err(Error::NotConnected),
+        }
+    }
+}
+
+// Create both APIs and log them in, returning the trader session id.
+fn init_apis(config: &XTPConfig, qspi: QSpi, tspi: TSpi) -> Result<(QuoteApi, TraderApi, u64)> {
+    fs::create_dir_all(&config.log_path).map_err(|e| Error::ApiInit(e.to_string()))?;
+
+    let mut qapi = QuoteApi::new(
+        config.quote.client_id,
+        &config.log_path,
+        config.log_level.into(),
+    );
+    qapi.register_spi(qspi);
+    qapi.set_heart_beat_interval(config.quote.heartbeat_interval);
+    qapi.set_udp_buffer_size(config.quote.udp_buffer_size);
+    qapi.login(
+        config.quote.addr,
+        &config.quote.username,
+        &config.quote.password,
+        config.quote.protocol.into(),
+    )
+    .map_err(|_| login_error(qapi.get_api_last_error()))?;
+
+    let mut tapi = TraderApi::new(
+        config.trader.client_id,
+        &config.log_path,
+        config.log_level.into(),
+    );
+    tapi.register_spi(tspi);
+    tapi.set_heart_beat_interval(config.trader.heartbeat_interval);
+    // MUST SET KEY FIRST! BEFORE LOGIN
+    tapi.set_software_key(&config.trader.key)
+        .map_err(|e| Error::InvalidKey(e.to_string()))?;
+    let session_id = tapi
+        .login(
+            config.trader.addr,
+            &config.trader.username,
+            &config.trader.password,
             XTPProtocolType::TCP,
         )
-        .unwrap();
+        .map_err(|_| login_error(tapi.get_api_last_error()))?;
+    Ok((qapi, tapi, session_id))
+}
 
-        self.trader_api = Some(Arc::new(tapi));
-        self.trader_rx = Some(rx);
+fn bar_subscription(source: BarSource) -> SubscriptionKind {
+    match source {
+        BarSource::Depth => SubscriptionKind::MarketData,
+        BarSource::TickByTick => SubscriptionKind::TickByTick,
     }
+}
 
-    fn handle(&self) -> XTPExchangeHandle {
-        XTPExchangeHandle::new(
-            self.quote_api.clone().unwrap(),
-            self.trader_api.clone().unwrap(),
-        )
+// Ids of the XTP error code table for logins refused because of the
+// account, as opposed to the gateway being unreachable.
+const AUTH_ERRORS: &[i32] = &[
+    10200001, // Unknown user.
+    10200002, // Wrong password.
+    10200003, // Account locked.
+    10200004, // Account not allowed on this server.
+    10200005, // Too many sessions of the account.
+];
+
+// What the API reports through its last error after a failed login.
+fn login_error(info: XTPRspInfoStruct) -> Error {
+    let msg = format!("{} (XTP error {})", info.error_msg, info.error_id);
+    if AUTH_ERRORS.contains(&info.error_id) {
+        Error::Auth(msg)
+    } else {
+        Error::Network(msg)
     }
 }
 
+/// Engage the kill switch of `h` on every SIGUSR1.
+#[cfg(unix)]
+fn engage_on_signal(h: XTPExchangeHandle) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals =
+        signal(SignalKind::user_defined1()).map_err(|e| Error::ApiInit(e.to_string()))?;
+    tokio::spawn(async move {
+        while let Some(()) = signals.recv().await {
+            let _ = h.engage_kill_switch("SIGUSR1");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn engage_on_signal(_: XTPExchangeHandle) -> Result<()> {
+    Err(Error::Config(
+        "kill_switch.on_signal needs Unix signals".to_string(),
+    ))
+}
+
 #[async_trait]
 impl Exchange for XTPExchange {
-    type Event = QuoteEvent;
+    type Event = Event;
     type Handle = XTPExchangeHandle;
 
-    async fn run(mut self) {
-        self.sys_init();
-        let h = self.handle();
+    async fn connect(&mut self) -> Result<()> {
+        self.sys_init().await
+    }
+
+    async fn run(mut self) -> Result<()> {
+        if self.quote_rx.is_none() || self.trader_rx.is_none() {
+            self.connect().await?;
+        }
+        let h = self.handle()?;
+        let reconnector = self.reconnector()?;
+        let mut quote_rx = self.quote_rx.take().ok_or(Error::NotConnected)?;
+        let mut trader_rx = self.trader_rx.take().ok_or(Error::NotConnected)?;
+
+        if let Err(e) = h.reconcile_positions().await {
+            warn!("Initial position reconcile failed: {}", e);
+        }
+        match h.load_instruments().await {
+            Ok(count) => info!("Loaded {} instruments", count),
+            Err(e) => warn!("Loading the instrument master failed: {}", e),
+        }
+
+        if self.kill_switch_config.on_signal {
+            engage_on_signal(h.clone())?;
+        }
 
+        let mut router = Router::new(
+            self.subscriptions.clone(),
+            self.order_ids.clone(),
+            self.order_manager.clone(),
+            self.snapshots.clone(),
+            self.bars.clone(),
+        );
         for s in self.strategies {
-            tokio::spawn(s.run(self.strategy_tx.subscribe(), h.clone()));
+            let (id, rx) = router.add_strategy(self.queue_config.strategy_capacity);
+            tokio::spawn(s.run(rx, h.for_strategy(id)));
+        }
+
+        let mut timer = time::interval(self.timer_interval);
+
+        for &session in &[Session::Quote, Session::Trader] {
+            router.route(XTPEvent::Connection(ConnectionState::Connected(session)));
         }
 
-        let mut qrx = self.quote_rx.unwrap();
-        let mut trx = self.trader_rx.unwrap();
-        let stx = self.strategy_tx;
+        let order_manager = self.order_manager.clone();
+        let portfolio = self.portfolio.clone();
+        let risk = self.risk.clone();
+        let kill_switch = self.kill_switch.clone();
+        // Trading day of the latest quote. The master loaded above may be the
+        // previous day's when started outside trading hours, so the first
+        // quote reloads it as well.
+        let mut quote_day = None;
+        let mut dispatch = |msg: XTPEvent| {
+            if let XTPEvent::MarketData(quote) = &msg {
+                let day = quote.data_time().map(trading_day);
+                if day.is_some() && day != quote_day {
+                    quote_day = day;
+                    let h = h.clone();
+                    tokio::spawn(async move {
+                        match h.load_instruments().await {
+                            Ok(count) => info!("Reloaded {} instruments", count),
+                            Err(e) => warn!("Reloading the instrument master failed: {}", e),
+                        }
+                    });
+                }
+            }
+            order_manager.apply(&msg);
+            let position = portfolio.apply(&msg);
+            if let XTPEvent::Trader(trader) = &msg {
+                if let TraderEvent::CancelOrderError { cancel_info, .. } = trader {
+                    kill_switch.cancel_rejected(cancel_info.order_xtp_id);
+                }
+                let _ = h.flatten_if_due();
+            }
+            let check_loss = match msg {
+                XTPEvent::Trader(TraderEvent::TradeEvent { .. }) | XTPEvent::Timer(_) => true,
+                _ => false,
+            };
+            if check_loss && risk.loss_breached(portfolio.total_pnl()) {
+                let _ = h.engage_kill_switch("Loss limit breached");
+            }
+            if let XTPEvent::Connection(ConnectionState::Disconnected { session, .. }) = msg {
+                reconnector.spawn(session);
+            }
+            router.route(msg);
+            if let Some(position) = position {
+                router.broadcast(Event::Position(position));
+            }
+        };
 
         loop {
             select! {
-                Some(msg) = qrx.next() => {
-                    stx.send(msg);
-                }
-                _ = trx.next() => {}
+                Some(msg) = quote_rx.recv() => dispatch(msg),
+                Some(msg) = trader_rx.recv() => dispatch(msg),
+                now = timer.tick() => dispatch(XTPEvent::Timer(now)),
             }
         }
     }
@@ -158,3 +981,27 @@ impl Exchange for XTPExchange {
         self.strategies.push(Box::new(s))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsp(error_id: i32) -> XTPRspInfoStruct {
+        XTPRspInfoStruct {
+            error_id,
+            error_msg: "refused".to_string(),
+        }
+    }
+
+    #[test]
+    fn account_errors_are_auth_failures() {
+        match login_error(rsp(10200002)) {
+            Error::Auth(msg) => assert_eq!(msg, "refused (XTP error 10200002)"),
+            other => panic!("{:?}", other),
+        }
+        match login_error(rsp(10100001)) {
+            Error::Network(_) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
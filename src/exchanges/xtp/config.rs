@@ -0,0 +1,327 @@
+use super::XTPExchange;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::net::SocketAddrV4;
+use std::time::Duration;
+use xtp::{XTPLogLevel, XTPProtocolType};
+
+/// Verbosity of the logs the XTP API writes under `XTPConfig::log_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiLogLevel {
+    Fatal,
+    Error,
+    Warning,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for ApiLogLevel {
+    fn default() -> Self {
+        ApiLogLevel::Trace
+    }
+}
+
+impl From<ApiLogLevel> for XTPLogLevel {
+    fn from(level: ApiLogLevel) -> Self {
+        match level {
+            ApiLogLevel::Fatal => XTPLogLevel::Fatal,
+            ApiLogLevel::Error => XTPLogLevel::Error,
+            ApiLogLevel::Warning => XTPLogLevel::Warning,
+            ApiLogLevel::Info => XTPLogLevel::Info,
+            ApiLogLevel::Debug => XTPLogLevel::Debug,
+            ApiLogLevel::Trace => XTPLogLevel::Trace,
+        }
+    }
+}
+
+/// How quotes are received. The trader session is always TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteProtocol {
+    Tcp,
+    Udp,
+}
+
+impl Default for QuoteProtocol {
+    fn default() -> Self {
+        QuoteProtocol::Tcp
+    }
+}
+
+impl From<QuoteProtocol> for XTPProtocolType {
+    fn from(protocol: QuoteProtocol) -> Self {
+        match protocol {
+            QuoteProtocol::Tcp => XTPProtocolType::TCP,
+            QuoteProtocol::Udp => XTPProtocolType::UDP,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct QuoteServerConfig {
+    pub addr: SocketAddrV4,
+    pub username: String,
+    pub password: String,
+    /// Must differ between processes logged in with the same account.
+    #[serde(default = "default_client_id")]
+    pub client_id: u8,
+    #[serde(default)]
+    pub protocol: QuoteProtocol,
+    /// Seconds.
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u32,
+    /// Megabytes, only used with `QuoteProtocol::Udp`.
+    #[serde(default = "default_udp_buffer_size")]
+    pub udp_buffer_size: u32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TraderServerConfig {
+    pub addr: SocketAddrV4,
+    pub username: String,
+    pub password: String,
+    /// The software key issued by the broker.
+    pub key: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: u8,
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u32,
+}
+
+// Debug leaves the password and the key out, configs end up in logs.
+const REDACTED: &str = "<redacted>";
+
+impl fmt::Debug for QuoteServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuoteServerConfig")
+            .field("addr", &self.addr)
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .field("client_id", &self.client_id)
+            .field("protocol", &self.protocol)
+            .field("heartbeat_interval", &self.heartbeat_interval)
+            .field("udp_buffer_size", &self.udp_buffer_size)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TraderServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TraderServerConfig")
+            .field("addr", &self.addr)
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .field("key", &REDACTED)
+            .field("client_id", &self.client_id)
+            .field("heartbeat_interval", &self.heartbeat_interval)
+            .finish()
+    }
+}
+
+/// Everything `XTPExchange` needs to connect, e.g.
+///
+/// ```toml
+/// log_path = "/var/log/xtp"
+/// log_level = "info"
+///
+/// [quote]
+/// addr = "10.0.0.1:6002"
+/// username = "..."
+/// password = "..."
+/// protocol = "udp"
+///
+/// [trader]
+/// addr = "10.0.0.2:6001"
+/// username = "..."
+/// password = "..."
+/// key = "..."
+/// client_id = 2
+/// ```
+///
+/// Its `Debug` output leaves out the passwords and the key.
+#[derive(Debug, Clone, Deserialize)]
+pub struct XTPConfig {
+    pub quote: QuoteServerConfig,
+    pub trader: TraderServerConfig,
+    /// Where the XTP API writes its logs, created if missing.
+    #[serde(default = "default_log_path")]
+    pub log_path: String,
+    #[serde(default)]
+    pub log_level: ApiLogLevel,
+}
+
+impl XTPConfig {
+    /// Both sessions log in with `username` and `password`, everything else
+    /// has its default.
+    pub fn new(
+        quote_addr: SocketAddrV4,
+        trader_addr: SocketAddrV4,
+        username: &str,
+        password: &str,
+        key: &str,
+    ) -> Self {
+        XTPConfig {
+            quote: QuoteServerConfig {
+                addr: quote_addr,
+                username: username.to_string(),
+                password: password.to_string(),
+                client_id: default_client_id(),
+                protocol: QuoteProtocol::default(),
+                heartbeat_interval: default_heartbeat_interval(),
+                udp_buffer_size: default_udp_buffer_size(),
+            },
+            trader: TraderServerConfig {
+                addr: trader_addr,
+                username: username.to_string(),
+                password: password.to_string(),
+                key: key.to_string(),
+                client_id: default_client_id(),
+                heartbeat_interval: default_heartbeat_interval(),
+            },
+            log_path: default_log_path(),
+            log_level: ApiLogLevel::default(),
+        }
+    }
+}
+
+fn default_client_id() -> u8 {
+    1
+}
+
+fn default_heartbeat_interval() -> u32 {
+    10
+}
+
+fn default_udp_buffer_size() -> u32 {
+    1024
+}
+
+fn default_log_path() -> String {
+    "/tmp/xtp".to_string()
+}
+
+/// A `Duration` written as a number of seconds, fractions allowed.
+pub(crate) fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    if !seconds.is_finite() || seconds < 0. {
+        return Err(serde::de::Error::custom(format!(
+            "invalid number of seconds {}",
+            seconds
+        )));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Builds an `XTPExchange` from the connection settings, starting from the
+/// defaults of `XTPConfig::new`.
+pub struct XTPExchangeBuilder {
+    config: XTPConfig,
+}
+
+impl XTPExchangeBuilder {
+    pub fn new(quote_addr: SocketAddrV4, trader_addr: SocketAddrV4) -> Self {
+        XTPExchangeBuilder {
+            config: XTPConfig::new(quote_addr, trader_addr, "", "", ""),
+        }
+    }
+
+    /// Log both sessions in with the same account.
+    pub fn credentials(self, username: &str, password: &str) -> Self {
+        self.quote_credentials(username, password)
+            .trader_credentials(username, password)
+    }
+
+    pub fn quote_credentials(mut self, username: &str, password: &str) -> Self {
+        self.config.quote.username = username.to_string();
+        self.config.quote.password = password.to_string();
+        self
+    }
+
+    pub fn trader_credentials(mut self, username: &str, password: &str) -> Self {
+        self.config.trader.username = username.to_string();
+        self.config.trader.password = password.to_string();
+        self
+    }
+
+    pub fn software_key(mut self, key: &str) -> Self {
+        self.config.trader.key = key.to_string();
+        self
+    }
+
+    pub fn quote_client_id(mut self, client_id: u8) -> Self {
+        self.config.quote.client_id = client_id;
+        self
+    }
+
+    pub fn trader_client_id(mut self, client_id: u8) -> Self {
+        self.config.trader.client_id = client_id;
+        self
+    }
+
+    pub fn quote_protocol(mut self, protocol: QuoteProtocol) -> Self {
+        self.config.quote.protocol = protocol;
+        self
+    }
+
+    /// Seconds, for both sessions.
+    pub fn heartbeat_interval(mut self, seconds: u32) -> Self {
+        self.config.quote.heartbeat_interval = seconds;
+        self.config.trader.heartbeat_interval = seconds;
+        self
+    }
+
+    pub fn udp_buffer_size(mut self, megabytes: u32) -> Self {
+        self.config.quote.udp_buffer_size = megabytes;
+        self
+    }
+
+    pub fn log_path(mut self, path: &str) -> Self {
+        self.config.log_path = path.to_string();
+        self
+    }
+
+    pub fn log_level(mut self, level: ApiLogLevel) -> Self {
+        self.config.log_level = level;
+        self
+    }
+
+    pub fn build(self) -> XTPExchange {
+        XTPExchange::with_config(self.config)
+    }
+}
+
+impl From<XTPConfig> for XTPExchangeBuilder {
+    fn from(config: XTPConfig) -> Self {
+        XTPExchangeBuilder { config }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_credentials() {
+        let config: XTPConfig = toml::from_str(
+            r#"
+            [quote]
+            addr = "10.0.0.1:6002"
+            username = "alice"
+            password = "quote-secret"
+
+            [trader]
+            addr = "10.0.0.2:6001"
+            username = "alice"
+            password = "trader-secret"
+            key = "key-secret"
+            "#,
+        )
+        .unwrap();
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("alice"));
+        assert!(debug.contains("10.0.0.2:6001"));
+        assert!(!debug.contains("secret"));
+    }
+}
@@ -0,0 +1,197 @@
+use super::quote_event::QuoteEvent;
+use crate::market::{
+    DepthLevel, Entrust, EntrustType, Instrument, InstrumentInfo, OrderBookL2, Price, Quote,
+    SecurityType, Trade, TradeFlag, Venue,
+};
+use crate::portfolio::Side;
+use xtp::{
+    OrderBookStruct, XTPExchangeType, XTPMarketDataStruct, XTPQuoteStaticInfo, XTPTickByTickData,
+    XTPTickByTickStruct, XTPTickerPriceInfo, XTPTickerType,
+};
+
+impl From<XTPExchangeType> for Venue {
+    fn from(exchange_id: XTPExchangeType) -> Self {
+        match exchange_id {
+            XTPExchangeType::SH => Venue::SH,
+            XTPExchangeType::SZ => Venue::SZ,
+            _ => Venue::Unknown,
+        }
+    }
+}
+
+impl From<Venue> for XTPExchangeType {
+    fn from(venue: Venue) -> Self {
+        match venue {
+            Venue::SH => XTPExchangeType::SH,
+            Venue::SZ => XTPExchangeType::SZ,
+            Venue::Unknown => XTPExchangeType::Unknown,
+        }
+    }
+}
+
+pub(crate) fn quote(
+    md: &XTPMarketDataStruct,
+    bid1_qty: &[i64],
+    max_bid1_count: i32,
+    ask1_qty: &[i64],
+    max_ask1_count: i32,
+) -> Quote {
+    Quote {
+        instrument: Instrument::new(md.exchange_id.into(), &md.ticker),
+        data_time: md.data_time,
+        last_price: Price::from_f64(md.last_price),
+        pre_close_price: Price::from_f64(md.pre_close_price),
+        open_price: Price::from_f64(md.open_price),
+        high_price: Price::from_f64(md.high_price),
+        low_price: Price::from_f64(md.low_price),
+        upper_limit_price: Price::from_f64(md.upper_limit_price),
+        lower_limit_price: Price::from_f64(md.lower_limit_price),
+        volume: md.qty,
+        turnover: md.turnover,
+        bids: levels(&md.bid[..], &md.bid_qty[..]),
+        asks: levels(&md.ask[..], &md.ask_qty[..]),
+        bid1_orders: bid1_qty.to_owned(),
+        bid1_order_count: max_bid1_count,
+        ask1_orders: ask1_qty.to_owned(),
+        ask1_order_count: max_ask1_count,
+    }
+}
+
+/// A `QuoteEvent::Trade` or `QuoteEvent::Entrust`.
+pub(crate) fn tick_by_tick(tbt: &XTPTickByTickStruct) -> QuoteEvent {
+    let instrument = Instrument::new(tbt.exchange_id.into(), &tbt.ticker);
+    match &tbt.data {
+        XTPTickByTickData::Entrust(e) => QuoteEvent::Entrust(Entrust {
+            instrument,
+            data_time: tbt.data_time,
+            channel_no: e.channel_no,
+            seq: e.seq,
+            price: Price::from_f64(e.price),
+            quantity: e.qty,
+            side: entrust_side(e.side as u8),
+            entrust_type: entrust_type(e.ord_type as u8),
+        }),
+        XTPTickByTickData::Trade(t) => QuoteEvent::Trade(Trade {
+            instrument,
+            data_time: tbt.data_time,
+            channel_no: t.channel_no,
+            seq: t.seq,
+            price: Price::from_f64(t.price),
+            quantity: t.qty,
+            amount: t.money,
+            bid_no: t.bid_no,
+            ask_no: t.ask_no,
+            flag: trade_flag(t.trade_flag as u8),
+        }),
+    }
+}
+
+pub(crate) fn order_book(ob: &OrderBookStruct) -> OrderBookL2 {
+    OrderBookL2 {
+        instrument: Instrument::new(ob.exchange_id.into(), &ob.ticker),
+        data_time: ob.data_time,
+        last_price: Price::from_f64(ob.last_price),
+        volume: ob.qty,
+        turnover: ob.turnover,
+        trades_count: ob.trades_count,
+        bids: levels(&ob.bid[..], &ob.bid_qty[..]),
+        asks: levels(&ob.ask[..], &ob.ask_qty[..]),
+    }
+}
+
+pub(crate) fn instrument_info(info: &XTPQuoteStaticInfo) -> InstrumentInfo {
+    InstrumentInfo {
+        instrument: Instrument::new(info.exchange_id.into(), &info.ticker),
+        name: info.ticker_name.clone(),
+        security_type: match info.ticker_type {
+            XTPTickerType::Stock => SecurityType::Stock,
+            XTPTickerType::TechStock => SecurityType::TechStock,
+            XTPTickerType::Index => SecurityType::Index,
+            XTPTickerType::Fund => SecurityType::Fund,
+            XTPTickerType::Bond => SecurityType::Bond,
+            XTPTickerType::Option => SecurityType::Option,
+            _ => SecurityType::Unknown,
+        },
+        pre_close_price: Price::from_f64(info.pre_close_price),
+        upper_limit_price: Price::from_f64(info.upper_limit_price),
+        lower_limit_price: Price::from_f64(info.lower_limit_price),
+        tick_size: Price::from_f64(info.price_tick),
+        buy_lot: i64::from(info.buy_qty_unit),
+        sell_lot: i64::from(info.sell_qty_unit),
+    }
+}
+
+pub(crate) fn last_price(info: &XTPTickerPriceInfo) -> (Instrument, Price) {
+    (
+        Instrument::new(info.exchange_id.into(), &info.ticker),
+        Price::from_f64(info.last_price),
+    )
+}
+
+// XTP pads the depth with zeroed levels.
+fn levels(prices: &[f64], quantities: &[i64]) -> Vec<DepthLevel> {
+    prices
+        .iter()
+        .zip(quantities)
+        .filter(|&(_, &quantity)| quantity > 0)
+        .map(|(&price, &quantity)| DepthLevel {
+            price: Price::from_f64(price),
+            quantity,
+        })
+        .collect()
+}
+
+// The exchanges' own codes, as passed through by XTP. Shanghai tells the
+// aggressor of a trade, Shenzhen only tells fills from cancellations.
+
+pub(crate) fn trade_flag(code: u8) -> TradeFlag {
+    match code {
+        b'B' => TradeFlag::BuyerInitiated,
+        b'S' => TradeFlag::SellerInitiated,
+        b'4' => TradeFlag::Cancel,
+        _ => TradeFlag::Unknown,
+    }
+}
+
+pub(crate) fn trade_flag_code(flag: TradeFlag) -> u8 {
+    match flag {
+        TradeFlag::BuyerInitiated => b'B',
+        TradeFlag::SellerInitiated => b'S',
+        TradeFlag::Cancel => b'4',
+        TradeFlag::Unknown => b'N',
+    }
+}
+
+pub(crate) fn entrust_side(code: u8) -> Option<Side> {
+    match code {
+        b'1' | b'B' => Some(Side::Buy),
+        b'2' | b'S' => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+pub(crate) fn entrust_side_code(side: Option<Side>) -> u8 {
+    match side {
+        Some(Side::Buy) => b'1',
+        Some(Side::Sell) => b'2',
+        None => 0,
+    }
+}
+
+pub(crate) fn entrust_type(code: u8) -> EntrustType {
+    match code {
+        b'1' => EntrustType::Market,
+        b'2' => EntrustType::Limit,
+        b'U' => EntrustType::BestOwnSide,
+        _ => EntrustType::Unknown,
+    }
+}
+
+pub(crate) fn entrust_type_code(entrust_type: EntrustType) -> u8 {
+    match entrust_type {
+        EntrustType::Market => b'1',
+        EntrustType::Limit => b'2',
+        EntrustType::BestOwnSide => b'U',
+        EntrustType::Unknown => 0,
+    }
+}
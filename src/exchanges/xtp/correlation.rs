@@ -0,0 +1,344 @@
+use crate::{Error, Result};
+use failure::Fallible;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::time;
+use xtp::{
+    XTPQueryAssetRsp, XTPQueryOrderRsp, XTPQueryStkPositionRsp, XTPQueryTradeRsp,
+    XTPQuoteStaticInfo, XTPRspInfoStruct, XTPTickerPriceInfo,
+};
+
+// XTP answers a query that matches nothing with this error instead of an
+// empty response.
+const XTP_NO_DATA: i32 = 11000350;
+
+struct Partial<T> {
+    items: Vec<T>,
+    // `None` once the request timed out or failed: its responses are still
+    // drained up to `is_last`, so that they do not end up in the next
+    // request of the same id.
+    tx: Option<oneshot::Sender<Result<Vec<T>>>>,
+    drained: Vec<oneshot::Sender<()>>,
+}
+
+/// How long queries wait for their responses. Shared by the correlators of
+/// an exchange, so changing it affects every query sent afterwards.
+#[derive(Clone)]
+pub(crate) struct RequestTimeout(Arc<AtomicU64>);
+
+impl RequestTimeout {
+    pub fn new(timeout: Duration) -> Self {
+        RequestTimeout(Arc::new(AtomicU64::new(timeout.as_millis() as u64)))
+    }
+
+    pub fn set(&self, timeout: Duration) {
+        self.0.store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Matches callback responses to the request that caused them. Responses
+/// may be split across many callbacks, they are collected until `is_last`.
+pub(crate) struct Correlator<T> {
+    pending: Mutex<HashMap<i32, Partial<T>>>,
+    timeout: RequestTimeout,
+}
+
+impl<T> Correlator<T> {
+    pub fn new(timeout: RequestTimeout) -> Self {
+        Correlator {
+            pending: Mutex::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    /// Send a request through `send` and wait for all of its responses.
+    /// While an earlier request of the same id is still draining, waits up
+    /// to the timeout for it to end first.
+    pub async fn request<F>(&self, request_id: i32, send: F) -> Result<Vec<T>>
+    where
+        F: FnOnce() -> Fallible<()>,
+    {
+        if let Some(drained) = self.drained(request_id) {
+            if time::timeout(self.timeout.get(), drained).await.is_err() {
+                warn!("Request {} never got its last response", request_id);
+            }
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let partial = Partial {
+            items: vec![],
+            tx: Some(tx),
+            drained: vec![],
+        };
+        // Replaces an earlier request that never got its last response.
+        self.pending.lock().unwrap().insert(request_id, partial);
+
+        if let Err(e) = send() {
+            self.cancel(request_id);
+            return Err(Error::Api(e.to_string()));
+        }
+
+        match time::timeout(self.timeout.get(), rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Api(format!("Request {} dropped", request_id))),
+            Err(_) => {
+                if let Some(partial) = self.pending.lock().unwrap().get_mut(&request_id) {
+                    partial.tx = None;
+                }
+                Err(Error::Timeout(request_id))
+            }
+        }
+    }
+
+    /// Whether `request_id` is awaited by somebody, or still draining.
+    /// Responses to any other request are passed on to the strategies as
+    /// they are.
+    pub fn contains(&self, request_id: i32) -> bool {
+        self.pending.lock().unwrap().contains_key(&request_id)
+    }
+
+    /// A failed response ends the request with `Error::Rsp` at once, the
+    /// responses after it are drained up to `is_last`.
+    pub fn respond(&self, request_id: i32, item: T, error_info: &XTPRspInfoStruct, is_last: bool) {
+        let mut pending = self.pending.lock().unwrap();
+        let partial = match pending.get_mut(&request_id) {
+            Some(partial) => partial,
+            None => return,
+        };
+
+        match error_info.error_id {
+            0 => {
+                if partial.tx.is_some() {
+                    partial.items.push(item);
+                }
+            }
+            XTP_NO_DATA => {}
+            error_id => match partial.tx.take() {
+                Some(tx) => {
+                    let _ = tx.send(Err(Error::Rsp {
+                        error_id,
+                        error_msg: error_info.error_msg.clone(),
+                    }));
+                }
+                None => warn!(
+                    "Request {} failed after it was given up: {} (XTP error {})",
+                    request_id, error_info.error_msg, error_id
+                ),
+            },
+        }
+
+        if is_last {
+            if let Some(partial) = pending.remove(&request_id) {
+                if let Some(tx) = partial.tx {
+                    let _ = tx.send(Ok(partial.items));
+                }
+                for tx in partial.drained {
+                    let _ = tx.send(());
+                }
+            }
+        }
+    }
+
+    // Told once the earlier request of `request_id` got its last response,
+    // `None` when there is none.
+    fn drained(&self, request_id: i32) -> Option<oneshot::Receiver<()>> {
+        let mut pending = self.pending.lock().unwrap();
+        let partial = pending.get_mut(&request_id)?;
+        let (tx, rx) = oneshot::channel();
+        partial.drained.push(tx);
+        Some(rx)
+    }
+
+    fn cancel(&self, request_id: i32) {
+        self.pending.lock().unwrap().remove(&request_id);
+    }
+}
+
+/// Outstanding trader queries, shared by the handle and TSpi.
+pub(crate) struct TraderQueries {
+    next_request_id: AtomicI32,
+    pub positions: Correlator<XTPQueryStkPositionRsp>,
+    pub assets: Correlator<XTPQueryAssetRsp>,
+    pub orders: Correlator<XTPQueryOrderRsp>,
+    pub trades: Correlator<XTPQueryTradeRsp>,
+}
+
+impl TraderQueries {
+    pub fn new(timeout: &RequestTimeout) -> Self {
+        TraderQueries {
+            next_request_id: AtomicI32::new(0),
+            positions: Correlator::new(timeout.clone()),
+            assets: Correlator::new(timeout.clone()),
+            orders: Correlator::new(timeout.clone()),
+            trades: Correlator::new(timeout.clone()),
+        }
+    }
+
+    pub fn next_request_id(&self) -> i32 {
+        // 0 is left to queries made by pixiu itself, e.g. after a reconnect
+        self.next_request_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// Outstanding quote queries, shared by the handle and QSpi. Quote responses
+/// carry no request id, so queries of a kind run one at a time under
+/// `QUOTE_REQUEST`, each after the responses to the one before are over.
+pub(crate) struct QuoteQueries {
+    pub tickers: Correlator<XTPQuoteStaticInfo>,
+    pub prices: Correlator<XTPTickerPriceInfo>,
+    pub tickers_running: AsyncMutex<()>,
+    pub prices_running: AsyncMutex<()>,
+}
+
+pub(crate) const QUOTE_REQUEST: i32 = 0;
+
+impl QuoteQueries {
+    pub fn new(timeout: &RequestTimeout) -> Self {
+        QuoteQueries {
+            tickers: Correlator::new(timeout.clone()),
+            prices: Correlator::new(timeout.clone()),
+            tickers_running: AsyncMutex::new(()),
+            prices_running: AsyncMutex::new(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsp(error_id: i32) -> XTPRspInfoStruct {
+        XTPRspInfoStruct {
+            error_id,
+            error_msg: String::new(),
+        }
+    }
+
+    fn correlator() -> Correlator<&'static str> {
+        Correlator::new(RequestTimeout::new(Duration::from_millis(50)))
+    }
+
+    #[tokio::test]
+    async fn collects_responses_up_to_the_last() {
+        let trades = correlator();
+        let items = trades
+            .request(1, || {
+                trades.respond(1, "a", &rsp(0), false);
+                trades.respond(2, "other", &rsp(0), false);
+                trades.respond(1, "b", &rsp(0), false);
+                trades.respond(1, "c", &rsp(0), true);
+                Ok(())
+            })
+            .await;
+        assert_eq!(items.unwrap(), vec!["a", "b", "c"]);
+        assert!(!trades.contains(1));
+    }
+
+    #[tokio::test]
+    async fn no_data_is_an_empty_response() {
+        let trades = correlator();
+        let items = trades
+            .request(1, || {
+                trades.respond(1, "", &rsp(XTP_NO_DATA), true);
+                Ok(())
+            })
+            .await;
+        assert_eq!(items.unwrap(), Vec::<&str>::new());
+    }
+
+    #[tokio::test]
+    async fn errors_end_the_request() {
+        let trades = correlator();
+        let items = trades
+            .request(1, || {
+                trades.respond(1, "a", &rsp(0), false);
+                trades.respond(1, "", &rsp(11000001), false);
+                Ok(())
+            })
+            .await;
+        match items {
+            Err(Error::Rsp { error_id, .. }) => assert_eq!(error_id, 11000001),
+            other => panic!("{:?}", other),
+        }
+        // What follows the error is still the request's.
+        assert!(trades.contains(1));
+        trades.respond(1, "b", &rsp(0), true);
+        assert!(!trades.contains(1));
+    }
+
+    #[tokio::test]
+    async fn times_out_without_the_last_response() {
+        let trades = correlator();
+        let items = trades.request(1, || Ok(())).await;
+        match items {
+            Err(Error::Timeout(1)) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let items = trades
+            .request(2, || Err(failure::err_msg("not logged in")))
+            .await;
+        match items {
+            Err(Error::Api(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(!trades.contains(2));
+    }
+
+    #[tokio::test]
+    async fn late_responses_stay_out_of_the_next_request() {
+        let tickers = correlator();
+        // SH answers in part before the timeout.
+        let sh = tickers
+            .request(QUOTE_REQUEST, || {
+                tickers.respond(QUOTE_REQUEST, "600036", &rsp(0), false);
+                Ok(())
+            })
+            .await;
+        match sh {
+            Err(Error::Timeout(QUOTE_REQUEST)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(tickers.contains(QUOTE_REQUEST));
+
+        let (sz, _) = futures::join!(tickers.request(QUOTE_REQUEST, || Ok(())), async {
+            tickers.respond(QUOTE_REQUEST, "600000", &rsp(0), true);
+            tokio::task::yield_now().await;
+            tickers.respond(QUOTE_REQUEST, "000001", &rsp(0), true);
+        });
+        assert_eq!(sz.unwrap(), vec!["000001"]);
+        assert!(!tickers.contains(QUOTE_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_get_their_own_responses() {
+        let orders = correlator();
+        let (first, second, _) = futures::join!(
+            orders.request(1, || Ok(())),
+            orders.request(2, || Ok(())),
+            async {
+                orders.respond(2, "c", &rsp(0), false);
+                orders.respond(1, "a", &rsp(0), false);
+                orders.respond(2, "d", &rsp(0), true);
+                orders.respond(1, "b", &rsp(0), true);
+            }
+        );
+        assert_eq!(first.unwrap(), vec!["a", "b"]);
+        assert_eq!(second.unwrap(), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn trader_request_ids_leave_zero_out() {
+        let queries = TraderQueries::new(&RequestTimeout::new(Duration::from_secs(1)));
+        assert_eq!(queries.next_request_id(), 1);
+        assert_eq!(queries.next_request_id(), 2);
+    }
+}
@@ -0,0 +1,32 @@
+use super::quote_event::QuoteEvent;
+use super::trader_event::TraderEvent;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Quote,
+    Trader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected(Session),
+    Disconnected {
+        session: Session,
+        reason: i32,
+    },
+    /// Reconnecting failed `ReconnectPolicy::max_attempts` times, the session
+    /// stays down.
+    GaveUp(Session),
+}
+
+/// What the gateway callbacks queue for the run loop, in the order they
+/// were called. Strategies receive them as `Event`, see `Router`.
+#[derive(Debug, Clone)]
+pub(crate) enum XTPEvent {
+    MarketData(QuoteEvent),
+    /// Order updates, fills, cancel rejects and account updates.
+    Trader(TraderEvent),
+    Connection(ConnectionState),
+    Timer(Instant),
+}
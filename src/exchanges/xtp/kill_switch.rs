@@ -0,0 +1,201 @@
+use super::order::{OrderRequest, PriceType};
+use super::order_manager::OrderSnapshot;
+use crate::market::Venue;
+use crate::portfolio::{Portfolio, Side};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KillSwitchConfig {
+    /// Sell every sellable position at market once engaged, after the open
+    /// orders are cancelled.
+    pub flatten: bool,
+    /// Engage on SIGUSR1, Unix only. Off by default, as the signal may
+    /// already be used by the host process.
+    pub on_signal: bool,
+}
+
+/// Blocks new orders from every strategy once engaged.
+#[derive(Default)]
+pub(crate) struct KillSwitch {
+    engaged: AtomicBool,
+    reason: Mutex<Option<String>>,
+    // XTP ids of the orders cancelled on engaging that are still open, the
+    // flatten waits for them. `None` when no flatten is pending.
+    flatten: Mutex<Option<HashSet<u64>>>,
+}
+
+impl KillSwitch {
+    /// Returns false when the switch was already engaged.
+    pub fn engage(&self, reason: &str) -> bool {
+        let mut current = self.reason.lock().unwrap();
+        if self.engaged.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        *current = Some(reason.to_string());
+        true
+    }
+
+    /// Also drops a pending flatten.
+    pub fn release(&self) {
+        let mut current = self.reason.lock().unwrap();
+        self.engaged.store(false, Ordering::SeqCst);
+        *current = None;
+        *self.flatten.lock().unwrap() = None;
+    }
+
+    /// The reason it was engaged for, `None` while released.
+    pub fn engaged(&self) -> Option<String> {
+        if !self.engaged.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.reason.lock().unwrap().clone()
+    }
+
+    /// Flatten once the cancels of `xtp_ids` are resolved.
+    pub fn flatten_after<I: IntoIterator<Item = u64>>(&self, xtp_ids: I) {
+        *self.flatten.lock().unwrap() = Some(xtp_ids.into_iter().collect());
+    }
+
+    /// The gateway refused to cancel `xtp_id`, which no longer holds up the
+    /// flatten.
+    pub fn cancel_rejected(&self, xtp_id: u64) {
+        if let Some(pending) = self.flatten.lock().unwrap().as_mut() {
+            pending.remove(&xtp_id);
+        }
+    }
+
+    /// Whether to flatten now: true once per `flatten_after`, when none of
+    /// its orders is `open` any more.
+    pub fn flatten_due<F: Fn(u64) -> bool>(&self, open: F) -> bool {
+        let mut flatten = self.flatten.lock().unwrap();
+        let due = match flatten.as_mut() {
+            Some(pending) => {
+                pending.retain(|&xtp_id| open(xtp_id));
+                pending.is_empty()
+            }
+            None => false,
+        };
+        if due {
+            *flatten = None;
+        }
+        due
+    }
+}
+
+/// Sells of every sellable position at market, less the shares that
+/// `open_orders` are still selling: the broker refuses to sell those again.
+pub(crate) fn flatten_orders(
+    portfolio: &Portfolio,
+    open_orders: &[OrderSnapshot],
+) -> Vec<OrderRequest> {
+    portfolio
+        .positions()
+        .filter(|position| position.venue != Venue::Unknown)
+        .filter_map(|position| {
+            let selling = open_orders
+                .iter()
+                .filter(|o| o.ticker == position.ticker && o.venue == position.venue)
+                .filter(|o| o.side == Side::Sell)
+                .map(|o| o.leaves_quantity)
+                .sum::<i64>();
+            let quantity = position.sellable_quantity - selling;
+            if quantity <= 0 {
+                return None;
+            }
+            Some(OrderRequest {
+                price_type: PriceType::Best5OrCancel,
+                ..OrderRequest::limit(&position.ticker, position.venue, Side::Sell, 0., quantity)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::order_manager::OrderState;
+    use super::*;
+
+    #[test]
+    fn engages_once_until_released() {
+        let kill_switch = KillSwitch::default();
+        assert_eq!(kill_switch.engaged(), None);
+        assert!(kill_switch.engage("Loss limit breached"));
+        assert!(!kill_switch.engage("SIGUSR1"));
+        assert_eq!(
+            kill_switch.engaged(),
+            Some("Loss limit breached".to_string())
+        );
+
+        kill_switch.release();
+        assert_eq!(kill_switch.engaged(), None);
+        assert!(kill_switch.engage("SIGUSR1"));
+        assert_eq!(kill_switch.engaged(), Some("SIGUSR1".to_string()));
+    }
+
+    #[test]
+    fn flattens_once_every_cancel_is_resolved() {
+        let kill_switch = KillSwitch::default();
+        assert!(!kill_switch.flatten_due(|_| false));
+
+        kill_switch.flatten_after(vec![1, 2, 3]);
+        assert!(!kill_switch.flatten_due(|xtp_id| xtp_id != 1));
+        // A rejected cancel leaves its order open for good.
+        kill_switch.cancel_rejected(2);
+        assert!(!kill_switch.flatten_due(|xtp_id| xtp_id == 2 || xtp_id == 3));
+        assert!(kill_switch.flatten_due(|xtp_id| xtp_id == 2));
+        assert!(!kill_switch.flatten_due(|_| false));
+
+        // Without open orders it flattens right away.
+        kill_switch.flatten_after(vec![]);
+        assert!(kill_switch.flatten_due(|_| true));
+    }
+
+    #[test]
+    fn release_drops_the_pending_flatten() {
+        let kill_switch = KillSwitch::default();
+        kill_switch.engage("SIGUSR1");
+        kill_switch.flatten_after(vec![1]);
+        kill_switch.release();
+        assert!(!kill_switch.flatten_due(|_| false));
+    }
+
+    fn open_sell(ticker: &str, leaves_quantity: i64) -> OrderSnapshot {
+        OrderSnapshot {
+            client_id: Some(1),
+            xtp_id: 1,
+            ticker: ticker.to_string(),
+            venue: Venue::SH,
+            side: Side::Sell,
+            price: 10.,
+            quantity: leaves_quantity,
+            filled_quantity: 0,
+            leaves_quantity,
+            avg_price: 0.,
+            state: OrderState::New,
+        }
+    }
+
+    #[test]
+    fn flatten_leaves_what_open_sells_hold() {
+        let mut portfolio = Portfolio::new();
+        portfolio.reconcile(Venue::SH, "600036", 1000, 1000, 10.);
+        portfolio.reconcile(Venue::SH, "600000", 500, 500, 10.);
+        portfolio.reconcile(Venue::SZ, "000001", 300, 0, 10.);
+
+        let mut reqs = flatten_orders(
+            &portfolio,
+            &[open_sell("600036", 400), open_sell("600000", 500)],
+        );
+        assert_eq!(reqs.len(), 1);
+        let req = reqs.pop().unwrap();
+        assert_eq!(req.ticker, "600036");
+        assert_eq!(req.venue, Venue::SH);
+        assert_eq!(req.side, Side::Sell);
+        assert_eq!(req.price_type, PriceType::Best5OrCancel);
+        assert_eq!(req.quantity, 600);
+    }
+}
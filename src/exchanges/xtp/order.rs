@@ -0,0 +1,259 @@
+use super::order_manager::OrderSnapshot;
+use super::StrategyId;
+use crate::market::{InstrumentInfo, Venue};
+use crate::portfolio::{Position, Side};
+use crate::risk::OrderCheck;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use xtp::{
+    XTPBusinessType, XTPMarketType, XTPOrderInsertInfo, XTPPositionEffectType, XTPPriceType,
+    XTPSideType,
+};
+
+/// Client side order id, assigned by pixiu when the order is submitted.
+pub type ClientOrderId = u32;
+
+/// How an order is priced. Everything but `Limit` is a market order, which
+/// of them an exchange accepts is up to its rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceType {
+    Limit,
+    /// Fill at the best price on the other side, cancel the rest.
+    BestOrCancel,
+    /// Fill against the five best levels, cancel the rest.
+    Best5OrCancel,
+    /// Fill against the five best levels, the rest becomes a limit order.
+    Best5OrLimit,
+    /// Fill in full or not at all.
+    AllOrCancel,
+    /// Rest at the best price on the own side.
+    ForwardBest,
+    /// Fill at the best price on the other side, the rest becomes a limit
+    /// order.
+    ReverseBestLimit,
+}
+
+/// A cash order.
+#[derive(Debug, Clone)]
+pub struct OrderRequest {
+    pub ticker: String,
+    pub venue: Venue,
+    pub side: Side,
+    pub price_type: PriceType,
+    pub price: f64,
+    pub quantity: i64,
+}
+
+impl OrderRequest {
+    /// A plain limit order, which is what most strategies need.
+    pub fn limit(ticker: &str, venue: Venue, side: Side, price: f64, quantity: i64) -> Self {
+        OrderRequest {
+            ticker: ticker.to_string(),
+            venue,
+            side,
+            price_type: PriceType::Limit,
+            price,
+            quantity,
+        }
+    }
+
+    /// What the risk engine needs to know about this order, given the
+    /// current position, the open orders, the last quote's price and
+    /// limit-down/limit-up band, and the instrument's static data. The band
+    /// of the static data is used when there is no quote.
+    pub(crate) fn order_check<'a>(
+        &'a self,
+        position: Option<&Position>,
+        open_orders: &[OrderSnapshot],
+        last_price: Option<f64>,
+        price_limits: Option<(f64, f64)>,
+        instrument: Option<&InstrumentInfo>,
+    ) -> OrderCheck<'a> {
+        let price_limits = price_limits.or_else(|| {
+            instrument
+                .filter(|i| i.has_price_limits())
+                .map(|i| (i.lower_limit_price.to_f64(), i.upper_limit_price.to_f64()))
+        });
+        let open_quantity = |side: Side| {
+            open_orders
+                .iter()
+                .filter(|o| o.ticker == self.ticker && o.venue == self.venue)
+                .filter(|o| o.side == side)
+                .map(|o| o.leaves_quantity)
+                .sum::<i64>()
+        };
+        OrderCheck {
+            ticker: &self.ticker,
+            side: self.side,
+            price: if self.price_type == PriceType::Limit {
+                Some(self.price)
+            } else {
+                None
+            },
+            quantity: self.quantity,
+            position: position.map_or(0, |p| p.quantity),
+            sellable: position.map_or(0, |p| p.sellable_quantity),
+            open_buy_quantity: open_quantity(Side::Buy),
+            open_sell_quantity: open_quantity(Side::Sell),
+            last_price,
+            lower_limit_price: price_limits.map(|(lower, _)| lower),
+            upper_limit_price: price_limits.map(|(_, upper)| upper),
+            lot_size: instrument.map(|i| match self.side {
+                Side::Buy => i.buy_lot,
+                Side::Sell => i.sell_lot,
+            }),
+            tick_size: instrument.map(|i| i.tick_size.to_f64()),
+        }
+    }
+
+    pub(crate) fn to_insert_info(&self, client_id: ClientOrderId) -> XTPOrderInsertInfo {
+        XTPOrderInsertInfo {
+            order_xtp_id: 0,
+            order_client_id: client_id,
+            ticker: self.ticker.clone(),
+            market: market_of(self.venue),
+            price: self.price,
+            stop_price: 0.,
+            quantity: self.quantity,
+            price_type: xtp_price_type(self.price_type),
+            side: match self.side {
+                Side::Buy => XTPSideType::Buy,
+                Side::Sell => XTPSideType::Sell,
+            },
+            position_effect: XTPPositionEffectType::Init,
+            reserved1: 0,
+            reserved2: 0,
+            business_type: XTPBusinessType::Cash,
+        }
+    }
+}
+
+/// The A-share market of a venue.
+fn market_of(venue: Venue) -> XTPMarketType {
+    match venue {
+        Venue::SH => XTPMarketType::SHA,
+        Venue::SZ => XTPMarketType::SZA,
+        Venue::Unknown => XTPMarketType::Unknown,
+    }
+}
+
+fn xtp_price_type(price_type: PriceType) -> XTPPriceType {
+    match price_type {
+        PriceType::Limit => XTPPriceType::Limit,
+        PriceType::BestOrCancel => XTPPriceType::BestOrCancel,
+        PriceType::Best5OrCancel => XTPPriceType::Best5OrCancel,
+        PriceType::Best5OrLimit => XTPPriceType::Best5OrLimit,
+        PriceType::AllOrCancel => XTPPriceType::AllOrCancel,
+        PriceType::ForwardBest => XTPPriceType::ForwardBest,
+        PriceType::ReverseBestLimit => XTPPriceType::ReverseBestLimit,
+    }
+}
+
+/// Maps between client and XTP order ids, and remembers which strategy
+/// placed each order so its reports can be routed back to it.
+#[derive(Default)]
+pub(crate) struct OrderIds {
+    next_client_id: AtomicU32,
+    maps: Mutex<OrderIdMaps>,
+}
+
+#[derive(Default)]
+struct OrderIdMaps {
+    xtp_ids: HashMap<ClientOrderId, u64>,
+    client_ids: HashMap<u64, ClientOrderId>,
+    owners: HashMap<ClientOrderId, StrategyId>,
+}
+
+impl OrderIds {
+    /// Allocate a client order id owned by `strategy`.
+    pub fn allocate(&self, strategy: StrategyId) -> ClientOrderId {
+        let client_id = self.next_client_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.maps.lock().unwrap().owners.insert(client_id, strategy);
+        client_id
+    }
+
+    pub fn set_xtp_id(&self, client_id: ClientOrderId, xtp_id: u64) {
+        let mut maps = self.maps.lock().unwrap();
+        maps.xtp_ids.insert(client_id, xtp_id);
+        maps.client_ids.insert(xtp_id, client_id);
+    }
+
+    pub fn xtp_id(&self, client_id: ClientOrderId) -> Option<u64> {
+        self.maps.lock().unwrap().xtp_ids.get(&client_id).cloned()
+    }
+
+    pub fn client_id(&self, xtp_id: u64) -> Option<ClientOrderId> {
+        self.maps.lock().unwrap().client_ids.get(&xtp_id).cloned()
+    }
+
+    pub fn owner(&self, client_id: ClientOrderId) -> Option<StrategyId> {
+        self.maps.lock().unwrap().owners.get(&client_id).cloned()
+    }
+
+    /// The strategy that placed the order `xtp_id`. Client order ids are
+    /// only unique within a run of one client, so reports are matched by
+    /// their XTP id: `None` for orders placed elsewhere, and for reports
+    /// that beat `insert_order` returning.
+    pub fn owner_of_xtp_id(&self, xtp_id: u64) -> Option<StrategyId> {
+        let maps = self.maps.lock().unwrap();
+        let client_id = maps.client_ids.get(&xtp_id)?;
+        maps.owners.get(client_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::order_manager::OrderState;
+    use super::*;
+
+    fn open(ticker: &str, side: Side, leaves_quantity: i64) -> OrderSnapshot {
+        OrderSnapshot {
+            client_id: Some(1),
+            xtp_id: 1,
+            ticker: ticker.to_string(),
+            venue: Venue::SH,
+            side,
+            price: 10.,
+            quantity: 1000,
+            filled_quantity: 1000 - leaves_quantity,
+            leaves_quantity,
+            avg_price: 10.,
+            state: OrderState::PartiallyFilled,
+        }
+    }
+
+    #[test]
+    fn counts_what_is_left_of_open_orders_per_side() {
+        let open_orders = vec![
+            open("600036", Side::Buy, 300),
+            open("600036", Side::Buy, 200),
+            open("600036", Side::Sell, 100),
+            open("600000", Side::Sell, 400),
+        ];
+        let req = OrderRequest::limit("600036", Venue::SH, Side::Sell, 10., 100);
+        let check = req.order_check(None, &open_orders, Some(10.), None, None);
+        assert_eq!(check.open_buy_quantity, 500);
+        assert_eq!(check.open_sell_quantity, 100);
+    }
+
+    #[test]
+    fn ids_map_both_ways_and_keep_their_owner() {
+        let ids = OrderIds::default();
+        let first = ids.allocate(3);
+        let second = ids.allocate(5);
+        assert!(first != 0 && second > first);
+        assert_eq!(ids.owner(first), Some(3));
+        assert_eq!(ids.owner(second), Some(5));
+
+        // Unknown to XTP until the insert returns.
+        assert_eq!(ids.xtp_id(first), None);
+        assert_eq!(ids.owner_of_xtp_id(42), None);
+
+        ids.set_xtp_id(first, 42);
+        assert_eq!(ids.xtp_id(first), Some(42));
+        assert_eq!(ids.client_id(42), Some(first));
+        assert_eq!(ids.owner_of_xtp_id(42), Some(3));
+        assert_eq!(ids.client_id(43), None);
+    }
+}
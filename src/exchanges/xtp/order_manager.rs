@@ -0,0 +1,473 @@
+use super::event::XTPEvent;
+use super::order::{ClientOrderId, OrderRequest};
+use super::positions::{side_of, venue_of};
+use super::trader_event::TraderEvent;
+use crate::market::Venue;
+use crate::portfolio::Side;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use xtp::{XTPOrderInfo, XTPOrderStatusType, XTPTradeReport};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderState {
+    /// Sent, not yet acknowledged by the exchange.
+    PendingNew,
+    New,
+    PartiallyFilled,
+    Filled,
+    /// Cancelled, possibly after being partially filled.
+    Cancelled,
+    Rejected,
+}
+
+impl OrderState {
+    pub fn is_terminal(self) -> bool {
+        match self {
+            OrderState::Filled | OrderState::Cancelled | OrderState::Rejected => true,
+            _ => false,
+        }
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            OrderState::PendingNew => 0,
+            OrderState::New => 1,
+            OrderState::PartiallyFilled => 2,
+            OrderState::Filled | OrderState::Cancelled | OrderState::Rejected => 3,
+        }
+    }
+
+    /// Orders only move forward, and never leave a terminal state.
+    fn can_become(self, next: OrderState) -> bool {
+        !self.is_terminal() && next.rank() >= self.rank()
+    }
+
+    fn from_xtp(status: XTPOrderStatusType) -> Option<OrderState> {
+        match status {
+            XTPOrderStatusType::Init => Some(OrderState::PendingNew),
+            XTPOrderStatusType::NoTradeQueueing => Some(OrderState::New),
+            XTPOrderStatusType::PartTradedQueueing => Some(OrderState::PartiallyFilled),
+            XTPOrderStatusType::AllTraded => Some(OrderState::Filled),
+            XTPOrderStatusType::PartTradedNotQueueing | XTPOrderStatusType::Canceled => {
+                Some(OrderState::Cancelled)
+            }
+            XTPOrderStatusType::Rejected => Some(OrderState::Rejected),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderSnapshot {
+    /// `None` for orders not placed through pixiu.
+    pub client_id: Option<ClientOrderId>,
+    pub xtp_id: u64,
+    pub ticker: String,
+    pub venue: Venue,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: i64,
+    pub filled_quantity: i64,
+    pub leaves_quantity: i64,
+    pub avg_price: f64,
+    pub state: OrderState,
+}
+
+struct Order {
+    snapshot: OrderSnapshot,
+    // Cumulative figures from the trade reports, as opposed to the order
+    // reports. Either may run ahead of the other.
+    traded_quantity: i64,
+    traded_amount: f64,
+    exec_ids: HashSet<String>,
+}
+
+impl Order {
+    fn new(snapshot: OrderSnapshot) -> Self {
+        Order {
+            snapshot,
+            traded_quantity: 0,
+            traded_amount: 0.,
+            exec_ids: HashSet::new(),
+        }
+    }
+
+    fn from_request(client_id: ClientOrderId, xtp_id: u64, req: &OrderRequest) -> Self {
+        Order::new(OrderSnapshot {
+            client_id: Some(client_id),
+            xtp_id,
+            ticker: req.ticker.clone(),
+            venue: req.venue,
+            side: req.side,
+            price: req.price,
+            quantity: req.quantity,
+            filled_quantity: 0,
+            leaves_quantity: req.quantity,
+            avg_price: 0.,
+            state: OrderState::PendingNew,
+        })
+    }
+
+    // `None` for orders pixiu does not trade, like margin orders.
+    fn from_report(order: &XTPOrderInfo) -> Option<Self> {
+        Some(Order::new(OrderSnapshot {
+            client_id: None,
+            xtp_id: order.order_xtp_id,
+            ticker: order.ticker.clone(),
+            venue: venue_of(order.market),
+            side: side_of(order.side)?,
+            price: order.price,
+            quantity: order.quantity,
+            filled_quantity: 0,
+            leaves_quantity: order.quantity,
+            avg_price: 0.,
+            state: OrderState::PendingNew,
+        }))
+    }
+
+    fn transition(&mut self, next: OrderState) -> bool {
+        let snapshot = &mut self.snapshot;
+        if snapshot.state.can_become(next) {
+            snapshot.state = next;
+            true
+        } else {
+            if snapshot.state != next {
+                warn!(
+                    "Order {}: ignoring transition {:?} -> {:?}",
+                    snapshot.xtp_id, snapshot.state, next
+                );
+            }
+            false
+        }
+    }
+
+    fn apply_order(&mut self, update: &OrderUpdate) {
+        if let Some(next) = update.state {
+            if !self.transition(next) {
+                return;
+            }
+        }
+        let snapshot = &mut self.snapshot;
+        snapshot.filled_quantity = snapshot.filled_quantity.max(update.qty_traded);
+        // Trade reports may have run ahead of this one.
+        snapshot.leaves_quantity = if snapshot.state.is_terminal() {
+            0
+        } else {
+            update
+                .qty_left
+                .min(snapshot.quantity - snapshot.filled_quantity)
+        };
+    }
+
+    fn apply_trade(&mut self, trade: &Trade) {
+        if !self.exec_ids.insert(trade.exec_id.clone()) {
+            return;
+        }
+        self.traded_quantity += trade.quantity;
+        self.traded_amount += trade.price * trade.quantity as f64;
+
+        let snapshot = &mut self.snapshot;
+        snapshot.filled_quantity = snapshot.filled_quantity.max(self.traded_quantity);
+        snapshot.avg_price = self.traded_amount / self.traded_quantity as f64;
+        if snapshot.state.is_terminal() {
+            return;
+        }
+        snapshot.leaves_quantity = snapshot.quantity - snapshot.filled_quantity;
+        let next = if snapshot.leaves_quantity <= 0 {
+            OrderState::Filled
+        } else {
+            OrderState::PartiallyFilled
+        };
+        self.transition(next);
+    }
+}
+
+// What the order manager takes from an order report.
+struct OrderUpdate {
+    state: Option<OrderState>,
+    qty_traded: i64,
+    qty_left: i64,
+}
+
+impl OrderUpdate {
+    fn from_xtp(order: &XTPOrderInfo) -> Self {
+        OrderUpdate {
+            state: OrderState::from_xtp(order.order_status),
+            qty_traded: order.qty_traded,
+            qty_left: order.qty_left,
+        }
+    }
+}
+
+// And from a trade report.
+struct Trade {
+    exec_id: String,
+    price: f64,
+    quantity: i64,
+}
+
+impl Trade {
+    fn from_xtp(trade: &XTPTradeReport) -> Self {
+        Trade {
+            exec_id: trade.exec_id.clone(),
+            price: trade.price,
+            quantity: trade.quantity,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Orders {
+    by_xtp_id: HashMap<u64, Order>,
+    by_client_id: HashMap<ClientOrderId, u64>,
+    // Trades that arrived before the insert returned or the first order
+    // report, applied once the order is known.
+    pending_trades: HashMap<u64, Vec<Trade>>,
+}
+
+impl Orders {
+    fn insert(&mut self, mut order: Order) {
+        let xtp_id = order.snapshot.xtp_id;
+        for trade in self.pending_trades.remove(&xtp_id).unwrap_or_default() {
+            order.apply_trade(&trade);
+        }
+        self.by_xtp_id.insert(xtp_id, order);
+    }
+
+    fn order_mut(&mut self, xtp_id: u64) -> &mut Order {
+        self.by_xtp_id.get_mut(&xtp_id).unwrap()
+    }
+
+    // `order` builds the order when this is the first seen of it, `None`
+    // leaves it untracked.
+    fn apply_order<F>(&mut self, xtp_id: u64, order: F, update: &OrderUpdate)
+    where
+        F: FnOnce() -> Option<Order>,
+    {
+        if !self.by_xtp_id.contains_key(&xtp_id) {
+            match order() {
+                Some(order) => self.insert(order),
+                None => return,
+            }
+        }
+        self.order_mut(xtp_id).apply_order(update);
+    }
+
+    fn apply_trade(&mut self, xtp_id: u64, trade: Trade) {
+        match self.by_xtp_id.get_mut(&xtp_id) {
+            Some(order) => order.apply_trade(&trade),
+            None => self
+                .pending_trades
+                .entry(xtp_id)
+                .or_insert_with(Vec::new)
+                .push(trade),
+        }
+    }
+}
+
+/// Tracks the life of every order seen on the trader session, applying
+/// order and trade reports in the order the run loop receives them.
+#[derive(Default)]
+pub(crate) struct OrderManager {
+    orders: Mutex<Orders>,
+}
+
+impl OrderManager {
+    pub fn on_submit(&self, client_id: ClientOrderId, xtp_id: u64, req: &OrderRequest) {
+        let mut orders = self.orders.lock().unwrap();
+        orders.by_client_id.insert(client_id, xtp_id);
+        // The first report may have beaten us here.
+        if !orders.by_xtp_id.contains_key(&xtp_id) {
+            orders.insert(Order::from_request(client_id, xtp_id, req));
+        }
+        orders.order_mut(xtp_id).snapshot.client_id = Some(client_id);
+    }
+
+    pub fn apply(&self, event: &XTPEvent) {
+        let mut orders = self.orders.lock().unwrap();
+        match event {
+            XTPEvent::Trader(TraderEvent::OrderEvent { order_info, .. })
+            | XTPEvent::Trader(TraderEvent::QueryOrder { order_info, .. }) => orders.apply_order(
+                order_info.order_xtp_id,
+                || Order::from_report(order_info),
+                &OrderUpdate::from_xtp(order_info),
+            ),
+            XTPEvent::Trader(TraderEvent::TradeEvent { trade_info, .. }) => {
+                orders.apply_trade(trade_info.order_xtp_id, Trade::from_xtp(trade_info))
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, client_id: ClientOrderId) -> Option<OrderSnapshot> {
+        let orders = self.orders.lock().unwrap();
+        let xtp_id = orders.by_client_id.get(&client_id)?;
+        orders.by_xtp_id.get(xtp_id).map(|o| o.snapshot.clone())
+    }
+
+    pub fn by_xtp_id(&self, xtp_id: u64) -> Option<OrderSnapshot> {
+        let orders = self.orders.lock().unwrap();
+        orders.by_xtp_id.get(&xtp_id).map(|o| o.snapshot.clone())
+    }
+
+    pub fn all(&self) -> Vec<OrderSnapshot> {
+        let orders = self.orders.lock().unwrap();
+        orders
+            .by_xtp_id
+            .values()
+            .map(|o| o.snapshot.clone())
+            .collect()
+    }
+
+    pub fn open(&self) -> Vec<OrderSnapshot> {
+        let orders = self.orders.lock().unwrap();
+        orders
+            .by_xtp_id
+            .values()
+            .filter(|o| !o.snapshot.state.is_terminal())
+            .map(|o| o.snapshot.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XTP_ID: u64 = 7;
+
+    fn buy(quantity: i64) -> OrderRequest {
+        OrderRequest::limit("600036", Venue::SH, Side::Buy, 10., quantity)
+    }
+
+    fn report(state: OrderState, qty_traded: i64, qty_left: i64) -> OrderUpdate {
+        OrderUpdate {
+            state: Some(state),
+            qty_traded,
+            qty_left,
+        }
+    }
+
+    fn trade(exec_id: &str, quantity: i64) -> Trade {
+        Trade {
+            exec_id: exec_id.to_string(),
+            price: 10.,
+            quantity,
+        }
+    }
+
+    // As the order manager knows an order placed elsewhere.
+    fn unknown() -> Option<Order> {
+        let mut order = Order::from_request(0, XTP_ID, &buy(1000));
+        order.snapshot.client_id = None;
+        Some(order)
+    }
+
+    fn apply_report(manager: &OrderManager, update: OrderUpdate) {
+        manager
+            .orders
+            .lock()
+            .unwrap()
+            .apply_order(XTP_ID, unknown, &update);
+    }
+
+    fn apply_trade(manager: &OrderManager, trade: Trade) {
+        manager.orders.lock().unwrap().apply_trade(XTP_ID, trade);
+    }
+
+    fn quantities(manager: &OrderManager) -> (OrderState, i64, i64) {
+        let order = manager.by_xtp_id(XTP_ID).unwrap();
+        (order.state, order.filled_quantity, order.leaves_quantity)
+    }
+
+    #[test]
+    fn states_only_move_forward() {
+        use OrderState::*;
+        assert!(PendingNew.can_become(New));
+        assert!(PendingNew.can_become(Filled));
+        assert!(New.can_become(PartiallyFilled));
+        assert!(PartiallyFilled.can_become(PartiallyFilled));
+        assert!(PartiallyFilled.can_become(Cancelled));
+        assert!(!New.can_become(PendingNew));
+        assert!(!PartiallyFilled.can_become(New));
+        for &terminal in &[Filled, Cancelled, Rejected] {
+            assert!(terminal.is_terminal());
+            assert!(!terminal.can_become(Filled));
+            assert!(!terminal.can_become(Cancelled));
+        }
+    }
+
+    #[test]
+    fn counts_each_trade_once() {
+        let manager = OrderManager::default();
+        manager.on_submit(1, XTP_ID, &buy(1000));
+        apply_trade(&manager, trade("1", 300));
+        apply_trade(&manager, trade("1", 300));
+        assert_eq!(
+            quantities(&manager),
+            (OrderState::PartiallyFilled, 300, 700)
+        );
+        apply_trade(&manager, trade("2", 700));
+        assert_eq!(quantities(&manager), (OrderState::Filled, 1000, 0));
+    }
+
+    #[test]
+    fn trades_before_the_insert_returns_are_kept() {
+        let manager = OrderManager::default();
+        apply_trade(&manager, trade("1", 400));
+        assert!(manager.by_xtp_id(XTP_ID).is_none());
+        manager.on_submit(1, XTP_ID, &buy(1000));
+        assert_eq!(
+            quantities(&manager),
+            (OrderState::PartiallyFilled, 400, 600)
+        );
+        assert_eq!(manager.get(1).unwrap().avg_price, 10.);
+    }
+
+    #[test]
+    fn reports_behind_the_trades_keep_the_leaves() {
+        let manager = OrderManager::default();
+        manager.on_submit(1, XTP_ID, &buy(1000));
+        apply_trade(&manager, trade("1", 300));
+        // Sent before the trade report, and already out of date.
+        apply_report(&manager, report(OrderState::New, 0, 1000));
+        assert_eq!(
+            quantities(&manager),
+            (OrderState::PartiallyFilled, 300, 700)
+        );
+        apply_report(&manager, report(OrderState::PartiallyFilled, 200, 800));
+        assert_eq!(
+            quantities(&manager),
+            (OrderState::PartiallyFilled, 300, 700)
+        );
+        apply_report(&manager, report(OrderState::PartiallyFilled, 500, 500));
+        assert_eq!(
+            quantities(&manager),
+            (OrderState::PartiallyFilled, 500, 500)
+        );
+    }
+
+    #[test]
+    fn final_report_before_the_trades() {
+        let manager = OrderManager::default();
+        manager.on_submit(1, XTP_ID, &buy(1000));
+        apply_report(&manager, report(OrderState::Cancelled, 300, 0));
+        assert_eq!(quantities(&manager), (OrderState::Cancelled, 300, 0));
+        apply_trade(&manager, trade("1", 300));
+        assert_eq!(quantities(&manager), (OrderState::Cancelled, 300, 0));
+        assert_eq!(manager.get(1).unwrap().avg_price, 10.);
+        assert!(manager.open().is_empty());
+    }
+
+    #[test]
+    fn report_before_the_insert_returns() {
+        let manager = OrderManager::default();
+        apply_report(&manager, report(OrderState::New, 0, 1000));
+        assert_eq!(manager.by_xtp_id(XTP_ID).unwrap().client_id, None);
+        manager.on_submit(1, XTP_ID, &buy(1000));
+        let order = manager.get(1).unwrap();
+        assert_eq!(order.client_id, Some(1));
+        assert_eq!(order.state, OrderState::New);
+    }
+}
@@ -0,0 +1,113 @@
+use super::event::XTPEvent;
+use super::quote_event::QuoteEvent;
+use super::trader_event::TraderEvent;
+use crate::market::Venue;
+use crate::portfolio::{Portfolio, Position, Side};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use xtp::{XTPMarketType, XTPQueryStkPositionRsp, XTPSideType};
+
+/// Keeps the shared `Portfolio` up to date from fills and quotes.
+#[derive(Default)]
+pub(crate) struct PortfolioTracker {
+    portfolio: Mutex<Portfolio>,
+    // Trade reports are resent after a reconnect.
+    exec_ids: Mutex<HashSet<String>>,
+}
+
+impl PortfolioTracker {
+    /// Returns the position a fill changed.
+    pub fn apply(&self, event: &XTPEvent) -> Option<Position> {
+        match event {
+            XTPEvent::MarketData(QuoteEvent::Quote(quote)) => {
+                let mut portfolio = self.portfolio.lock().unwrap();
+                portfolio.roll_day(trading_day(quote.data_time));
+                portfolio.on_price(
+                    quote.instrument.venue,
+                    &quote.instrument.ticker,
+                    quote.last_price.to_f64(),
+                );
+                None
+            }
+            XTPEvent::Trader(TraderEvent::TradeEvent { trade_info, .. }) => {
+                if !self
+                    .exec_ids
+                    .lock()
+                    .unwrap()
+                    .insert(trade_info.exec_id.clone())
+                {
+                    return None;
+                }
+                let (venue, side) = match (venue_of(trade_info.market), side_of(trade_info.side)) {
+                    (Venue::Unknown, _) | (_, None) => return None,
+                    (venue, Some(side)) => (venue, side),
+                };
+                let mut portfolio = self.portfolio.lock().unwrap();
+                portfolio.roll_day(trading_day(trade_info.trade_time));
+                portfolio.on_fill(
+                    venue,
+                    &trade_info.ticker,
+                    side,
+                    trade_info.quantity,
+                    trade_info.price,
+                );
+                portfolio.position(venue, &trade_info.ticker).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reconcile(&self, positions: &[XTPQueryStkPositionRsp]) {
+        let mut portfolio = self.portfolio.lock().unwrap();
+        let mut reported = HashSet::new();
+        for position in positions {
+            let venue = venue_of(position.market);
+            if venue != Venue::Unknown {
+                portfolio.reconcile(
+                    venue,
+                    &position.ticker,
+                    position.total_qty,
+                    position.sellable_qty,
+                    position.avg_price,
+                );
+                reported.insert((venue, position.ticker.clone()));
+            }
+        }
+        portfolio.close_unreported(&reported);
+    }
+
+    pub fn position(&self, venue: Venue, ticker: &str) -> Option<Position> {
+        let portfolio = self.portfolio.lock().unwrap();
+        portfolio.position(venue, ticker).cloned()
+    }
+
+    pub fn total_pnl(&self) -> f64 {
+        let portfolio = self.portfolio.lock().unwrap();
+        portfolio.realized_pnl() + portfolio.unrealized_pnl()
+    }
+
+    pub fn snapshot(&self) -> Portfolio {
+        self.portfolio.lock().unwrap().clone()
+    }
+}
+
+pub(crate) fn venue_of(market: XTPMarketType) -> Venue {
+    match market {
+        XTPMarketType::SHA => Venue::SH,
+        XTPMarketType::SZA => Venue::SZ,
+        _ => Venue::Unknown,
+    }
+}
+
+pub(crate) fn side_of(side: XTPSideType) -> Option<Side> {
+    match side {
+        XTPSideType::Buy => Some(Side::Buy),
+        XTPSideType::Sell => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+/// YYYYMMDD of an XTP YYYYMMDDHHMMSSsss timestamp.
+pub(crate) fn trading_day(time: i64) -> u32 {
+    (time / 1_000_000_000) as u32
+}
@@ -0,0 +1,395 @@
+use super::event::XTPEvent;
+use super::quote_event::QuoteEvent;
+use crate::market::Instrument;
+use crate::{Error, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::sync::mpsc;
+
+/// What to do when an event arrives while its channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    DropOldest,
+    DropNewest,
+    /// Replace the queued snapshot of the same ticker, otherwise drop oldest.
+    ConflateLatest,
+    /// Block the XTP callback thread until the run loop catches up.
+    Block,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelConfig {
+    pub capacity: usize,
+    pub policy: BackpressurePolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// QSpi to run loop.
+    pub quote: ChannelConfig,
+    /// TSpi to run loop.
+    pub trader: ChannelConfig,
+    /// Run loop to each strategy. A strategy lagging behind by more than
+    /// this gets `RecvError::Lagged`.
+    pub strategy_capacity: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            quote: ChannelConfig {
+                capacity: 4096,
+                policy: BackpressurePolicy::DropOldest,
+            },
+            trader: ChannelConfig {
+                capacity: 4096,
+                policy: BackpressurePolicy::Block,
+            },
+            strategy_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DroppedEvents {
+    pub quote: u64,
+    pub trader: u64,
+}
+
+impl QueueConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.quote.capacity < 1 || self.trader.capacity < 1 || self.strategy_capacity < 1 {
+            return Err(Error::Config(
+                "Channel capacities must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Bounded queue between an XTP callback thread and the run loop, applying
+/// the configured backpressure policy on push.
+pub(crate) struct EventQueue {
+    config: ChannelConfig,
+    buf: Mutex<Buffer>,
+    not_full: Condvar,
+    wake: Mutex<mpsc::Sender<()>>,
+    dropped: AtomicU64,
+}
+
+pub(crate) struct QueueReceiver {
+    queue: Arc<EventQueue>,
+    wake: mpsc::Receiver<()>,
+}
+
+type ConflationKey = (u8, Instrument);
+
+#[derive(Default)]
+struct Buffer {
+    events: VecDeque<XTPEvent>,
+    // Sequence number of the front event, each event keeps its number while
+    // queued.
+    head: u64,
+    // Sequence number of the queued snapshot of each key, with
+    // `ConflateLatest` only.
+    latest: HashMap<ConflationKey, u64>,
+}
+
+impl Buffer {
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn push_back(&mut self, event: XTPEvent, conflate: bool) {
+        if conflate {
+            if let Some(key) = conflation_key(&event) {
+                let seq = self.head + self.events.len() as u64;
+                self.latest.insert(key, seq);
+            }
+        }
+        self.events.push_back(event);
+    }
+
+    fn pop_front(&mut self) -> Option<XTPEvent> {
+        let event = self.events.pop_front()?;
+        if !self.latest.is_empty() {
+            if let Some(key) = conflation_key(&event) {
+                if self.latest.get(&key) == Some(&self.head) {
+                    self.latest.remove(&key);
+                }
+            }
+        }
+        self.head += 1;
+        Some(event)
+    }
+
+    // Replace the queued snapshot with the same key, handing `event` back
+    // when there is none.
+    fn conflate(&mut self, event: XTPEvent) -> Option<XTPEvent> {
+        let seq = match conflation_key(&event).and_then(|key| self.latest.get(&key)) {
+            Some(&seq) => seq,
+            None => return Some(event),
+        };
+        self.events[(seq - self.head) as usize] = event;
+        None
+    }
+}
+
+impl EventQueue {
+    pub fn new(config: ChannelConfig) -> Result<(Arc<EventQueue>, QueueReceiver)> {
+        // A full queue would never get below a capacity of zero.
+        if config.capacity < 1 {
+            return Err(Error::Config(
+                "Channel capacity must be at least 1".to_string(),
+            ));
+        }
+        // A single pending wakeup is enough, the receiver drains everything
+        // queued once woken.
+        let (tx, rx) = mpsc::channel(1);
+        let queue = Arc::new(EventQueue {
+            config,
+            buf: Mutex::new(Buffer::default()),
+            not_full: Condvar::new(),
+            wake: Mutex::new(tx),
+            dropped: AtomicU64::new(0),
+        });
+        let receiver = QueueReceiver {
+            queue: queue.clone(),
+            wake: rx,
+        };
+        Ok((queue, receiver))
+    }
+
+    pub fn push(&self, event: XTPEvent) {
+        let mut buf = self.buf.lock().unwrap();
+        let conflate = self.config.policy == BackpressurePolicy::ConflateLatest;
+
+        let event = if conflate {
+            match buf.conflate(event) {
+                Some(event) => event,
+                None => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        } else {
+            event
+        };
+
+        while buf.len() >= self.config.capacity {
+            match self.config.policy {
+                BackpressurePolicy::DropOldest | BackpressurePolicy::ConflateLatest => {
+                    buf.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                BackpressurePolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                BackpressurePolicy::Block => buf = self.not_full.wait(buf).unwrap(),
+            }
+        }
+
+        buf.push_back(event, conflate);
+        drop(buf);
+        self.notify();
+    }
+
+    /// Enqueue regardless of capacity, for rare events that must not be lost
+    /// such as connection state changes.
+    pub fn force_push(&self, event: XTPEvent) {
+        let conflate = self.config.policy == BackpressurePolicy::ConflateLatest;
+        self.buf.lock().unwrap().push_back(event, conflate);
+        self.notify();
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn pop(&self) -> Option<XTPEvent> {
+        let event = self.buf.lock().unwrap().pop_front();
+        if event.is_some() {
+            self.not_full.notify_one();
+        }
+        event
+    }
+
+    fn notify(&self) {
+        let _ = self.wake.lock().unwrap().try_send(());
+    }
+}
+
+impl QueueReceiver {
+    pub async fn recv(&mut self) -> Option<XTPEvent> {
+        loop {
+            if let Some(event) = self.queue.pop() {
+                return Some(event);
+            }
+            self.wake.recv().await?;
+        }
+    }
+}
+
+// Only full snapshots can be conflated, tick-by-tick data is incremental.
+fn conflation_key(event: &XTPEvent) -> Option<ConflationKey> {
+    match event {
+        XTPEvent::MarketData(QuoteEvent::Quote(quote)) => Some((0, quote.instrument.clone())),
+        XTPEvent::MarketData(QuoteEvent::OrderBook(ob)) => Some((1, ob.instrument.clone())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::{Price, Quote, Venue};
+    use std::thread;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn config(capacity: usize, policy: BackpressurePolicy) -> ChannelConfig {
+        ChannelConfig { capacity, policy }
+    }
+
+    fn quote(ticker: &str, last_price: f64) -> XTPEvent {
+        XTPEvent::MarketData(QuoteEvent::Quote(Quote {
+            instrument: Instrument::new(Venue::SH, ticker),
+            data_time: 20200102093000000,
+            last_price: Price::from_f64(last_price),
+            pre_close_price: Price::ZERO,
+            open_price: Price::ZERO,
+            high_price: Price::ZERO,
+            low_price: Price::ZERO,
+            upper_limit_price: Price::ZERO,
+            lower_limit_price: Price::ZERO,
+            volume: 0,
+            turnover: 0.,
+            bids: vec![],
+            asks: vec![],
+            bid1_orders: vec![],
+            bid1_order_count: 0,
+            ask1_orders: vec![],
+            ask1_order_count: 0,
+        }))
+    }
+
+    fn last_price(event: &XTPEvent) -> (String, f64) {
+        match event {
+            XTPEvent::MarketData(QuoteEvent::Quote(quote)) => {
+                (quote.instrument.ticker.clone(), quote.last_price.to_f64())
+            }
+            _ => panic!("Not a quote: {:?}", event),
+        }
+    }
+
+    fn drain(queue: &EventQueue) -> Vec<(String, f64)> {
+        let mut prices = vec![];
+        while let Some(event) = queue.pop() {
+            prices.push(last_price(&event));
+        }
+        prices
+    }
+
+    #[test]
+    fn zero_capacity_is_rejected() {
+        assert!(EventQueue::new(config(0, BackpressurePolicy::Block)).is_err());
+        let mut queues = QueueConfig::default();
+        queues.strategy_capacity = 0;
+        assert!(queues.validate().is_err());
+        assert!(QueueConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest() {
+        let (queue, _rx) = EventQueue::new(config(2, BackpressurePolicy::DropOldest)).unwrap();
+        for price in &[1., 2., 3.] {
+            queue.push(quote("600036", *price));
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(
+            drain(&queue),
+            vec![("600036".to_string(), 2.), ("600036".to_string(), 3.)]
+        );
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_oldest() {
+        let (queue, _rx) = EventQueue::new(config(2, BackpressurePolicy::DropNewest)).unwrap();
+        for price in &[1., 2., 3.] {
+            queue.push(quote("600036", *price));
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(
+            drain(&queue),
+            vec![("600036".to_string(), 1.), ("600036".to_string(), 2.)]
+        );
+    }
+
+    #[test]
+    fn conflate_latest_replaces_in_place() {
+        let (queue, _rx) = EventQueue::new(config(8, BackpressurePolicy::ConflateLatest)).unwrap();
+        queue.push(quote("600036", 1.));
+        queue.push(quote("000001", 10.));
+        queue.push(quote("600036", 2.));
+        queue.push(XTPEvent::Timer(Instant::now()));
+        queue.push(quote("600036", 3.));
+        assert_eq!(queue.dropped(), 2);
+
+        assert_eq!(
+            last_price(&queue.pop().unwrap()),
+            ("600036".to_string(), 3.)
+        );
+        assert_eq!(
+            last_price(&queue.pop().unwrap()),
+            ("000001".to_string(), 10.)
+        );
+        assert!(matches!(queue.pop(), Some(XTPEvent::Timer(_))));
+        assert!(queue.pop().is_none());
+
+        // Once the snapshot left the queue, the next one is queued again.
+        queue.push(quote("600036", 4.));
+        queue.push(quote("600036", 5.));
+        assert_eq!(drain(&queue), vec![("600036".to_string(), 5.)]);
+    }
+
+    #[test]
+    fn conflate_latest_drops_oldest_when_full() {
+        let (queue, _rx) = EventQueue::new(config(2, BackpressurePolicy::ConflateLatest)).unwrap();
+        queue.push(quote("600036", 1.));
+        queue.push(quote("000001", 10.));
+        queue.push(quote("600000", 20.));
+        // 600036 was dropped to make room, so it is queued anew.
+        queue.push(quote("600036", 2.));
+        queue.push(quote("600000", 21.));
+        assert_eq!(
+            drain(&queue),
+            vec![("600000".to_string(), 21.), ("600036".to_string(), 2.)]
+        );
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (queue, _rx) = EventQueue::new(config(1, BackpressurePolicy::Block)).unwrap();
+        queue.push(quote("600036", 1.));
+
+        let pusher = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(quote("600036", 2.)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.buf.lock().unwrap().len(), 1);
+
+        assert_eq!(
+            last_price(&queue.pop().unwrap()),
+            ("600036".to_string(), 1.)
+        );
+        pusher.join().unwrap();
+        assert_eq!(drain(&queue), vec![("600036".to_string(), 2.)]);
+        assert_eq!(queue.dropped(), 0);
+    }
+}
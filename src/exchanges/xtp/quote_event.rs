@@ -1,12 +1,115 @@
-use xtp::XTPMarketDataStruct;
+use super::subscriptions::SubscriptionKind;
+use crate::bars::Bar;
+use crate::market::{Entrust, Instrument, OrderBookL2, Quote, Trade};
 
 #[derive(Debug, Clone)]
 pub enum QuoteEvent {
-    MarketDepth {
-        market_data: XTPMarketDataStruct,
-        bid1_qty: Vec<i64>,
-        max_bid1_count: i32,
-        ask1_qty: Vec<i64>,
-        max_ask1_count: i32,
-    },
+    /// A depth snapshot, for market data subscribers.
+    Quote(Quote),
+    /// Tick-by-tick trades and order entries, for tick-by-tick subscribers.
+    Trade(Trade),
+    Entrust(Entrust),
+    OrderBook(OrderBookL2),
+    /// A bar closed, for the strategies subscribed to its bars, see
+    /// `ExchangeHandle::subscribe_bars`.
+    Bar(Bar),
+    /// Sent to strategies in conflated delivery mode in place of depth
+    /// updates, see `DeliveryMode::Conflated`.
+    SnapshotsUpdated,
+}
+
+impl QuoteEvent {
+    /// Exchange time, YYYYMMDDHHMMSSsss, of everything but bars and
+    /// `SnapshotsUpdated`.
+    pub fn data_time(&self) -> Option<i64> {
+        match self {
+            QuoteEvent::Quote(quote) => Some(quote.data_time),
+            QuoteEvent::Trade(trade) => Some(trade.data_time),
+            QuoteEvent::Entrust(entrust) => Some(entrust.data_time),
+            QuoteEvent::OrderBook(ob) => Some(ob.data_time),
+            QuoteEvent::Bar(_) | QuoteEvent::SnapshotsUpdated => None,
+        }
+    }
+
+    // What strategies subscribe to for this event.
+    pub(crate) fn topic(&self) -> Option<(SubscriptionKind, &Instrument)> {
+        match self {
+            QuoteEvent::Quote(quote) => Some((SubscriptionKind::MarketData, &quote.instrument)),
+            QuoteEvent::Trade(trade) => Some((SubscriptionKind::TickByTick, &trade.instrument)),
+            QuoteEvent::Entrust(entrust) => {
+                Some((SubscriptionKind::TickByTick, &entrust.instrument))
+            }
+            QuoteEvent::OrderBook(ob) => Some((SubscriptionKind::OrderBook, &ob.instrument)),
+            QuoteEvent::Bar(_) | QuoteEvent::SnapshotsUpdated => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::{EntrustType, Price, TradeFlag, Venue};
+
+    fn instrument() -> Instrument {
+        Instrument::new(Venue::SZ, "000001")
+    }
+
+    #[test]
+    fn tick_by_tick_events_go_to_their_subscribers() {
+        let trade = QuoteEvent::Trade(Trade {
+            instrument: instrument(),
+            data_time: 20200102093000010,
+            channel_no: 1,
+            seq: 1,
+            price: Price::from_f64(10.),
+            quantity: 100,
+            amount: 1000.,
+            bid_no: 1,
+            ask_no: 2,
+            flag: TradeFlag::BuyerInitiated,
+        });
+        let entrust = QuoteEvent::Entrust(Entrust {
+            instrument: instrument(),
+            data_time: 20200102093000020,
+            channel_no: 1,
+            seq: 2,
+            price: Price::from_f64(10.),
+            quantity: 100,
+            side: None,
+            entrust_type: EntrustType::Limit,
+        });
+        let book = QuoteEvent::OrderBook(OrderBookL2 {
+            instrument: instrument(),
+            data_time: 20200102093000030,
+            last_price: Price::from_f64(10.),
+            volume: 100,
+            turnover: 1000.,
+            trades_count: 1,
+            bids: vec![],
+            asks: vec![],
+        });
+
+        let instrument = instrument();
+        assert_eq!(
+            trade.topic(),
+            Some((SubscriptionKind::TickByTick, &instrument))
+        );
+        assert_eq!(trade.data_time(), Some(20200102093000010));
+        assert_eq!(
+            entrust.topic(),
+            Some((SubscriptionKind::TickByTick, &instrument))
+        );
+        assert_eq!(entrust.data_time(), Some(20200102093000020));
+        assert_eq!(
+            book.topic(),
+            Some((SubscriptionKind::OrderBook, &instrument))
+        );
+        assert_eq!(book.data_time(), Some(20200102093000030));
+    }
+
+    #[test]
+    fn snapshot_notices_have_no_topic() {
+        assert_eq!(QuoteEvent::SnapshotsUpdated.topic(), None);
+        assert_eq!(QuoteEvent::SnapshotsUpdated.data_time(), None);
+    }
 }
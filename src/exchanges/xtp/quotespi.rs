@@ -1,21 +1,39 @@
+use super::convert;
+use super::correlation::{QuoteQueries, QUOTE_REQUEST};
+use super::event::{ConnectionState, Session, XTPEvent};
+use super::queue::EventQueue;
 use super::quote_event::QuoteEvent;
+use super::snapshot::Snapshots;
 use log::{error, info, warn};
-use std::sync::Mutex;
-use tokio::sync::mpsc::Sender;
+use std::sync::Arc;
 use xtp::{
-    OrderBookStruct, QuoteSpi, XTPMarketDataStruct, XTPRspInfoStruct, XTPSpecificTickerStruct,
-    XTPTickByTickStruct,
+    OrderBookStruct, QuoteSpi, XTPMarketDataStruct, XTPQuoteStaticInfo, XTPRspInfoStruct,
+    XTPSpecificTickerStruct, XTPTickByTickStruct, XTPTickerPriceInfo,
 };
 
 type XTPST = XTPSpecificTickerStruct;
 type XTPRI = XTPRspInfoStruct;
 pub struct QSpi {
-    tx: Mutex<Sender<QuoteEvent>>,
+    queue: Arc<EventQueue>,
+    snapshots: Arc<Snapshots>,
+    queries: Arc<QuoteQueries>,
 }
 
 impl QSpi {
-    pub fn new(tx: Sender<QuoteEvent>) -> Self {
-        QSpi { tx: Mutex::new(tx) }
+    pub fn new(
+        queue: Arc<EventQueue>,
+        snapshots: Arc<Snapshots>,
+        queries: Arc<QuoteQueries>,
+    ) -> Self {
+        QSpi {
+            queue,
+            snapshots,
+            queries,
+        }
+    }
+
+    fn send(&self, event: XTPEvent) {
+        self.queue.push(event);
     }
 }
 
@@ -25,7 +43,12 @@ impl QuoteSpi for QSpi {
     }
 
     fn on_disconnected(&self, reason: i32) {
-        warn!("Disconnected, reason: {}", reason)
+        warn!("Disconnected, reason: {}", reason);
+        let state = ConnectionState::Disconnected {
+            session: Session::Quote,
+            reason,
+        };
+        self.queue.force_push(XTPEvent::Connection(state));
     }
 
     fn on_sub_market_data(&self, ticker: XTPST, error_info: XTPRI, is_last: bool) {
@@ -58,21 +81,45 @@ impl QuoteSpi for QSpi {
         //     "Market Depth: {:?}, {:?}, {}, {:?}, {}",
         //     market_data, bid1_qty, max_bid1_count, ask1_qty, max_ask1_count
         // );
-        if let Err(e) = self.tx.lock().unwrap().try_send(QuoteEvent::MarketDepth {
-            market_data,
-            bid1_qty: bid1_qty.to_owned(),
+        let quote = convert::quote(
+            &market_data,
+            bid1_qty,
             max_bid1_count,
-            ask1_qty: ask1_qty.to_owned(),
+            ask1_qty,
             max_ask1_count,
-        }) {
-            error!("Buffer full {:?}", e);
-        }
+        );
+        self.snapshots.update(&quote);
+        self.send(XTPEvent::MarketData(QuoteEvent::Quote(quote)));
     }
+
     fn on_tick_by_tick(&self, tbt_data: XTPTickByTickStruct) {
-        info!("Tick by tick: {:?}", tbt_data);
+        self.send(XTPEvent::MarketData(convert::tick_by_tick(&tbt_data)));
+    }
+
+    fn on_query_all_tickers(
+        &self,
+        ticker_info: XTPQuoteStaticInfo,
+        error_info: XTPRI,
+        is_last: bool,
+    ) {
+        self.queries
+            .tickers
+            .respond(QUOTE_REQUEST, ticker_info, &error_info, is_last);
+    }
+
+    fn on_query_tickers_price_info(
+        &self,
+        ticker_info: XTPTickerPriceInfo,
+        error_info: XTPRI,
+        is_last: bool,
+    ) {
+        self.queries
+            .prices
+            .respond(QUOTE_REQUEST, ticker_info, &error_info, is_last);
     }
 
     fn on_order_book(&self, ob: OrderBookStruct) {
-        info!("Orderbook: {:?}", ob);
+        let book = convert::order_book(&ob);
+        self.send(XTPEvent::MarketData(QuoteEvent::OrderBook(book)));
     }
 }
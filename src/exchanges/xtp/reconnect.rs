@@ -0,0 +1,231 @@
+use super::config::{seconds, QuoteServerConfig, TraderServerConfig};
+use super::event::{ConnectionState, Session, XTPEvent};
+use super::login_error;
+use super::queue::EventQueue;
+use super::subscriptions::Subscriptions;
+use crate::{Error, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::cmp::min;
+use std::future::Future;
+use std::iter;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{task, time};
+use xtp::{QuoteApi, TraderApi, XTPProtocolType, XTPQueryOrderReq};
+
+/// The backoffs are written in seconds in a deployment file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    #[serde(deserialize_with = "seconds")]
+    pub initial_backoff: Duration,
+    #[serde(deserialize_with = "seconds")]
+    pub max_backoff: Duration,
+    /// Give up after this many failed logins, which strategies learn from
+    /// `ConnectionState::GaveUp`. `None` retries forever.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Reconnector {
+    pub policy: ReconnectPolicy,
+    pub quote: QuoteServerConfig,
+    pub trader: TraderServerConfig,
+    pub quote_api: Arc<QuoteApi>,
+    pub trader_api: Arc<TraderApi>,
+    pub session_id: Arc<AtomicU64>,
+    pub subscriptions: Arc<Subscriptions>,
+    pub quote_queue: Arc<EventQueue>,
+    pub trader_queue: Arc<EventQueue>,
+    pub quote_reconnecting: Arc<AtomicBool>,
+    pub trader_reconnecting: Arc<AtomicBool>,
+}
+
+impl Reconnector {
+    /// Start reconnecting `session` in the background, unless a reconnect for
+    /// it is already in flight.
+    pub fn spawn(&self, session: Session) {
+        let flag = match session {
+            Session::Quote => self.quote_reconnecting.clone(),
+            Session::Trader => self.trader_reconnecting.clone(),
+        };
+        if flag.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.reconnect(session).await;
+            flag.store(false, Ordering::SeqCst);
+        });
+    }
+
+    async fn reconnect(&self, session: Session) {
+        let login = || {
+            // Logins block, keep them off the runtime's threads.
+            let this = self.clone();
+            async move {
+                task::spawn_blocking(move || this.login(session))
+                    .await
+                    .unwrap_or_else(|e| Err(Error::Network(e.to_string())))
+            }
+        };
+        let state = match retry(&self.policy, session, login).await {
+            Some(attempts) => {
+                info!(
+                    "{:?} session reconnected after {} attempts",
+                    session, attempts
+                );
+                ConnectionState::Connected(session)
+            }
+            None => {
+                error!("{:?} session gave up reconnecting", session);
+                ConnectionState::GaveUp(session)
+            }
+        };
+        let event = XTPEvent::Connection(state);
+        match session {
+            Session::Quote => self.quote_queue.force_push(event),
+            Session::Trader => self.trader_queue.force_push(event),
+        }
+    }
+
+    fn login(&self, session: Session) -> Result<()> {
+        match session {
+            Session::Quote => {
+                self.quote_api
+                    .login(
+                        self.quote.addr,
+                        &self.quote.username,
+                        &self.quote.password,
+                        self.quote.protocol.into(),
+                    )
+                    .map_err(|_| login_error(self.quote_api.get_api_last_error()))?;
+                self.subscriptions.replay(&*self.quote_api)?;
+            }
+            Session::Trader => {
+                let session_id = self
+                    .trader_api
+                    .login(
+                        self.trader.addr,
+                        &self.trader.username,
+                        &self.trader.password,
+                        XTPProtocolType::TCP,
+                    )
+                    .map_err(|_| login_error(self.trader_api.get_api_last_error()))?;
+                self.session_id.store(session_id, Ordering::SeqCst);
+
+                // Reports for orders that changed while we were away arrive
+                // as TraderEvent::QueryOrder.
+                let req = XTPQueryOrderReq {
+                    ticker: String::new(),
+                    begin_time: 0,
+                    end_time: 0,
+                };
+                self.trader_api
+                    .query_orders(&req, session_id, 0)
+                    .map_err(|e| Error::Network(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// How long to wait before each login attempt: doubling from the initial
+// backoff up to the maximum, for as many attempts as allowed.
+fn backoffs(policy: &ReconnectPolicy) -> impl Iterator<Item = Duration> {
+    let max_backoff = policy.max_backoff;
+    let delays = iter::successors(Some(policy.initial_backoff), move |&backoff| {
+        Some(min(backoff * 2, max_backoff))
+    });
+    delays.take(policy.max_attempts.unwrap_or(usize::max_value()))
+}
+
+// Returns how many attempts `login` took to succeed, `None` once the policy
+// gives up.
+async fn retry<F, Fut>(policy: &ReconnectPolicy, session: Session, mut login: F) -> Option<usize>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    for (attempt, backoff) in backoffs(policy).enumerate() {
+        time::delay_for(backoff).await;
+        match login().await {
+            Ok(()) => return Some(attempt + 1),
+            Err(e) => warn!("{:?} session reconnect failed: {}", session, e),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn policy(max_attempts: Option<usize>) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            max_attempts,
+        }
+    }
+
+    fn millis(policy: &ReconnectPolicy, count: usize) -> Vec<u64> {
+        backoffs(policy)
+            .take(count)
+            .map(|backoff| backoff.as_millis() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(millis(&policy(None), 6), vec![10, 20, 40, 40, 40, 40]);
+        assert_eq!(millis(&policy(Some(2)), 6), vec![10, 20]);
+        assert_eq!(millis(&policy(Some(0)), 6), Vec::<u64>::new());
+    }
+
+    #[tokio::test]
+    async fn retries_until_the_login_succeeds() {
+        let mut logins = 0;
+        let start = Instant::now();
+        let attempts = retry(&policy(Some(5)), Session::Quote, || {
+            logins += 1;
+            let result = if logins < 3 {
+                Err(Error::Network("refused".to_string()))
+            } else {
+                Ok(())
+            };
+            async move { result }
+        })
+        .await;
+        assert_eq!(attempts, Some(3));
+        assert_eq!(logins, 3);
+        // 10 + 20 + 40 milliseconds of backoff.
+        assert!(start.elapsed() >= Duration::from_millis(70));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let mut logins = 0;
+        let attempts = retry(&policy(Some(2)), Session::Trader, || {
+            logins += 1;
+            async { Err(Error::Network("refused".to_string())) }
+        })
+        .await;
+        assert_eq!(attempts, None);
+        assert_eq!(logins, 2);
+    }
+}
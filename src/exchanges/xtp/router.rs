@@ -0,0 +1,324 @@
+use super::event::XTPEvent;
+use super::order::OrderIds;
+use super::order_manager::OrderManager;
+use super::positions::{side_of, venue_of};
+use super::quote_event::QuoteEvent;
+use super::snapshot::Snapshots;
+use super::subscriptions::{SubscriptionKind, Subscriptions};
+use super::trader_event::TraderEvent;
+use super::{StrategyId, SYSTEM};
+use crate::bars::{Bar, BarHub};
+use crate::{Event, Fill};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use xtp::XTPExchangeType;
+
+/// Delivers each event only to the strategies interested in it: quotes go to
+/// the subscribers of the instrument, order reports to the strategy that
+/// placed the order. Everything else is broadcast, like the position changes
+/// the run loop sends through `broadcast`. Order reports become
+/// `Event::Order` with the state the order manager has after them, so
+/// events must be applied to it before being routed.
+pub(crate) struct Router {
+    strategies: Vec<broadcast::Sender<Event>>,
+    subscriptions: Arc<Subscriptions>,
+    order_ids: Arc<OrderIds>,
+    order_manager: Arc<OrderManager>,
+    snapshots: Arc<Snapshots>,
+    bars: Arc<BarHub>,
+}
+
+impl Router {
+    pub fn new(
+        subscriptions: Arc<Subscriptions>,
+        order_ids: Arc<OrderIds>,
+        order_manager: Arc<OrderManager>,
+        snapshots: Arc<Snapshots>,
+        bars: Arc<BarHub>,
+    ) -> Self {
+        Router {
+            strategies: vec![],
+            subscriptions,
+            order_ids,
+            order_manager,
+            snapshots,
+            bars,
+        }
+    }
+
+    pub fn add_strategy(&mut self, capacity: usize) -> (StrategyId, broadcast::Receiver<Event>) {
+        let (tx, rx) = broadcast::channel(capacity);
+        self.strategies.push(tx);
+        (self.strategies.len() - 1, rx)
+    }
+
+    pub fn route(&self, event: XTPEvent) {
+        match event {
+            XTPEvent::MarketData(quote) => {
+                let (kind, instrument) = match quote.topic() {
+                    Some(topic) => topic,
+                    None => return,
+                };
+                let exchange_id = XTPExchangeType::from(instrument.venue);
+                let ticker = &instrument.ticker;
+                for id in self.subscriptions.subscribers(kind, exchange_id, ticker) {
+                    if kind == SubscriptionKind::MarketData && self.snapshots.is_conflated(id) {
+                        if self.snapshots.mark_updated(id, instrument) {
+                            self.send(id, Event::MarketData(QuoteEvent::SnapshotsUpdated));
+                        }
+                    } else {
+                        self.send(id, Event::MarketData(quote.clone()));
+                    }
+                }
+                self.route_bars(self.bars_of(&quote));
+            }
+            XTPEvent::Trader(trader) => {
+                let owner = self.owner(&trader);
+                let event = match self.event_of(trader) {
+                    Some(event) => event,
+                    None => return,
+                };
+                match owner {
+                    Some(id) => self.send(id, event),
+                    None => self.broadcast(event),
+                }
+            }
+            XTPEvent::Timer(now) => {
+                self.route_bars(self.bars.on_timer(now));
+                self.broadcast(Event::Timer(now));
+            }
+            XTPEvent::Connection(state) => self.broadcast(Event::Connection(state)),
+        }
+    }
+
+    // What strategies see of a trader report. Position and asset query
+    // results are only for the handle queries, trades pixiu does not model,
+    // like margin trades, are left out.
+    fn event_of(&self, event: TraderEvent) -> Option<Event> {
+        match event {
+            TraderEvent::OrderEvent { order_info, .. } => self
+                .order_manager
+                .by_xtp_id(order_info.order_xtp_id)
+                .map(Event::Order),
+            TraderEvent::QueryOrder { order_info, .. } => self
+                .order_manager
+                .by_xtp_id(order_info.order_xtp_id)
+                .map(Event::Order),
+            TraderEvent::TradeEvent { trade_info, .. } => Some(Event::Fill(Fill {
+                client_id: self
+                    .order_manager
+                    .by_xtp_id(trade_info.order_xtp_id)
+                    .and_then(|order| order.client_id),
+                exec_id: trade_info.exec_id,
+                ticker: trade_info.ticker,
+                venue: venue_of(trade_info.market),
+                side: side_of(trade_info.side)?,
+                price: trade_info.price,
+                quantity: trade_info.quantity,
+                fee: None,
+                trade_time: trade_info.trade_time,
+            })),
+            TraderEvent::CancelOrderError {
+                cancel_info,
+                error_info,
+                ..
+            } => Some(Event::CancelRejected {
+                client_id: self.order_ids.client_id(cancel_info.order_xtp_id),
+                error_id: error_info.error_id,
+                error_msg: error_info.error_msg,
+            }),
+            TraderEvent::QueryTrade { .. }
+            | TraderEvent::QueryPosition { .. }
+            | TraderEvent::QueryAsset { .. } => None,
+        }
+    }
+
+    // Bars closed by a quote. Subscribing to bars subscribes the router to
+    // the underlying market data, see `BAR_SUBSCRIBER`.
+    fn bars_of(&self, quote: &QuoteEvent) -> Vec<(Bar, Vec<StrategyId>)> {
+        match quote {
+            QuoteEvent::Quote(quote) => self.bars.on_depth(
+                quote.instrument.venue,
+                &quote.instrument.ticker,
+                quote.data_time,
+                quote.last_price.to_f64(),
+                quote.volume,
+                quote.turnover,
+            ),
+            QuoteEvent::Trade(trade) => self.bars.on_trade(
+                trade.instrument.venue,
+                &trade.instrument.ticker,
+                trade.data_time,
+                trade.price.to_f64(),
+                trade.quantity,
+                trade.amount,
+            ),
+            _ => vec![],
+        }
+    }
+
+    fn route_bars(&self, bars: Vec<(Bar, Vec<StrategyId>)>) {
+        for (bar, ids) in bars {
+            for id in ids {
+                self.send(id, Event::MarketData(QuoteEvent::Bar(bar.clone())));
+            }
+        }
+    }
+
+    fn send(&self, id: StrategyId, event: Event) {
+        if let Some(tx) = self.strategies.get(id) {
+            let _ = tx.send(event);
+        }
+    }
+
+    pub fn broadcast(&self, event: Event) {
+        for tx in &self.strategies {
+            let _ = tx.send(event.clone());
+        }
+    }
+
+    // Reports for orders not placed through pixiu have no owner, and are
+    // broadcast since they still affect everybody's account. So are the
+    // reports of the orders the exchange placed itself.
+    fn owner(&self, event: &TraderEvent) -> Option<StrategyId> {
+        let xtp_id = match event {
+            TraderEvent::OrderEvent { order_info, .. } => order_info.order_xtp_id,
+            TraderEvent::TradeEvent { trade_info, .. } => trade_info.order_xtp_id,
+            TraderEvent::CancelOrderError { cancel_info, .. } => cancel_info.order_xtp_id,
+            TraderEvent::QueryOrder { order_info, .. } => order_info.order_xtp_id,
+            TraderEvent::QueryTrade { trade_info, .. } => trade_info.order_xtp_id,
+            TraderEvent::QueryPosition { .. } | TraderEvent::QueryAsset { .. } => return None,
+        };
+        self.owner_of(xtp_id)
+    }
+
+    fn owner_of(&self, xtp_id: u64) -> Option<StrategyId> {
+        self.order_ids
+            .owner_of_xtp_id(xtp_id)
+            .filter(|&owner| owner != SYSTEM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::subscriptions::QuoteSession;
+    use super::*;
+    use crate::market::{Instrument, Price, Quote, Venue};
+    use failure::Fallible;
+    use tokio::sync::broadcast::TryRecvError;
+
+    struct Session;
+
+    impl QuoteSession for Session {
+        fn subscribe(
+            &self,
+            _: SubscriptionKind,
+            _: XTPExchangeType,
+            _: Option<&[&str]>,
+        ) -> Fallible<()> {
+            Ok(())
+        }
+
+        fn unsubscribe(
+            &self,
+            _: SubscriptionKind,
+            _: XTPExchangeType,
+            _: Option<&[&str]>,
+        ) -> Fallible<()> {
+            Ok(())
+        }
+    }
+
+    fn router() -> Router {
+        Router::new(
+            Arc::new(Subscriptions::default()),
+            Arc::new(OrderIds::default()),
+            Arc::new(OrderManager::default()),
+            Arc::new(Snapshots::default()),
+            Arc::new(BarHub::default()),
+        )
+    }
+
+    fn quote(venue: Venue, ticker: &str) -> XTPEvent {
+        let instrument = Instrument::new(venue, ticker);
+        XTPEvent::MarketData(QuoteEvent::Quote(Quote::new(
+            instrument,
+            20200106093000000,
+            Price::from_f64(10.),
+        )))
+    }
+
+    fn received(rx: &mut broadcast::Receiver<Event>) -> Vec<String> {
+        let mut tickers = vec![];
+        loop {
+            match rx.try_recv() {
+                Ok(Event::MarketData(QuoteEvent::Quote(quote))) => {
+                    tickers.push(quote.instrument.ticker)
+                }
+                Ok(event) => panic!("unexpected {:?}", event),
+                Err(TryRecvError::Empty) => return tickers,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn quotes_go_to_their_subscribers() {
+        let mut router = router();
+        let (first, mut first_rx) = router.add_strategy(16);
+        let (second, mut second_rx) = router.add_strategy(16);
+        let depth = SubscriptionKind::MarketData;
+        router
+            .subscriptions
+            .subscribe(&Session, first, depth, &["600036"], XTPExchangeType::SH)
+            .unwrap();
+        router
+            .subscriptions
+            .subscribe_all(&Session, second, depth, XTPExchangeType::SZ)
+            .unwrap();
+
+        router.route(quote(Venue::SH, "600036"));
+        router.route(quote(Venue::SH, "600000"));
+        router.route(quote(Venue::SZ, "000001"));
+        // Same ticker, other exchange.
+        router.route(quote(Venue::SZ, "600036"));
+
+        assert_eq!(received(&mut first_rx), vec!["600036"]);
+        assert_eq!(received(&mut second_rx), vec!["000001", "600036"]);
+    }
+
+    #[test]
+    fn reports_go_to_the_strategy_of_the_xtp_id() {
+        let mut router = router();
+        let (first, _first_rx) = router.add_strategy(16);
+        let (second, _second_rx) = router.add_strategy(16);
+
+        let a = router.order_ids.allocate(first);
+        let b = router.order_ids.allocate(second);
+        router.order_ids.set_xtp_id(a, 101);
+        router.order_ids.set_xtp_id(b, 102);
+        assert_eq!(router.owner_of(101), Some(first));
+        assert_eq!(router.owner_of(102), Some(second));
+        // Placed by another client, or before a restart, whatever its
+        // client id.
+        assert_eq!(router.owner_of(103), None);
+
+        // Not sent yet.
+        router.order_ids.allocate(first);
+        assert_eq!(router.owner_of(0), None);
+    }
+
+    #[test]
+    fn orders_of_the_exchange_have_no_owner() {
+        let mut router = router();
+        let (first, _rx) = router.add_strategy(16);
+        assert_eq!(first, 0);
+
+        let strategy = router.order_ids.allocate(first);
+        let system = router.order_ids.allocate(SYSTEM);
+        router.order_ids.set_xtp_id(strategy, 101);
+        router.order_ids.set_xtp_id(system, 102);
+        assert_eq!(router.owner_of(101), Some(first));
+        assert_eq!(router.owner_of(102), None);
+    }
+}
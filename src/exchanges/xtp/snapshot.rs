@@ -0,0 +1,142 @@
+use super::StrategyId;
+use crate::market::{Instrument, Quote, Venue};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Every depth update is delivered as `QuoteEvent::Quote`.
+    Every,
+    /// Depth updates are folded into the snapshot store, and the strategy
+    /// gets a single `QuoteEvent::SnapshotsUpdated` until it calls
+    /// `XTPExchangeHandle::updated_snapshots`.
+    Conflated,
+}
+
+/// Latest depth snapshot per ticker, plus the tickers updated since each
+/// conflated strategy last looked.
+#[derive(Default)]
+pub(crate) struct Snapshots {
+    latest: RwLock<HashMap<Instrument, Quote>>,
+    pending: Mutex<HashMap<StrategyId, HashSet<Instrument>>>,
+}
+
+impl Snapshots {
+    pub fn update(&self, quote: &Quote) {
+        self.latest
+            .write()
+            .unwrap()
+            .insert(quote.instrument.clone(), quote.clone());
+    }
+
+    pub fn get(&self, ticker: &str, venue: Venue) -> Option<Quote> {
+        let key = Instrument::new(venue, ticker);
+        self.latest.read().unwrap().get(&key).cloned()
+    }
+
+    pub fn set_delivery_mode(&self, strategy: StrategyId, mode: DeliveryMode) {
+        let mut pending = self.pending.lock().unwrap();
+        match mode {
+            DeliveryMode::Every => {
+                pending.remove(&strategy);
+            }
+            DeliveryMode::Conflated => {
+                pending.entry(strategy).or_insert_with(HashSet::new);
+            }
+        }
+    }
+
+    pub fn is_conflated(&self, strategy: StrategyId) -> bool {
+        self.pending.lock().unwrap().contains_key(&strategy)
+    }
+
+    /// Mark the ticker as updated for a conflated strategy. Returns true when
+    /// the strategy had nothing pending, i.e. it needs to be notified.
+    pub fn mark_updated(&self, strategy: StrategyId, instrument: &Instrument) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get_mut(&strategy) {
+            Some(keys) => {
+                let notify = keys.is_empty();
+                keys.insert(instrument.clone());
+                notify
+            }
+            None => false,
+        }
+    }
+
+    pub fn take_updated(&self, strategy: StrategyId) -> Vec<Quote> {
+        let keys = match self.pending.lock().unwrap().get_mut(&strategy) {
+            Some(keys) => keys.drain().collect::<Vec<_>>(),
+            None => return vec![],
+        };
+        let latest = self.latest.read().unwrap();
+        keys.iter()
+            .filter_map(|key| latest.get(key).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::Price;
+
+    fn quote(ticker: &str, last_price: f64) -> Quote {
+        Quote {
+            instrument: Instrument::new(Venue::SH, ticker),
+            data_time: 20200102093000000,
+            last_price: Price::from_f64(last_price),
+            pre_close_price: Price::ZERO,
+            open_price: Price::ZERO,
+            high_price: Price::ZERO,
+            low_price: Price::ZERO,
+            upper_limit_price: Price::ZERO,
+            lower_limit_price: Price::ZERO,
+            volume: 0,
+            turnover: 0.,
+            bids: vec![],
+            asks: vec![],
+            bid1_orders: vec![],
+            bid1_order_count: 0,
+            ask1_orders: vec![],
+            ask1_order_count: 0,
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_quote() {
+        let snapshots = Snapshots::default();
+        assert!(snapshots.get("600036", Venue::SH).is_none());
+        snapshots.update(&quote("600036", 10.));
+        snapshots.update(&quote("600036", 10.5));
+        let latest = snapshots.get("600036", Venue::SH).unwrap();
+        assert_eq!(latest.last_price, Price::from_f64(10.5));
+        assert!(snapshots.get("600036", Venue::SZ).is_none());
+    }
+
+    #[test]
+    fn conflated_strategies_are_notified_once_until_they_look() {
+        let snapshots = Snapshots::default();
+        snapshots.set_delivery_mode(1, DeliveryMode::Conflated);
+        assert!(snapshots.is_conflated(1));
+        assert!(!snapshots.is_conflated(2));
+
+        let sh = Instrument::new(Venue::SH, "600036");
+        snapshots.update(&quote("600036", 10.));
+        assert!(snapshots.mark_updated(1, &sh));
+        snapshots.update(&quote("600036", 10.5));
+        assert!(!snapshots.mark_updated(1, &sh));
+        // Strategies getting every update are never notified.
+        assert!(!snapshots.mark_updated(2, &sh));
+
+        let updated = snapshots.take_updated(1);
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].last_price, Price::from_f64(10.5));
+        assert!(snapshots.take_updated(1).is_empty());
+        assert!(snapshots.mark_updated(1, &sh));
+
+        snapshots.set_delivery_mode(1, DeliveryMode::Every);
+        assert!(!snapshots.is_conflated(1));
+        assert!(snapshots.take_updated(1).is_empty());
+    }
+}
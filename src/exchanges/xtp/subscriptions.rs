@@ -0,0 +1,539 @@
+use super::StrategyId;
+use crate::{Error, Result};
+use failure::Fallible;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use xtp::{QuoteApi, XTPExchangeType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubscriptionKind {
+    MarketData,
+    TickByTick,
+    OrderBook,
+}
+
+/// The calls the registry makes on the quote session, `QuoteApi` outside of
+/// tests. `tickers` is `None` for a subscribe-all on the exchange.
+pub(crate) trait QuoteSession {
+    fn subscribe(
+        &self,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+        tickers: Option<&[&str]>,
+    ) -> Fallible<()>;
+
+    fn unsubscribe(
+        &self,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+        tickers: Option<&[&str]>,
+    ) -> Fallible<()>;
+}
+
+impl QuoteSession for QuoteApi {
+    fn subscribe(
+        &self,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+        tickers: Option<&[&str]>,
+    ) -> Fallible<()> {
+        match (kind, tickers) {
+            (SubscriptionKind::MarketData, Some(tickers)) => {
+                self.subscribe_market_data(tickers, exchange_id)
+            }
+            (SubscriptionKind::TickByTick, Some(tickers)) => {
+                self.subscribe_tick_by_tick(tickers, exchange_id)
+            }
+            (SubscriptionKind::OrderBook, Some(tickers)) => {
+                self.subscribe_order_book(tickers, exchange_id)
+            }
+            (SubscriptionKind::MarketData, None) => self.subscribe_all_market_data(exchange_id),
+            (SubscriptionKind::TickByTick, None) => self.subscribe_all_tick_by_tick(exchange_id),
+            (SubscriptionKind::OrderBook, None) => self.subscribe_all_order_book(exchange_id),
+        }
+    }
+
+    fn unsubscribe(
+        &self,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+        tickers: Option<&[&str]>,
+    ) -> Fallible<()> {
+        match (kind, tickers) {
+            (SubscriptionKind::MarketData, Some(tickers)) => {
+                self.unsubscribe_market_data(tickers, exchange_id)
+            }
+            (SubscriptionKind::TickByTick, Some(tickers)) => {
+                self.unsubscribe_tick_by_tick(tickers, exchange_id)
+            }
+            (SubscriptionKind::OrderBook, Some(tickers)) => {
+                self.unsubscribe_order_book(tickers, exchange_id)
+            }
+            (SubscriptionKind::MarketData, None) => self.unsubscribe_all_market_data(exchange_id),
+            (SubscriptionKind::TickByTick, None) => self.unsubscribe_all_tick_by_tick(exchange_id),
+            (SubscriptionKind::OrderBook, None) => self.unsubscribe_all_order_book(exchange_id),
+        }
+    }
+}
+
+/// A single subscription on the quote session. `ticker` is `None` for a
+/// subscribe-all on the exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Topic {
+    kind: SubscriptionKind,
+    exchange_id: XTPExchangeType,
+    ticker: Option<String>,
+}
+
+// Topics sent in a single call: tickers of one kind on one exchange, or a
+// subscribe-all.
+#[derive(Debug)]
+struct Batch {
+    kind: SubscriptionKind,
+    exchange_id: XTPExchangeType,
+    tickers: Option<Vec<String>>,
+}
+
+impl Batch {
+    fn subscribe(&self, api: &dyn QuoteSession) -> Fallible<()> {
+        let tickers = self.tickers();
+        api.subscribe(
+            self.kind,
+            self.exchange_id,
+            tickers.as_ref().map(|t| &t[..]),
+        )
+    }
+
+    fn unsubscribe(&self, api: &dyn QuoteSession) -> Fallible<()> {
+        let tickers = self.tickers();
+        api.unsubscribe(
+            self.kind,
+            self.exchange_id,
+            tickers.as_ref().map(|t| &t[..]),
+        )
+    }
+
+    fn tickers(&self) -> Option<Vec<&str>> {
+        self.tickers
+            .as_ref()
+            .map(|tickers| tickers.iter().map(|t| t.as_str()).collect())
+    }
+
+    fn contains(&self, topic: &Topic) -> bool {
+        topic.kind == self.kind
+            && topic.exchange_id == self.exchange_id
+            && match (&topic.ticker, &self.tickers) {
+                (Some(ticker), Some(tickers)) => tickers.contains(ticker),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+// One batch per kind and exchange for the tickers, one per subscribe-all,
+// in the order the topics come.
+fn batches<'a>(topics: impl IntoIterator<Item = &'a Topic>) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = vec![];
+    for topic in topics {
+        let batch = batches.iter_mut().find(|b| {
+            b.kind == topic.kind
+                && b.exchange_id == topic.exchange_id
+                && b.tickers.is_some() == topic.ticker.is_some()
+        });
+        match (batch, &topic.ticker) {
+            (Some(batch), Some(ticker)) => {
+                let tickers = batch.tickers.get_or_insert_with(Vec::new);
+                if !tickers.contains(ticker) {
+                    tickers.push(ticker.clone());
+                }
+            }
+            (Some(_), None) => {}
+            (None, ticker) => batches.push(Batch {
+                kind: topic.kind,
+                exchange_id: topic.exchange_id,
+                tickers: ticker.as_ref().map(|t| vec![t.clone()]),
+            }),
+        }
+    }
+    batches
+}
+
+/// Subscriptions made through `XTPExchangeHandle`, reference counted by
+/// strategy. All strategies share one quote session, so the QuoteApi is only
+/// called when the first subscriber of a topic arrives or the last one leaves.
+/// The registry is also replayed after the quote session reconnects.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    topics: Mutex<HashMap<Topic, HashSet<StrategyId>>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        kind: SubscriptionKind,
+        tickers: &[&str],
+        exchange_id: XTPExchangeType,
+    ) -> Result<()> {
+        let topics = tickers.iter().map(|t| Topic {
+            kind,
+            exchange_id,
+            ticker: Some(t.to_string()),
+        });
+        self.add(api, strategy, topics)
+    }
+
+    pub fn unsubscribe(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        kind: SubscriptionKind,
+        tickers: &[&str],
+        exchange_id: XTPExchangeType,
+    ) -> Result<()> {
+        let topics = tickers.iter().map(|t| Topic {
+            kind,
+            exchange_id,
+            ticker: Some(t.to_string()),
+        });
+        self.remove(api, strategy, topics)
+    }
+
+    pub fn subscribe_all(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+    ) -> Result<()> {
+        let topic = Topic {
+            kind,
+            exchange_id,
+            ticker: None,
+        };
+        self.add(api, strategy, Some(topic))
+    }
+
+    pub fn unsubscribe_all(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+    ) -> Result<()> {
+        let topic = Topic {
+            kind,
+            exchange_id,
+            ticker: None,
+        };
+        self.remove(api, strategy, Some(topic))
+    }
+
+    /// Strategies subscribed to `ticker`, either directly or through a
+    /// subscribe-all on its exchange.
+    pub fn subscribers(
+        &self,
+        kind: SubscriptionKind,
+        exchange_id: XTPExchangeType,
+        ticker: &str,
+    ) -> HashSet<StrategyId> {
+        let topics = self.topics.lock().unwrap();
+        let mut subscribers = HashSet::new();
+        for t in &[Some(ticker.to_string()), None] {
+            let topic = Topic {
+                kind,
+                exchange_id,
+                ticker: t.clone(),
+            };
+            if let Some(strategies) = topics.get(&topic) {
+                subscribers.extend(strategies);
+            }
+        }
+        subscribers
+    }
+
+    pub fn replay(&self, api: &dyn QuoteSession) -> Result<()> {
+        let topics = self.topics.lock().unwrap();
+        for batch in batches(topics.keys()) {
+            batch.subscribe(api).map_err(api_error)?;
+        }
+        Ok(())
+    }
+
+    // All or nothing: when a call fails, the calls made before it for the
+    // same subscribe are rolled back and the registry is left as it was.
+    fn add(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        new: impl IntoIterator<Item = Topic>,
+    ) -> Result<()> {
+        let mut topics = self.topics.lock().unwrap();
+        let new: Vec<Topic> = new.into_iter().collect();
+        let missing = new.iter().filter(|topic| !topics.contains_key(topic));
+        let mut subscribed = vec![];
+        for batch in batches(missing) {
+            if let Err(e) = batch.subscribe(api) {
+                rollback(api, subscribed);
+                return Err(api_error(e));
+            }
+            subscribed.push(batch);
+        }
+        for topic in new {
+            topics
+                .entry(topic)
+                .or_insert_with(HashSet::new)
+                .insert(strategy);
+        }
+        Ok(())
+    }
+
+    // Topics the QuoteApi failed to unsubscribe keep their subscriber, so
+    // the registry still matches what the session is subscribed to.
+    fn remove(
+        &self,
+        api: &dyn QuoteSession,
+        strategy: StrategyId,
+        old: impl IntoIterator<Item = Topic>,
+    ) -> Result<()> {
+        let mut topics = self.topics.lock().unwrap();
+        let leaving: Vec<Topic> = old
+            .into_iter()
+            .filter(|topic| {
+                topics
+                    .get(topic)
+                    .map_or(false, |strategies| strategies.contains(&strategy))
+            })
+            .collect();
+        let last = leaving.iter().filter(|topic| topics[*topic].len() == 1);
+        let mut result = Ok(());
+        let mut failed = vec![];
+        for batch in batches(last) {
+            if let Err(e) = batch.unsubscribe(api) {
+                if result.is_ok() {
+                    result = Err(api_error(e));
+                }
+                failed.push(batch);
+            }
+        }
+        for topic in leaving {
+            if failed.iter().any(|batch| batch.contains(&topic)) {
+                continue;
+            }
+            let empty = match topics.get_mut(&topic) {
+                Some(strategies) => strategies.remove(&strategy) && strategies.is_empty(),
+                None => false,
+            };
+            if empty {
+                topics.remove(&topic);
+            }
+        }
+        result
+    }
+}
+
+fn rollback(api: &dyn QuoteSession, subscribed: Vec<Batch>) {
+    for batch in subscribed {
+        if let Err(e) = batch.unsubscribe(api) {
+            warn!("Rolling back the subscription to {:?} failed: {}", batch, e);
+        }
+    }
+}
+
+fn api_error(e: failure::Error) -> Error {
+    Error::Api(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Records the calls it gets, and fails those with `fail` among the
+    // tickers.
+    #[derive(Default)]
+    struct Session {
+        calls: RefCell<Vec<String>>,
+        fail: Option<&'static str>,
+    }
+
+    impl Session {
+        fn call(
+            &self,
+            what: &str,
+            exchange_id: XTPExchangeType,
+            tickers: Option<&[&str]>,
+        ) -> Fallible<()> {
+            let tickers = tickers.map_or("*".to_string(), |t| t.join(","));
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {:?} {}", what, exchange_id, tickers));
+            match self.fail {
+                Some(fail) if tickers.split(',').any(|t| t == fail) => {
+                    Err(failure::err_msg("refused"))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.replace(vec![])
+        }
+    }
+
+    impl QuoteSession for Session {
+        fn subscribe(
+            &self,
+            kind: SubscriptionKind,
+            exchange_id: XTPExchangeType,
+            tickers: Option<&[&str]>,
+        ) -> Fallible<()> {
+            self.call(&format!("+{:?}", kind), exchange_id, tickers)
+        }
+
+        fn unsubscribe(
+            &self,
+            kind: SubscriptionKind,
+            exchange_id: XTPExchangeType,
+            tickers: Option<&[&str]>,
+        ) -> Fallible<()> {
+            self.call(&format!("-{:?}", kind), exchange_id, tickers)
+        }
+    }
+
+    const SH: XTPExchangeType = XTPExchangeType::SH;
+    const SZ: XTPExchangeType = XTPExchangeType::SZ;
+    const DEPTH: SubscriptionKind = SubscriptionKind::MarketData;
+
+    fn subscribers(subscriptions: &Subscriptions, ticker: &str) -> Vec<StrategyId> {
+        let mut ids: Vec<_> = subscriptions
+            .subscribers(DEPTH, SH, ticker)
+            .into_iter()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn calls_the_session_once_per_subscribe() {
+        let session = Session::default();
+        let subscriptions = Subscriptions::default();
+        subscriptions
+            .subscribe(&session, 0, DEPTH, &["600036", "600000"], SH)
+            .unwrap();
+        assert_eq!(session.calls(), vec!["+MarketData SH 600036,600000"]);
+
+        // Only what nobody subscribed to yet.
+        subscriptions
+            .subscribe(&session, 1, DEPTH, &["600000", "601318"], SH)
+            .unwrap();
+        assert_eq!(session.calls(), vec!["+MarketData SH 601318"]);
+        assert_eq!(subscribers(&subscriptions, "600000"), vec![0, 1]);
+
+        // And only what the last subscriber leaves.
+        subscriptions
+            .unsubscribe(&session, 0, DEPTH, &["600036", "600000"], SH)
+            .unwrap();
+        assert_eq!(session.calls(), vec!["-MarketData SH 600036"]);
+        assert_eq!(subscribers(&subscriptions, "600036"), vec![]);
+        assert_eq!(subscribers(&subscriptions, "600000"), vec![1]);
+    }
+
+    #[test]
+    fn subscribe_all_reaches_every_ticker() {
+        let session = Session::default();
+        let subscriptions = Subscriptions::default();
+        subscriptions.subscribe_all(&session, 2, DEPTH, SH).unwrap();
+        subscriptions
+            .subscribe(&session, 0, DEPTH, &["600036"], SH)
+            .unwrap();
+        assert_eq!(
+            session.calls(),
+            vec!["+MarketData SH *", "+MarketData SH 600036"]
+        );
+        assert_eq!(subscribers(&subscriptions, "600036"), vec![0, 2]);
+        assert_eq!(subscribers(&subscriptions, "600000"), vec![2]);
+        assert!(subscriptions.subscribers(DEPTH, SZ, "000001").is_empty());
+    }
+
+    #[test]
+    fn failed_subscribe_changes_nothing() {
+        let session = Session {
+            fail: Some("000001"),
+            ..Session::default()
+        };
+        let subscriptions = Subscriptions::default();
+        let topics = vec![
+            Topic {
+                kind: DEPTH,
+                exchange_id: SH,
+                ticker: Some("600036".to_string()),
+            },
+            Topic {
+                kind: DEPTH,
+                exchange_id: SZ,
+                ticker: Some("000001".to_string()),
+            },
+        ];
+        assert!(subscriptions.add(&session, 0, topics).is_err());
+        // What went through before the failure is rolled back.
+        assert_eq!(
+            session.calls(),
+            vec![
+                "+MarketData SH 600036",
+                "+MarketData SZ 000001",
+                "-MarketData SH 600036"
+            ]
+        );
+        assert_eq!(subscribers(&subscriptions, "600036"), vec![]);
+    }
+
+    #[test]
+    fn failed_unsubscribe_keeps_the_subscriber() {
+        let subscriptions = Subscriptions::default();
+        subscriptions
+            .subscribe(&Session::default(), 0, DEPTH, &["600000", "600036"], SH)
+            .unwrap();
+
+        let session = Session {
+            fail: Some("600000"),
+            ..Session::default()
+        };
+        assert!(subscriptions
+            .unsubscribe(&session, 0, DEPTH, &["600000", "600036"], SH)
+            .is_err());
+        assert_eq!(session.calls(), vec!["-MarketData SH 600000,600036"]);
+        assert_eq!(subscribers(&subscriptions, "600000"), vec![0]);
+        assert_eq!(subscribers(&subscriptions, "600036"), vec![0]);
+    }
+
+    #[test]
+    fn replay_resubscribes_everything() {
+        let session = Session::default();
+        let subscriptions = Subscriptions::default();
+        subscriptions
+            .subscribe(&session, 0, DEPTH, &["600036", "600000"], SH)
+            .unwrap();
+        subscriptions
+            .subscribe(&session, 1, DEPTH, &["601318"], SH)
+            .unwrap();
+        subscriptions
+            .subscribe(&session, 1, SubscriptionKind::TickByTick, &["000001"], SZ)
+            .unwrap();
+        subscriptions.subscribe_all(&session, 2, DEPTH, SZ).unwrap();
+        session.calls();
+
+        subscriptions.replay(&session).unwrap();
+        let mut calls = session.calls();
+        calls.sort();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], "+MarketData SZ *");
+        assert!(calls[1].starts_with("+MarketData SH "));
+        let mut tickers: Vec<&str> = calls[1]["+MarketData SH ".len()..].split(',').collect();
+        tickers.sort();
+        assert_eq!(tickers, vec!["600000", "600036", "601318"]);
+        assert_eq!(calls[2], "+TickByTick SZ 000001");
+    }
+}
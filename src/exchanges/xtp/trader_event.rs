@@ -1,2 +1,50 @@
+use xtp::{
+    XTPOrderCancelInfo, XTPOrderInfo, XTPQueryAssetRsp, XTPQueryOrderRsp, XTPQueryStkPositionRsp,
+    XTPQueryTradeRsp, XTPRspInfoStruct, XTPTradeReport,
+};
+
 #[derive(Debug, Clone)]
-pub enum TraderEvent {}
+pub(crate) enum TraderEvent {
+    OrderEvent {
+        order_info: XTPOrderInfo,
+        error_info: XTPRspInfoStruct,
+        session_id: u64,
+    },
+    TradeEvent {
+        trade_info: XTPTradeReport,
+        session_id: u64,
+    },
+    CancelOrderError {
+        cancel_info: XTPOrderCancelInfo,
+        error_info: XTPRspInfoStruct,
+        session_id: u64,
+    },
+    QueryOrder {
+        order_info: XTPQueryOrderRsp,
+        error_info: XTPRspInfoStruct,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    },
+    QueryTrade {
+        trade_info: XTPQueryTradeRsp,
+        error_info: XTPRspInfoStruct,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    },
+    QueryPosition {
+        position: XTPQueryStkPositionRsp,
+        error_info: XTPRspInfoStruct,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    },
+    QueryAsset {
+        asset: XTPQueryAssetRsp,
+        error_info: XTPRspInfoStruct,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    },
+}
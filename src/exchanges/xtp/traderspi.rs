@@ -1,17 +1,27 @@
+use super::correlation::TraderQueries;
+use super::event::{ConnectionState, Session, XTPEvent};
+use super::queue::EventQueue;
 use super::trader_event::TraderEvent;
-use log::error;
-use tokio::sync::mpsc::Sender;
-use xtp::{TraderSpi, XTPRspInfoStruct, XTPSpecificTickerStruct};
+use log::{error, warn};
+use std::sync::Arc;
+use xtp::{
+    TraderSpi, XTPOrderCancelInfo, XTPOrderInfo, XTPQueryAssetRsp, XTPQueryOrderRsp,
+    XTPQueryStkPositionRsp, XTPQueryTradeRsp, XTPRspInfoStruct, XTPTradeReport,
+};
 
-type XTPST = XTPSpecificTickerStruct;
 type XTPRI = XTPRspInfoStruct;
 pub struct TSpi {
-    tx: Sender<TraderEvent>,
+    queue: Arc<EventQueue>,
+    queries: Arc<TraderQueries>,
 }
 
 impl TSpi {
-    pub fn new(tx: Sender<TraderEvent>) -> Self {
-        TSpi { tx }
+    pub fn new(queue: Arc<EventQueue>, queries: Arc<TraderQueries>) -> Self {
+        TSpi { queue, queries }
+    }
+
+    fn send(&self, event: TraderEvent) {
+        self.queue.push(XTPEvent::Trader(event));
     }
 }
 
@@ -19,4 +29,164 @@ impl TraderSpi for TSpi {
     fn on_error(&self, error_info: XTPRI) {
         error!("{:?}", error_info);
     }
+
+    fn on_disconnected(&self, session_id: u64, reason: i32) {
+        warn!("Disconnected, session: {}, reason: {}", session_id, reason);
+        self.queue
+            .force_push(XTPEvent::Connection(ConnectionState::Disconnected {
+                session: Session::Trader,
+                reason,
+            }));
+    }
+
+    fn on_order_event(&self, order_info: XTPOrderInfo, error_info: XTPRI, session_id: u64) {
+        self.send(TraderEvent::OrderEvent {
+            order_info,
+            error_info,
+            session_id,
+        });
+    }
+
+    fn on_trade_event(&self, trade_info: XTPTradeReport, session_id: u64) {
+        self.send(TraderEvent::TradeEvent {
+            trade_info,
+            session_id,
+        });
+    }
+
+    fn on_cancel_order_error(
+        &self,
+        cancel_info: XTPOrderCancelInfo,
+        error_info: XTPRI,
+        session_id: u64,
+    ) {
+        self.send(TraderEvent::CancelOrderError {
+            cancel_info,
+            error_info,
+            session_id,
+        });
+    }
+
+    fn on_query_order(
+        &self,
+        order_info: XTPQueryOrderRsp,
+        error_info: XTPRI,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    ) {
+        if self.queries.orders.contains(request_id) {
+            self.queries
+                .orders
+                .respond(request_id, order_info, &error_info, is_last);
+            return;
+        }
+        self.send(TraderEvent::QueryOrder {
+            order_info,
+            error_info,
+            request_id,
+            is_last,
+            session_id,
+        });
+    }
+
+    fn on_query_trade(
+        &self,
+        trade_info: XTPQueryTradeRsp,
+        error_info: XTPRI,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    ) {
+        if self.queries.trades.contains(request_id) {
+            self.queries
+                .trades
+                .respond(request_id, trade_info, &error_info, is_last);
+            return;
+        }
+        self.send(TraderEvent::QueryTrade {
+            trade_info,
+            error_info,
+            request_id,
+            is_last,
+            session_id,
+        });
+    }
+
+    fn on_query_position(
+        &self,
+        position: XTPQueryStkPositionRsp,
+        error_info: XTPRI,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    ) {
+        if self.queries.positions.contains(request_id) {
+            self.queries
+                .positions
+                .respond(request_id, position, &error_info, is_last);
+            return;
+        }
+        self.send(TraderEvent::QueryPosition {
+            position,
+            error_info,
+            request_id,
+            is_last,
+            session_id,
+        });
+    }
+
+    fn on_query_asset(
+        &self,
+        asset: XTPQueryAssetRsp,
+        error_info: XTPRI,
+        request_id: i32,
+        is_last: bool,
+        session_id: u64,
+    ) {
+        if self.queries.assets.contains(request_id) {
+            self.queries
+                .assets
+                .respond(request_id, asset, &error_info, is_last);
+            return;
+        }
+        self.send(TraderEvent::QueryAsset {
+            asset,
+            error_info,
+            request_id,
+            is_last,
+            session_id,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::correlation::RequestTimeout;
+    use super::super::queue::{BackpressurePolicy, ChannelConfig};
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn disconnects_are_queued() {
+        let (queue, mut rx) = EventQueue::new(ChannelConfig {
+            capacity: 1,
+            policy: BackpressurePolicy::DropNewest,
+        })
+        .unwrap();
+        let queries = TraderQueries::new(&RequestTimeout::new(Duration::from_secs(1)));
+        let spi = TSpi::new(queue, Arc::new(queries));
+
+        spi.on_disconnected(1, 7);
+        match rx.recv().await {
+            Some(XTPEvent::Connection(state)) => assert_eq!(
+                state,
+                ConnectionState::Disconnected {
+                    session: Session::Trader,
+                    reason: 7,
+                }
+            ),
+            other => panic!("{:?}", other),
+        }
+    }
 }
@@ -0,0 +1,46 @@
+use crate::market::{Instrument, InstrumentInfo, Venue};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Static data of every instrument of the exchanges, looked up by exchange
+/// and ticker. `XTPExchange` fills it when it starts running.
+#[derive(Default)]
+pub struct InstrumentMaster {
+    instruments: RwLock<HashMap<Instrument, InstrumentInfo>>,
+}
+
+impl InstrumentMaster {
+    pub fn new() -> Self {
+        InstrumentMaster::default()
+    }
+
+    pub fn get(&self, venue: Venue, ticker: &str) -> Option<InstrumentInfo> {
+        let key = Instrument::new(venue, ticker);
+        self.instruments.read().unwrap().get(&key).cloned()
+    }
+
+    pub fn insert(&self, info: InstrumentInfo) {
+        self.instruments
+            .write()
+            .unwrap()
+            .insert(info.instrument.clone(), info);
+    }
+
+    /// Replace the instruments of `venue`, e.g. with the ones of a new
+    /// trading day.
+    pub fn replace(&self, venue: Venue, infos: Vec<InstrumentInfo>) {
+        let mut instruments = self.instruments.write().unwrap();
+        instruments.retain(|instrument, _| instrument.venue != venue);
+        for info in infos {
+            instruments.insert(info.instrument.clone(), info);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -1,16 +1,53 @@
+pub mod backtest;
+pub mod bars;
+pub mod deploy;
+mod error;
+mod event;
 mod exchanges;
+pub mod instruments;
+pub mod market;
+pub mod portfolio;
+pub mod recorder;
+pub mod risk;
 
-pub use crate::exchanges::xtp::XTPExchange;
+pub use crate::backtest::{Backtest, BacktestReport};
+pub use crate::bars::{Bar, BarBuilder, BarSource};
+pub use crate::deploy::{Deployment, StrategyRegistry};
+pub use crate::error::{Error, Result};
+pub use crate::event::{Event, Fill};
+pub use crate::exchanges::replay::ReplayExchange;
+pub use crate::exchanges::sim::{
+    FeeSchedule, FillModel, Pace, RandomWalk, SimExchange, SimExchangeHandle,
+};
+pub use crate::exchanges::xtp::{
+    ApiLogLevel, BackpressurePolicy, ChannelConfig, ClientOrderId, ConnectionState, DeliveryMode,
+    DroppedEvents, KillSwitchConfig, OrderRequest, OrderSnapshot, OrderState, PriceType,
+    QueueConfig, QuoteEvent, QuoteProtocol, QuoteServerConfig, ReconnectPolicy, Session,
+    StrategyId, TraderServerConfig, XTPConfig, XTPExchange, XTPExchangeBuilder, XTPExchangeHandle,
+};
+pub use crate::instruments::InstrumentMaster;
+pub use crate::market::{
+    DepthLevel, Entrust, EntrustType, Instrument, InstrumentInfo, OrderBookL2, Price, Quote,
+    SecurityType, Trade, TradeFlag, Venue,
+};
+pub use crate::portfolio::{Portfolio, Position, Side};
+pub use crate::recorder::Recorder;
+pub use crate::risk::{RiskEngine, RiskLimits, RiskRejection};
 
 use async_trait::async_trait;
+use std::time::Duration;
 use tokio::sync::broadcast::Receiver;
 
 #[async_trait]
 pub trait Exchange: Sized {
     type Event;
-    type Handle;
+    type Handle: ExchangeHandle;
 
-    async fn run(self);
+    /// Establish the sessions with the gateway. `run` connects by itself when
+    /// this has not been called.
+    async fn connect(&mut self) -> Result<()>;
+
+    async fn run(self) -> Result<()>;
 
     fn register<S>(&mut self, s: S)
     where
@@ -21,3 +58,79 @@ pub trait Exchange: Sized {
 pub trait Strategy<E: Exchange> {
     async fn run(self: Box<Self>, rx: Receiver<E::Event>, h: E::Handle);
 }
+
+/// What a strategy can do on any exchange. Strategies written against it,
+/// and against `Event`, run unchanged on every exchange:
+///
+/// ```ignore
+/// impl<E: Exchange<Event = Event>> Strategy<E> for MyStrategy
+/// ```
+pub trait ExchangeHandle: Clone + Send + Sync + 'static {
+    fn subscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    fn unsubscribe_market_data(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    fn subscribe_all_market_data(&self, venue: Venue) -> Result<()>;
+
+    fn unsubscribe_all_market_data(&self, venue: Venue) -> Result<()>;
+
+    fn subscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    fn unsubscribe_tick_by_tick(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    fn subscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    fn unsubscribe_order_book(&self, tickers: &[&str], venue: Venue) -> Result<()>;
+
+    /// Receive `QuoteEvent::Bar` every `interval` for `tickers`, built from
+    /// `source`. The market data itself is only delivered when subscribed
+    /// to separately. Intervals below a millisecond are refused.
+    fn subscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()>;
+
+    fn unsubscribe_bars(
+        &self,
+        tickers: &[&str],
+        venue: Venue,
+        interval: Duration,
+        source: BarSource,
+    ) -> Result<()>;
+
+    /// The most recent depth snapshot of a ticker.
+    fn last_snapshot(&self, ticker: &str, venue: Venue) -> Option<Quote>;
+
+    /// Static data of a ticker, `None` when the instrument master does not
+    /// know it.
+    fn instrument(&self, ticker: &str, venue: Venue) -> Option<InstrumentInfo>;
+
+    /// Submit an order after it passed the risk checks.
+    fn insert_order(&self, req: &OrderRequest) -> Result<ClientOrderId>;
+
+    fn cancel_order(&self, client_id: ClientOrderId) -> Result<()>;
+
+    fn insert_orders(&self, reqs: &[OrderRequest]) -> Vec<Result<ClientOrderId>> {
+        reqs.iter().map(|req| self.insert_order(req)).collect()
+    }
+
+    fn cancel_orders(&self, client_ids: &[ClientOrderId]) -> Vec<Result<()>> {
+        client_ids.iter().map(|&id| self.cancel_order(id)).collect()
+    }
+
+    /// Current state of an order placed through any handle.
+    fn order(&self, client_id: ClientOrderId) -> Option<OrderSnapshot>;
+
+    /// Every order of the trading day.
+    fn orders(&self) -> Vec<OrderSnapshot>;
+
+    fn open_orders(&self) -> Vec<OrderSnapshot>;
+
+    /// A copy of the positions and PnL as of now.
+    fn portfolio(&self) -> Portfolio;
+
+    fn position(&self, ticker: &str, venue: Venue) -> Option<Position>;
+}
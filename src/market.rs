@@ -0,0 +1,262 @@
+//! Market data as pixiu sees it, independent of the broker SDK it came from.
+//! Prices are fixed point, so they compare and hash exactly.
+
+use crate::portfolio::Side;
+use std::fmt;
+use std::ops::{Add, Sub};
+
+/// A price in ten-thousandths of the currency unit, finer than the tick of
+/// any A-share instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Price(i64);
+
+impl Price {
+    pub const SCALE: i64 = 10_000;
+    pub const ZERO: Price = Price(0);
+
+    pub fn from_raw(raw: i64) -> Self {
+        Price(raw)
+    }
+
+    /// Rounded to the nearest ten-thousandth.
+    pub fn from_f64(price: f64) -> Self {
+        Price((price * Price::SCALE as f64).round() as i64)
+    }
+
+    pub fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Price::SCALE as f64
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Add for Price {
+    type Output = Price;
+
+    fn add(self, other: Price) -> Price {
+        Price(self.0 + other.0)
+    }
+}
+
+impl Sub for Price {
+    type Output = Price;
+
+    fn sub(self, other: Price) -> Price {
+        Price(self.0 - other.0)
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.abs();
+        write!(
+            f,
+            "{}{}.{:04}",
+            sign,
+            abs / Price::SCALE,
+            abs % Price::SCALE
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Venue {
+    /// Shanghai Stock Exchange.
+    SH,
+    /// Shenzhen Stock Exchange.
+    SZ,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    pub venue: Venue,
+    pub ticker: String,
+}
+
+impl Instrument {
+    pub fn new(venue: Venue, ticker: &str) -> Self {
+        Instrument {
+            venue,
+            ticker: ticker.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:?}", self.ticker, self.venue)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub quantity: i64,
+}
+
+/// A depth snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub instrument: Instrument,
+    /// Exchange time, YYYYMMDDHHMMSSsss.
+    pub data_time: i64,
+    pub last_price: Price,
+    pub pre_close_price: Price,
+    pub open_price: Price,
+    pub high_price: Price,
+    pub low_price: Price,
+    /// Zero when the instrument has no price limits.
+    pub upper_limit_price: Price,
+    pub lower_limit_price: Price,
+    /// Cumulative volume and turnover of the day.
+    pub volume: i64,
+    pub turnover: f64,
+    /// Best first, without empty levels.
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    /// Quantities of the first orders queued at the best bid and ask, and
+    /// how many orders are queued there in all.
+    pub bid1_orders: Vec<i64>,
+    pub bid1_order_count: i32,
+    pub ask1_orders: Vec<i64>,
+    pub ask1_order_count: i32,
+}
+
+impl Quote {
+    /// A quote without depth nor price limits.
+    pub fn new(instrument: Instrument, data_time: i64, last_price: Price) -> Self {
+        Quote {
+            instrument,
+            data_time,
+            last_price,
+            pre_close_price: Price::ZERO,
+            open_price: Price::ZERO,
+            high_price: Price::ZERO,
+            low_price: Price::ZERO,
+            upper_limit_price: Price::ZERO,
+            lower_limit_price: Price::ZERO,
+            volume: 0,
+            turnover: 0.,
+            bids: vec![],
+            asks: vec![],
+            bid1_orders: vec![],
+            bid1_order_count: 0,
+            ask1_orders: vec![],
+            ask1_order_count: 0,
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<DepthLevel> {
+        self.bids.first().cloned()
+    }
+
+    pub fn best_ask(&self) -> Option<DepthLevel> {
+        self.asks.first().cloned()
+    }
+}
+
+/// Who took liquidity in a trade, or whether it is a cancellation, which
+/// Shenzhen reports as trades.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeFlag {
+    BuyerInitiated,
+    SellerInitiated,
+    Unknown,
+    Cancel,
+}
+
+/// A tick-by-tick trade.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub instrument: Instrument,
+    pub data_time: i64,
+    pub channel_no: i32,
+    pub seq: i64,
+    /// Zero for cancellations.
+    pub price: Price,
+    pub quantity: i64,
+    pub amount: f64,
+    /// Sequence numbers of the orders on both sides.
+    pub bid_no: i64,
+    pub ask_no: i64,
+    pub flag: TradeFlag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntrustType {
+    Limit,
+    Market,
+    /// At the best price on the order's own side.
+    BestOwnSide,
+    Unknown,
+}
+
+/// A tick-by-tick order entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entrust {
+    pub instrument: Instrument,
+    pub data_time: i64,
+    pub channel_no: i32,
+    pub seq: i64,
+    pub price: Price,
+    pub quantity: i64,
+    /// `None` for anything but plain buys and sells.
+    pub side: Option<Side>,
+    pub entrust_type: EntrustType,
+}
+
+/// The order book rebuilt from tick-by-tick data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBookL2 {
+    pub instrument: Instrument,
+    pub data_time: i64,
+    pub last_price: Price,
+    pub volume: i64,
+    pub turnover: f64,
+    pub trades_count: i64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityType {
+    Stock,
+    /// Stocks of the Shanghai STAR Market.
+    TechStock,
+    Index,
+    Fund,
+    Bond,
+    Option,
+    Unknown,
+}
+
+/// Static data of an instrument for the trading day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentInfo {
+    pub instrument: Instrument,
+    pub name: String,
+    pub security_type: SecurityType,
+    pub pre_close_price: Price,
+    /// Zero when the instrument has no price limits.
+    pub upper_limit_price: Price,
+    pub lower_limit_price: Price,
+    pub tick_size: Price,
+    /// Order quantities must be multiples of these, except when selling a
+    /// whole holding.
+    pub buy_lot: i64,
+    pub sell_lot: i64,
+}
+
+impl InstrumentInfo {
+    pub fn has_price_limits(&self) -> bool {
+        !self.upper_limit_price.is_zero()
+    }
+}
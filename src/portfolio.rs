@@ -0,0 +1,255 @@
+use crate::market::Venue;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub venue: Venue,
+    pub ticker: String,
+    pub quantity: i64,
+    /// Shares that can be sold today. Under T+1, shares bought today only
+    /// become sellable on the next trading day.
+    pub sellable_quantity: i64,
+    pub avg_cost: f64,
+    pub realized_pnl: f64,
+    /// Last traded price, 0 until a quote or fill has been seen.
+    pub last_price: f64,
+}
+
+impl Position {
+    fn new(venue: Venue, ticker: &str) -> Self {
+        Position {
+            venue,
+            ticker: ticker.to_string(),
+            quantity: 0,
+            sellable_quantity: 0,
+            avg_cost: 0.,
+            realized_pnl: 0.,
+            last_price: 0.,
+        }
+    }
+
+    pub fn market_value(&self) -> f64 {
+        self.last_price * self.quantity as f64
+    }
+
+    pub fn unrealized_pnl(&self) -> f64 {
+        if self.last_price == 0. {
+            return 0.;
+        }
+        (self.last_price - self.avg_cost) * self.quantity as f64
+    }
+}
+
+/// Positions and PnL built from fills and quotes, following the T+1
+/// settlement of the Shanghai and Shenzhen A-share markets.
+#[derive(Debug, Clone, Default)]
+pub struct Portfolio {
+    positions: HashMap<(Venue, String), Position>,
+    /// YYYYMMDD of the latest event seen.
+    trading_day: u32,
+}
+
+impl Portfolio {
+    pub fn new() -> Self {
+        Portfolio::default()
+    }
+
+    pub fn trading_day(&self) -> u32 {
+        self.trading_day
+    }
+
+    /// Advance to `day` (YYYYMMDD). Everything held overnight becomes
+    /// sellable. The first day seen releases nothing: holdings reconciled
+    /// before it already carry the broker's sellable quantity.
+    pub fn roll_day(&mut self, day: u32) {
+        if day <= self.trading_day {
+            return;
+        }
+        let first = self.trading_day == 0;
+        self.trading_day = day;
+        if first {
+            return;
+        }
+        for position in self.positions.values_mut() {
+            position.sellable_quantity = position.quantity;
+        }
+    }
+
+    pub fn on_fill(&mut self, venue: Venue, ticker: &str, side: Side, quantity: i64, price: f64) {
+        let position = self.entry(venue, ticker);
+        match side {
+            Side::Buy => {
+                let cost = position.avg_cost * position.quantity as f64 + price * quantity as f64;
+                position.quantity += quantity;
+                position.avg_cost = cost / position.quantity as f64;
+            }
+            Side::Sell => {
+                if quantity > position.sellable_quantity {
+                    warn!(
+                        "Selling {} {} but only {} sellable",
+                        quantity, ticker, position.sellable_quantity
+                    );
+                }
+                position.realized_pnl += (price - position.avg_cost) * quantity as f64;
+                position.quantity -= quantity;
+                position.sellable_quantity -= quantity;
+                if position.quantity == 0 {
+                    position.avg_cost = 0.;
+                }
+            }
+        }
+        position.last_price = price;
+    }
+
+    /// Mark the position, if any, to `price`.
+    pub fn on_price(&mut self, venue: Venue, ticker: &str, price: f64) {
+        if let Some(position) = self.positions.get_mut(&(venue, ticker.to_string())) {
+            position.last_price = price;
+        }
+    }
+
+    /// Overwrite holdings with what the broker reports, keeping the realized
+    /// PnL accumulated so far.
+    pub fn reconcile(
+        &mut self,
+        venue: Venue,
+        ticker: &str,
+        quantity: i64,
+        sellable_quantity: i64,
+        avg_cost: f64,
+    ) {
+        let position = self.entry(venue, ticker);
+        if position.quantity != quantity || position.sellable_quantity != sellable_quantity {
+            warn!(
+                "Reconciling {}: {}/{} -> {}/{}",
+                ticker, position.quantity, position.sellable_quantity, quantity, sellable_quantity
+            );
+        }
+        position.quantity = quantity;
+        position.sellable_quantity = sellable_quantity;
+        position.avg_cost = avg_cost;
+    }
+
+    /// Zero the holdings of every position missing from `reported`, which the
+    /// broker no longer has. Call after `reconcile` with everything reported.
+    pub fn close_unreported(&mut self, reported: &HashSet<(Venue, String)>) {
+        for (key, position) in &mut self.positions {
+            if position.quantity == 0 || reported.contains(key) {
+                continue;
+            }
+            warn!(
+                "Reconciling {}: {}/{} -> 0/0",
+                position.ticker, position.quantity, position.sellable_quantity
+            );
+            position.quantity = 0;
+            position.sellable_quantity = 0;
+            position.avg_cost = 0.;
+        }
+    }
+
+    pub fn position(&self, venue: Venue, ticker: &str) -> Option<&Position> {
+        self.positions.get(&(venue, ticker.to_string()))
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &Position> {
+        self.positions.values()
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.positions.values().map(|p| p.realized_pnl).sum()
+    }
+
+    pub fn unrealized_pnl(&self) -> f64 {
+        self.positions.values().map(Position::unrealized_pnl).sum()
+    }
+
+    fn entry(&mut self, venue: Venue, ticker: &str) -> &mut Position {
+        self.positions
+            .entry((venue, ticker.to_string()))
+            .or_insert_with(|| Position::new(venue, ticker))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SH: Venue = Venue::SH;
+
+    fn held(portfolio: &Portfolio) -> (i64, i64) {
+        let position = portfolio.position(SH, "600036").unwrap();
+        (position.quantity, position.sellable_quantity)
+    }
+
+    #[test]
+    fn bought_today_is_sellable_tomorrow() {
+        let mut portfolio = Portfolio::new();
+        portfolio.roll_day(20200102);
+        portfolio.on_fill(SH, "600036", Side::Buy, 100, 10.);
+        assert_eq!(held(&portfolio), (100, 0));
+
+        portfolio.roll_day(20200102);
+        assert_eq!(held(&portfolio), (100, 0));
+        portfolio.roll_day(20200101);
+        assert_eq!(held(&portfolio), (100, 0));
+
+        portfolio.roll_day(20200103);
+        assert_eq!(portfolio.trading_day(), 20200103);
+        assert_eq!(held(&portfolio), (100, 100));
+    }
+
+    #[test]
+    fn sell_realizes_pnl_and_reduces_sellable() {
+        let mut portfolio = Portfolio::new();
+        portfolio.roll_day(20200102);
+        portfolio.on_fill(SH, "600036", Side::Buy, 200, 10.);
+        portfolio.roll_day(20200103);
+        portfolio.on_fill(SH, "600036", Side::Buy, 100, 13.);
+        assert_eq!(held(&portfolio), (300, 200));
+
+        portfolio.on_fill(SH, "600036", Side::Sell, 150, 12.);
+        assert_eq!(held(&portfolio), (150, 50));
+        assert!((portfolio.realized_pnl() - 150.).abs() < 1e-9);
+        assert!((portfolio.unrealized_pnl() - 150.).abs() < 1e-9);
+
+        portfolio.on_fill(SH, "600036", Side::Sell, 150, 12.);
+        let position = portfolio.position(SH, "600036").unwrap();
+        assert_eq!(position.quantity, 0);
+        assert_eq!(position.avg_cost, 0.);
+    }
+
+    #[test]
+    fn first_day_keeps_reconciled_sellable() {
+        let mut portfolio = Portfolio::new();
+        portfolio.reconcile(SH, "600036", 300, 100, 10.);
+        portfolio.roll_day(20200102);
+        assert_eq!(held(&portfolio), (300, 100));
+
+        portfolio.roll_day(20200103);
+        assert_eq!(held(&portfolio), (300, 300));
+    }
+
+    #[test]
+    fn reconcile_overwrites_and_closes_unreported() {
+        let mut portfolio = Portfolio::new();
+        portfolio.roll_day(20200102);
+        portfolio.on_fill(SH, "600036", Side::Buy, 100, 10.);
+        portfolio.on_fill(SH, "601398", Side::Buy, 100, 5.);
+
+        portfolio.reconcile(SH, "600036", 200, 100, 9.);
+        let reported = vec![(SH, "600036".to_string())].into_iter().collect();
+        portfolio.close_unreported(&reported);
+
+        assert_eq!(held(&portfolio), (200, 100));
+        assert_eq!(portfolio.position(SH, "600036").unwrap().avg_cost, 9.);
+        let closed = portfolio.position(SH, "601398").unwrap();
+        assert_eq!((closed.quantity, closed.sellable_quantity), (0, 0));
+    }
+}
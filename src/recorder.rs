@@ -0,0 +1,436 @@
+mod format;
+mod reader;
+mod writer;
+
+pub use self::format::{
+    DepthRecord, MarketRecord, OrderBookRecord, Record, Tick, TickByTickRecord,
+};
+pub use self::reader::{recordings, RecordReader};
+pub use self::writer::RecordWriter;
+use crate::exchanges::xtp::{
+    entrust_side, entrust_side_code, entrust_type, entrust_type_code, trade_flag, trade_flag_code,
+};
+use crate::market::{Entrust, Instrument, OrderBookL2, Quote, Trade, Venue};
+use crate::{Event, Exchange, ExchangeHandle, QuoteEvent, Strategy, XTPExchange};
+use async_trait::async_trait;
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{Receiver, RecvError};
+
+/// Writes every quote it receives to a directory of recordings, one file per
+/// trading day. Register it with the exchange like any strategy.
+pub struct Recorder {
+    dir: PathBuf,
+    market_data: Vec<(Venue, Vec<String>)>,
+    all_market_data: Vec<Venue>,
+    tick_by_tick: Vec<(Venue, Vec<String>)>,
+    order_book: Vec<(Venue, Vec<String>)>,
+}
+
+impl Recorder {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Recorder {
+            dir: dir.into(),
+            market_data: vec![],
+            all_market_data: vec![],
+            tick_by_tick: vec![],
+            order_book: vec![],
+        }
+    }
+
+    pub fn market_data(mut self, tickers: &[&str], venue: Venue) -> Self {
+        self.market_data.push((venue, owned(tickers)));
+        self
+    }
+
+    pub fn all_market_data(mut self, venue: Venue) -> Self {
+        self.all_market_data.push(venue);
+        self
+    }
+
+    pub fn tick_by_tick(mut self, tickers: &[&str], venue: Venue) -> Self {
+        self.tick_by_tick.push((venue, owned(tickers)));
+        self
+    }
+
+    pub fn order_book(mut self, tickers: &[&str], venue: Venue) -> Self {
+        self.order_book.push((venue, owned(tickers)));
+        self
+    }
+
+    fn subscribe(&self, h: &<XTPExchange as Exchange>::Handle) {
+        let mut results = vec![];
+        for (venue, tickers) in &self.market_data {
+            results.push(h.subscribe_market_data(&borrowed(tickers), *venue));
+        }
+        for venue in &self.all_market_data {
+            results.push(h.subscribe_all_market_data(*venue));
+        }
+        for (venue, tickers) in &self.tick_by_tick {
+            results.push(h.subscribe_tick_by_tick(&borrowed(tickers), *venue));
+        }
+        for (venue, tickers) in &self.order_book {
+            results.push(h.subscribe_order_book(&borrowed(tickers), *venue));
+        }
+        for e in results.into_iter().filter_map(Result::err) {
+            error!("Recorder subscription failed: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl Strategy<XTPExchange> for Recorder {
+    async fn run(
+        self: Box<Self>,
+        mut rx: Receiver<<XTPExchange as Exchange>::Event>,
+        h: <XTPExchange as Exchange>::Handle,
+    ) {
+        let mut writer = match RecordWriter::new(&self.dir) {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!("Cannot record to {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        self.subscribe(&h);
+        info!("Recording to {}", self.dir.display());
+
+        loop {
+            match rx.recv().await {
+                Ok(Event::MarketData(quote)) => {
+                    if let Some(record) = record_of(&quote, now_micros()) {
+                        if let Err(e) = writer.write(&record) {
+                            error!("Recorder write failed: {}", e);
+                        }
+                    }
+                }
+                // Bound what a crash can lose to one timer interval.
+                Ok(Event::Timer(_)) => {
+                    if let Err(e) = writer.flush() {
+                        error!("Recorder flush failed: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => warn!("Recorder lagged, {} events lost", n),
+                Err(RecvError::Closed) => break,
+            }
+        }
+        if let Err(e) = writer.flush() {
+            error!("Recorder flush failed: {}", e);
+        }
+    }
+}
+
+// Only Shanghai and Shenzhen can be recorded, see `RecordWriter::write`.
+pub(crate) fn record_of(quote: &QuoteEvent, received_at: u64) -> Option<Record> {
+    let data = match quote {
+        QuoteEvent::Quote(quote) => MarketRecord::Depth(depth_record(quote)),
+        QuoteEvent::Trade(trade) => MarketRecord::TickByTick(trade_record(trade)),
+        QuoteEvent::Entrust(entrust) => MarketRecord::TickByTick(entrust_record(entrust)),
+        QuoteEvent::OrderBook(ob) => MarketRecord::OrderBook(order_book_record(ob)),
+        QuoteEvent::Bar(_) | QuoteEvent::SnapshotsUpdated => return None,
+    };
+    match data.venue() {
+        Venue::SH | Venue::SZ => Some(Record { received_at, data }),
+        _ => None,
+    }
+}
+
+fn depth_record(quote: &Quote) -> DepthRecord {
+    DepthRecord {
+        venue: quote.instrument.venue,
+        ticker: quote.instrument.ticker.clone(),
+        data_time: quote.data_time,
+        last_price: quote.last_price,
+        pre_close_price: quote.pre_close_price,
+        open_price: quote.open_price,
+        high_price: quote.high_price,
+        low_price: quote.low_price,
+        upper_limit_price: quote.upper_limit_price,
+        lower_limit_price: quote.lower_limit_price,
+        volume: quote.volume,
+        turnover: quote.turnover,
+        bids: quote.bids.clone(),
+        asks: quote.asks.clone(),
+        bid1_qty: quote.bid1_orders.clone(),
+        max_bid1_count: quote.bid1_order_count,
+        ask1_qty: quote.ask1_orders.clone(),
+        max_ask1_count: quote.ask1_order_count,
+    }
+}
+
+fn trade_record(trade: &Trade) -> TickByTickRecord {
+    TickByTickRecord {
+        venue: trade.instrument.venue,
+        ticker: trade.instrument.ticker.clone(),
+        seq: trade.seq,
+        data_time: trade.data_time,
+        tick: Tick::Trade {
+            channel_no: trade.channel_no,
+            price: trade.price,
+            qty: trade.quantity,
+            money: trade.amount,
+            bid_no: trade.bid_no,
+            ask_no: trade.ask_no,
+            trade_flag: trade_flag_code(trade.flag),
+        },
+    }
+}
+
+fn entrust_record(entrust: &Entrust) -> TickByTickRecord {
+    TickByTickRecord {
+        venue: entrust.instrument.venue,
+        ticker: entrust.instrument.ticker.clone(),
+        seq: entrust.seq,
+        data_time: entrust.data_time,
+        tick: Tick::Entrust {
+            channel_no: entrust.channel_no,
+            price: entrust.price,
+            qty: entrust.quantity,
+            side: entrust_side_code(entrust.side),
+            ord_type: entrust_type_code(entrust.entrust_type),
+        },
+    }
+}
+
+fn order_book_record(ob: &OrderBookL2) -> OrderBookRecord {
+    OrderBookRecord {
+        venue: ob.instrument.venue,
+        ticker: ob.instrument.ticker.clone(),
+        data_time: ob.data_time,
+        last_price: ob.last_price,
+        volume: ob.volume,
+        turnover: ob.turnover,
+        trades_count: ob.trades_count,
+        bids: ob.bids.clone(),
+        asks: ob.asks.clone(),
+    }
+}
+
+/// The market data a record was made from.
+impl From<MarketRecord> for QuoteEvent {
+    fn from(record: MarketRecord) -> Self {
+        match record {
+            MarketRecord::Depth(depth) => QuoteEvent::Quote(depth.into()),
+            MarketRecord::TickByTick(tick) => tick.into(),
+            MarketRecord::OrderBook(ob) => QuoteEvent::OrderBook(ob.into()),
+        }
+    }
+}
+
+impl From<DepthRecord> for Quote {
+    fn from(depth: DepthRecord) -> Self {
+        Quote {
+            instrument: Instrument::new(depth.venue, &depth.ticker),
+            data_time: depth.data_time,
+            last_price: depth.last_price,
+            pre_close_price: depth.pre_close_price,
+            open_price: depth.open_price,
+            high_price: depth.high_price,
+            low_price: depth.low_price,
+            upper_limit_price: depth.upper_limit_price,
+            lower_limit_price: depth.lower_limit_price,
+            volume: depth.volume,
+            turnover: depth.turnover,
+            bids: depth.bids,
+            asks: depth.asks,
+            bid1_orders: depth.bid1_qty,
+            bid1_order_count: depth.max_bid1_count,
+            ask1_orders: depth.ask1_qty,
+            ask1_order_count: depth.max_ask1_count,
+        }
+    }
+}
+
+impl From<TickByTickRecord> for QuoteEvent {
+    fn from(tick: TickByTickRecord) -> Self {
+        let instrument = Instrument::new(tick.venue, &tick.ticker);
+        match tick.tick {
+            Tick::Entrust {
+                channel_no,
+                price,
+                qty,
+                side,
+                ord_type,
+            } => QuoteEvent::Entrust(Entrust {
+                instrument,
+                data_time: tick.data_time,
+                channel_no,
+                seq: tick.seq,
+                price,
+                quantity: qty,
+                side: entrust_side(side),
+                entrust_type: entrust_type(ord_type),
+            }),
+            Tick::Trade {
+                channel_no,
+                price,
+                qty,
+                money,
+                bid_no,
+                ask_no,
+                trade_flag: flag,
+            } => QuoteEvent::Trade(Trade {
+                instrument,
+                data_time: tick.data_time,
+                channel_no,
+                seq: tick.seq,
+                price,
+                quantity: qty,
+                amount: money,
+                bid_no,
+                ask_no,
+                flag: trade_flag(flag),
+            }),
+        }
+    }
+}
+
+impl From<OrderBookRecord> for OrderBookL2 {
+    fn from(ob: OrderBookRecord) -> Self {
+        OrderBookL2 {
+            instrument: Instrument::new(ob.venue, &ob.ticker),
+            data_time: ob.data_time,
+            last_price: ob.last_price,
+            volume: ob.volume,
+            turnover: ob.turnover,
+            trades_count: ob.trades_count,
+            bids: ob.bids,
+            asks: ob.asks,
+        }
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+fn owned(tickers: &[&str]) -> Vec<String> {
+    tickers.iter().map(|t| t.to_string()).collect()
+}
+
+fn borrowed(tickers: &[String]) -> Vec<&str> {
+    tickers.iter().map(String::as_str).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::format::{decode, encode};
+    use super::*;
+    use crate::market::{DepthLevel, EntrustType, Price, TradeFlag};
+    use crate::portfolio::Side;
+
+    fn levels(prices: &[f64], quantity: i64) -> Vec<DepthLevel> {
+        prices
+            .iter()
+            .map(|&price| DepthLevel {
+                price: Price::from_f64(price),
+                quantity,
+            })
+            .collect()
+    }
+
+    /// A quote with every field set.
+    pub(crate) fn full_quote(ticker: &str, data_time: i64) -> Quote {
+        Quote {
+            instrument: Instrument::new(Venue::SH, ticker),
+            data_time,
+            last_price: Price::from_f64(10.02),
+            pre_close_price: Price::from_f64(9.95),
+            open_price: Price::from_f64(9.98),
+            high_price: Price::from_f64(10.05),
+            low_price: Price::from_f64(9.97),
+            upper_limit_price: Price::from_f64(10.95),
+            lower_limit_price: Price::from_f64(8.96),
+            volume: 123_400,
+            turnover: 1_236_500.5,
+            bids: levels(&[10.01, 10., 9.99], 300),
+            asks: levels(&[10.02, 10.03], 500),
+            bid1_orders: vec![100, 200],
+            bid1_order_count: 2,
+            ask1_orders: vec![500],
+            ask1_order_count: 1,
+        }
+    }
+
+    // Through the bytes of a recording.
+    fn round_trip(event: QuoteEvent) -> QuoteEvent {
+        let buf = encode(&record_of(&event, 1).unwrap()).unwrap();
+        let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        assert_eq!(len as usize, buf.len() - 4);
+        decode(&buf[4..]).unwrap().data.into()
+    }
+
+    #[test]
+    fn depth_round_trips_in_full() {
+        let quote = full_quote("600036", 20200102093000000);
+        match round_trip(QuoteEvent::Quote(quote.clone())) {
+            QuoteEvent::Quote(replayed) => assert_eq!(replayed, quote),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn tick_by_tick_round_trips() {
+        let trade = Trade {
+            instrument: Instrument::new(Venue::SZ, "000001"),
+            data_time: 20200102093000120,
+            channel_no: 2011,
+            seq: 42,
+            price: Price::from_f64(15.31),
+            quantity: 700,
+            amount: 10_717.,
+            bid_no: 40,
+            ask_no: 41,
+            flag: TradeFlag::SellerInitiated,
+        };
+        match round_trip(QuoteEvent::Trade(trade.clone())) {
+            QuoteEvent::Trade(replayed) => assert_eq!(replayed, trade),
+            other => panic!("{:?}", other),
+        }
+
+        let entrust = Entrust {
+            instrument: Instrument::new(Venue::SZ, "000001"),
+            data_time: 20200102093000130,
+            channel_no: 2011,
+            seq: 43,
+            price: Price::from_f64(15.3),
+            quantity: 1000,
+            side: Some(Side::Buy),
+            entrust_type: EntrustType::Limit,
+        };
+        match round_trip(QuoteEvent::Entrust(entrust.clone())) {
+            QuoteEvent::Entrust(replayed) => assert_eq!(replayed, entrust),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn order_book_round_trips() {
+        let ob = OrderBookL2 {
+            instrument: Instrument::new(Venue::SZ, "000001"),
+            data_time: 20200102093003000,
+            last_price: Price::from_f64(15.31),
+            volume: 9_000,
+            turnover: 137_790.,
+            trades_count: 12,
+            bids: levels(&[15.3, 15.29], 1000),
+            asks: levels(&[15.31], 200),
+        };
+        match round_trip(QuoteEvent::OrderBook(ob.clone())) {
+            QuoteEvent::OrderBook(replayed) => assert_eq!(replayed, ob),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_shanghai_and_shenzhen_are_recorded() {
+        assert!(record_of(&QuoteEvent::SnapshotsUpdated, 1).is_none());
+        let mut quote = full_quote("600036", 20200102093000000);
+        quote.instrument.venue = Venue::Unknown;
+        assert!(record_of(&QuoteEvent::Quote(quote), 1).is_none());
+    }
+}
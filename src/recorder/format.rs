@@ -0,0 +1,486 @@
+use crate::market::{DepthLevel, Price, Venue};
+use std::io::{self, Read, Write};
+
+// A recording holds one trading day. It starts with `MAGIC` and `VERSION`,
+// followed by the records, each prefixed with the length of the rest of it:
+//
+//     u32 len | u8 kind | u64 received_at | body
+//
+// Everything is little endian, prices are the i64 of `Price`, strings a
+// u16 length and UTF-8 bytes, depth levels a u8 count and (price, i64
+// quantity) pairs.
+pub(crate) const MAGIC: &[u8; 4] = b"PXMD";
+pub(crate) const VERSION: u16 = 2;
+pub(crate) const HEADER_LEN: u64 = 6;
+
+const KIND_DEPTH: u8 = 1;
+const KIND_TICK_BY_TICK: u8 = 2;
+const KIND_ORDER_BOOK: u8 = 3;
+const TICK_ENTRUST: u8 = 1;
+const TICK_TRADE: u8 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Unix time in microseconds when the recorder received the event.
+    pub received_at: u64,
+    pub data: MarketRecord,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarketRecord {
+    Depth(DepthRecord),
+    TickByTick(TickByTickRecord),
+    OrderBook(OrderBookRecord),
+}
+
+impl MarketRecord {
+    pub fn venue(&self) -> Venue {
+        match self {
+            MarketRecord::Depth(r) => r.venue,
+            MarketRecord::TickByTick(r) => r.venue,
+            MarketRecord::OrderBook(r) => r.venue,
+        }
+    }
+
+    pub fn ticker(&self) -> &str {
+        match self {
+            MarketRecord::Depth(r) => &r.ticker,
+            MarketRecord::TickByTick(r) => &r.ticker,
+            MarketRecord::OrderBook(r) => &r.ticker,
+        }
+    }
+
+    /// Exchange timestamp, YYYYMMDDHHMMSSsss.
+    pub fn data_time(&self) -> i64 {
+        match self {
+            MarketRecord::Depth(r) => r.data_time,
+            MarketRecord::TickByTick(r) => r.data_time,
+            MarketRecord::OrderBook(r) => r.data_time,
+        }
+    }
+}
+
+/// A `QuoteEvent::Quote`.
+#[derive(Debug, Clone)]
+pub struct DepthRecord {
+    pub venue: Venue,
+    pub ticker: String,
+    pub data_time: i64,
+    pub last_price: Price,
+    pub pre_close_price: Price,
+    pub open_price: Price,
+    pub high_price: Price,
+    pub low_price: Price,
+    pub upper_limit_price: Price,
+    pub lower_limit_price: Price,
+    pub volume: i64,
+    pub turnover: f64,
+    /// Best first, empty levels left out.
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    /// Quantities of the orders queued at the best bid and ask.
+    pub bid1_qty: Vec<i64>,
+    pub max_bid1_count: i32,
+    pub ask1_qty: Vec<i64>,
+    pub max_ask1_count: i32,
+}
+
+/// A `QuoteEvent::Trade` or `QuoteEvent::Entrust`.
+#[derive(Debug, Clone)]
+pub struct TickByTickRecord {
+    pub venue: Venue,
+    pub ticker: String,
+    pub seq: i64,
+    pub data_time: i64,
+    pub tick: Tick,
+}
+
+#[derive(Debug, Clone)]
+pub enum Tick {
+    Entrust {
+        channel_no: i32,
+        price: Price,
+        qty: i64,
+        side: u8,
+        ord_type: u8,
+    },
+    Trade {
+        channel_no: i32,
+        price: Price,
+        qty: i64,
+        money: f64,
+        bid_no: i64,
+        ask_no: i64,
+        trade_flag: u8,
+    },
+}
+
+/// A `QuoteEvent::OrderBook`.
+#[derive(Debug, Clone)]
+pub struct OrderBookRecord {
+    pub venue: Venue,
+    pub ticker: String,
+    pub data_time: i64,
+    pub last_price: Price,
+    pub volume: i64,
+    pub turnover: f64,
+    pub trades_count: i64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())
+}
+
+pub(crate) fn read_header<R: Read>(r: &mut R) -> io::Result<()> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a pixiu recording"));
+    }
+    let version = get_u16(r)?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    Ok(())
+}
+
+/// The record with its length prefix. Only Shanghai and Shenzhen records can
+/// be encoded.
+pub(crate) fn encode(record: &Record) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; 4];
+    match &record.data {
+        MarketRecord::Depth(r) => {
+            buf.push(KIND_DEPTH);
+            put_u64(&mut buf, record.received_at);
+            put_exchange(&mut buf, r.venue)?;
+            put_str(&mut buf, &r.ticker);
+            put_i64(&mut buf, r.data_time);
+            for &price in &[
+                r.last_price,
+                r.pre_close_price,
+                r.open_price,
+                r.high_price,
+                r.low_price,
+                r.upper_limit_price,
+                r.lower_limit_price,
+            ] {
+                put_price(&mut buf, price);
+            }
+            put_i64(&mut buf, r.volume);
+            put_f64(&mut buf, r.turnover);
+            put_levels(&mut buf, &r.bids);
+            put_levels(&mut buf, &r.asks);
+            put_quantities(&mut buf, &r.bid1_qty);
+            put_i32(&mut buf, r.max_bid1_count);
+            put_quantities(&mut buf, &r.ask1_qty);
+            put_i32(&mut buf, r.max_ask1_count);
+        }
+        MarketRecord::TickByTick(r) => {
+            buf.push(KIND_TICK_BY_TICK);
+            put_u64(&mut buf, record.received_at);
+            put_exchange(&mut buf, r.venue)?;
+            put_str(&mut buf, &r.ticker);
+            put_i64(&mut buf, r.seq);
+            put_i64(&mut buf, r.data_time);
+            match r.tick {
+                Tick::Entrust {
+                    channel_no,
+                    price,
+                    qty,
+                    side,
+                    ord_type,
+                } => {
+                    buf.push(TICK_ENTRUST);
+                    put_i32(&mut buf, channel_no);
+                    put_price(&mut buf, price);
+                    put_i64(&mut buf, qty);
+                    buf.push(side);
+                    buf.push(ord_type);
+                }
+                Tick::Trade {
+                    channel_no,
+                    price,
+                    qty,
+                    money,
+                    bid_no,
+                    ask_no,
+                    trade_flag,
+                } => {
+                    buf.push(TICK_TRADE);
+                    put_i32(&mut buf, channel_no);
+                    put_price(&mut buf, price);
+                    put_i64(&mut buf, qty);
+                    put_f64(&mut buf, money);
+                    put_i64(&mut buf, bid_no);
+                    put_i64(&mut buf, ask_no);
+                    buf.push(trade_flag);
+                }
+            }
+        }
+        MarketRecord::OrderBook(r) => {
+            buf.push(KIND_ORDER_BOOK);
+            put_u64(&mut buf, record.received_at);
+            put_exchange(&mut buf, r.venue)?;
+            put_str(&mut buf, &r.ticker);
+            put_i64(&mut buf, r.data_time);
+            put_price(&mut buf, r.last_price);
+            put_i64(&mut buf, r.volume);
+            put_f64(&mut buf, r.turnover);
+            put_i64(&mut buf, r.trades_count);
+            put_levels(&mut buf, &r.bids);
+            put_levels(&mut buf, &r.asks);
+        }
+    }
+    let len = (buf.len() - 4) as u32;
+    buf[..4].copy_from_slice(&len.to_le_bytes());
+    Ok(buf)
+}
+
+/// Decode a record without its length prefix.
+pub(crate) fn decode(mut buf: &[u8]) -> io::Result<Record> {
+    let r = &mut buf;
+    let kind = get_u8(r)?;
+    let received_at = get_u64(r)?;
+    let data = match kind {
+        KIND_DEPTH => MarketRecord::Depth(DepthRecord {
+            venue: get_exchange(r)?,
+            ticker: get_str(r)?,
+            data_time: get_i64(r)?,
+            last_price: get_price(r)?,
+            pre_close_price: get_price(r)?,
+            open_price: get_price(r)?,
+            high_price: get_price(r)?,
+            low_price: get_price(r)?,
+            upper_limit_price: get_price(r)?,
+            lower_limit_price: get_price(r)?,
+            volume: get_i64(r)?,
+            turnover: get_f64(r)?,
+            bids: get_levels(r)?,
+            asks: get_levels(r)?,
+            bid1_qty: get_quantities(r)?,
+            max_bid1_count: get_i32(r)?,
+            ask1_qty: get_quantities(r)?,
+            max_ask1_count: get_i32(r)?,
+        }),
+        KIND_TICK_BY_TICK => MarketRecord::TickByTick(TickByTickRecord {
+            venue: get_exchange(r)?,
+            ticker: get_str(r)?,
+            seq: get_i64(r)?,
+            data_time: get_i64(r)?,
+            tick: match get_u8(r)? {
+                TICK_ENTRUST => Tick::Entrust {
+                    channel_no: get_i32(r)?,
+                    price: get_price(r)?,
+                    qty: get_i64(r)?,
+                    side: get_u8(r)?,
+                    ord_type: get_u8(r)?,
+                },
+                TICK_TRADE => Tick::Trade {
+                    channel_no: get_i32(r)?,
+                    price: get_price(r)?,
+                    qty: get_i64(r)?,
+                    money: get_f64(r)?,
+                    bid_no: get_i64(r)?,
+                    ask_no: get_i64(r)?,
+                    trade_flag: get_u8(r)?,
+                },
+                other => return Err(invalid(&format!("unknown tick kind {}", other))),
+            },
+        }),
+        KIND_ORDER_BOOK => MarketRecord::OrderBook(OrderBookRecord {
+            venue: get_exchange(r)?,
+            ticker: get_str(r)?,
+            data_time: get_i64(r)?,
+            last_price: get_price(r)?,
+            volume: get_i64(r)?,
+            turnover: get_f64(r)?,
+            trades_count: get_i64(r)?,
+            bids: get_levels(r)?,
+            asks: get_levels(r)?,
+        }),
+        other => return Err(invalid(&format!("unknown record kind {}", other))),
+    };
+    Ok(Record { received_at, data })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn put_i32(buf: &mut Vec<u8>, v: i32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_i64(buf: &mut Vec<u8>, v: i64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_f64(buf: &mut Vec<u8>, v: f64) {
+    buf.extend_from_slice(&v.to_bits().to_le_bytes());
+}
+
+fn put_price(buf: &mut Vec<u8>, price: Price) {
+    put_i64(buf, price.raw());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_exchange(buf: &mut Vec<u8>, venue: Venue) -> io::Result<()> {
+    buf.push(match venue {
+        Venue::SH => 1,
+        Venue::SZ => 2,
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot record exchange {:?}", other),
+            ))
+        }
+    });
+    Ok(())
+}
+
+fn put_levels(buf: &mut Vec<u8>, levels: &[DepthLevel]) {
+    buf.push(levels.len() as u8);
+    for level in levels {
+        put_price(buf, level.price);
+        put_i64(buf, level.quantity);
+    }
+}
+
+fn put_quantities(buf: &mut Vec<u8>, quantities: &[i64]) {
+    buf.extend_from_slice(&(quantities.len() as u16).to_le_bytes());
+    for &qty in quantities {
+        put_i64(buf, qty);
+    }
+}
+
+pub(crate) fn get_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn get_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn get_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn get_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(i32::from_le_bytes(b))
+}
+
+fn get_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(i64::from_le_bytes(b))
+}
+
+fn get_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn get_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(get_u64(r)?))
+}
+
+fn get_price<R: Read>(r: &mut R) -> io::Result<Price> {
+    Ok(Price::from_raw(get_i64(r)?))
+}
+
+fn get_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut b = vec![0; get_u16(r)? as usize];
+    r.read_exact(&mut b)?;
+    String::from_utf8(b).map_err(|e| invalid(&e.to_string()))
+}
+
+fn get_exchange<R: Read>(r: &mut R) -> io::Result<Venue> {
+    match get_u8(r)? {
+        1 => Ok(Venue::SH),
+        2 => Ok(Venue::SZ),
+        other => Err(invalid(&format!("unknown exchange {}", other))),
+    }
+}
+
+fn get_levels<R: Read>(r: &mut R) -> io::Result<Vec<DepthLevel>> {
+    (0..get_u8(r)?)
+        .map(|_| {
+            Ok(DepthLevel {
+                price: get_price(r)?,
+                quantity: get_i64(r)?,
+            })
+        })
+        .collect()
+}
+
+fn get_quantities<R: Read>(r: &mut R) -> io::Result<Vec<i64>> {
+    (0..get_u16(r)?).map(|_| get_i64(r)).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn level(price: f64, quantity: i64) -> DepthLevel {
+        DepthLevel {
+            price: Price::from_f64(price),
+            quantity,
+        }
+    }
+
+    pub(crate) fn depth(ticker: &str, data_time: i64, last_price: f64) -> Record {
+        Record {
+            received_at: 1_577_928_600_000_000,
+            data: MarketRecord::Depth(DepthRecord {
+                venue: Venue::SH,
+                ticker: ticker.to_string(),
+                data_time,
+                last_price: Price::from_f64(last_price),
+                pre_close_price: Price::from_f64(9.9),
+                open_price: Price::from_f64(9.95),
+                high_price: Price::from_f64(10.2),
+                low_price: Price::from_f64(9.8),
+                upper_limit_price: Price::from_f64(10.89),
+                lower_limit_price: Price::from_f64(8.91),
+                volume: 12_300,
+                turnover: 123_456.5,
+                bids: vec![level(last_price - 0.01, 300), level(last_price - 0.02, 500)],
+                asks: vec![level(last_price, 200)],
+                bid1_qty: vec![100, 200],
+                max_bid1_count: 2,
+                ask1_qty: vec![200],
+                max_ask1_count: 1,
+            }),
+        }
+    }
+
+    #[test]
+    fn unknown_exchange_is_not_encoded() {
+        let mut record = depth("600036", 20200102093000000, 10.);
+        if let MarketRecord::Depth(ref mut r) = record.data {
+            r.venue = Venue::Unknown;
+        }
+        let e = encode(&record).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
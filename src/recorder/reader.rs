@@ -0,0 +1,127 @@
+use super::format::{decode, get_u32, read_header, Record, HEADER_LEN};
+use log::warn;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+pub(crate) const EXTENSION: &str = "pxmd";
+
+// Far above the largest record, which is a depth record with full queues at
+// the best bid and ask. A longer length is garbage.
+const MAX_RECORD_LEN: u32 = 1 << 20;
+
+/// Iterates the records of a recording, in the order they were written.
+/// A record cut short by a crash of the recorder ends the iteration, so
+/// does an implausible record length after reporting it.
+pub struct RecordReader<R = BufReader<File>> {
+    inner: R,
+    offset: u64,
+    corrupt: bool,
+}
+
+impl RecordReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        RecordReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        read_header(&mut inner)?;
+        Ok(RecordReader {
+            inner,
+            offset: HEADER_LEN,
+            corrupt: false,
+        })
+    }
+
+    /// Length of the header and the complete records read so far.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        if self.corrupt {
+            return Ok(None);
+        }
+        let len = match get_u32(&mut self.inner) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if len > MAX_RECORD_LEN {
+            self.corrupt = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record length {} at offset {}", len, self.offset),
+            ));
+        }
+        let mut buf = vec![0; len as usize];
+        match self.inner.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("Truncated record at offset {}", self.offset);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+        self.offset += 4 + u64::from(len);
+        decode(&buf).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        self.read_record().transpose()
+    }
+}
+
+/// The recordings in `dir` with their trading day (YYYYMMDD), oldest first.
+pub fn recordings<P: AsRef<Path>>(dir: P) -> io::Result<Vec<(u32, PathBuf)>> {
+    let mut days = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != EXTENSION) {
+            continue;
+        }
+        let day = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok());
+        if let Some(day) = day {
+            days.push((day, path));
+        }
+    }
+    days.sort();
+    Ok(days)
+}
+
+pub(crate) fn recording_path(dir: &Path, day: u32) -> PathBuf {
+    dir.join(format!("{}.{}", day, EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::format::tests::depth;
+    use super::super::format::{encode, write_header};
+    use super::*;
+
+    #[test]
+    fn stops_at_an_implausible_length() {
+        let first = encode(&depth("600036", 20200102093000000, 10.)).unwrap();
+        let mut buf = vec![];
+        write_header(&mut buf).unwrap();
+        buf.extend_from_slice(&first);
+        buf.extend_from_slice(&u32::max_value().to_le_bytes());
+        buf.extend(encode(&depth("600036", 20200102093003000, 10.1)).unwrap());
+
+        let mut reader = RecordReader::new(&buf[..]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let e = reader.next().unwrap().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(reader.next().is_none());
+        assert_eq!(reader.offset(), HEADER_LEN + first.len() as u64);
+    }
+}
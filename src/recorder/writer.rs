@@ -0,0 +1,178 @@
+use super::format::{encode, write_header, Record};
+use super::reader::{recording_path, RecordReader};
+use crate::exchanges::xtp::trading_day;
+use log::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Appends records to the recording of their trading day in a directory,
+/// switching files when the day changes.
+pub struct RecordWriter {
+    dir: PathBuf,
+    day: u32,
+    file: Option<BufWriter<File>>,
+}
+
+impl RecordWriter {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(RecordWriter {
+            dir,
+            day: 0,
+            file: None,
+        })
+    }
+
+    /// Fails with `InvalidInput`, before touching any file, for records of
+    /// exchanges other than Shanghai and Shenzhen.
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        let buf = encode(record)?;
+        let day = trading_day(record.data.data_time());
+        if self.file.is_none() || day != self.day {
+            self.rotate(day)?;
+        }
+        match self.file {
+            Some(ref mut file) => file.write_all(&buf),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotate(&mut self, day: u32) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let path = recording_path(&self.dir, day);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            write_header(&mut file)?;
+        } else {
+            // A crash may have left half a record at the end, and whatever
+            // is appended after it would be unreadable.
+            let mut reader = RecordReader::open(&path)?;
+            for _ in &mut reader {}
+            if reader.offset() < len {
+                warn!(
+                    "Dropping {} trailing bytes of {}",
+                    len - reader.offset(),
+                    path.display()
+                );
+                file.set_len(reader.offset())?;
+            }
+            file.seek(SeekFrom::End(0))?;
+        }
+        self.day = day;
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::format::tests::depth;
+    use super::super::format::MarketRecord;
+    use super::*;
+    use crate::market::Venue;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pixiu-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn prices(path: &Path) -> Vec<f64> {
+        RecordReader::open(path)
+            .unwrap()
+            .map(|record| match record.unwrap().data {
+                MarketRecord::Depth(r) => r.last_price.to_f64(),
+                _ => panic!("not a depth record"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rotates_by_trading_day() {
+        let dir = temp_dir("rotate");
+        let mut writer = RecordWriter::new(&dir).unwrap();
+        writer
+            .write(&depth("600036", 20200102093000000, 10.))
+            .unwrap();
+        writer
+            .write(&depth("600036", 20200102150000000, 10.5))
+            .unwrap();
+        writer
+            .write(&depth("600036", 20200103093000000, 11.))
+            .unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(prices(&recording_path(&dir, 20200102)), vec![10., 10.5]);
+        assert_eq!(prices(&recording_path(&dir, 20200103)), vec![11.]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drops_a_truncated_tail_before_appending() {
+        let dir = temp_dir("truncated");
+        let path = recording_path(&dir, 20200102);
+        {
+            let mut writer = RecordWriter::new(&dir).unwrap();
+            writer
+                .write(&depth("600036", 20200102093000000, 10.))
+                .unwrap();
+            writer
+                .write(&depth("600036", 20200102093003000, 10.1))
+                .unwrap();
+        }
+        // Cut the last record in half, as a crash mid-write would.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+        assert_eq!(prices(&path), vec![10.]);
+
+        let mut writer = RecordWriter::new(&dir).unwrap();
+        writer
+            .write(&depth("600036", 20200102093006000, 10.2))
+            .unwrap();
+        writer.flush().unwrap();
+        assert_eq!(prices(&path), vec![10., 10.2]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_exchange() {
+        let dir = temp_dir("unknown");
+        let mut record = depth("600036", 20200102093000000, 10.);
+        if let MarketRecord::Depth(ref mut r) = record.data {
+            r.venue = Venue::Unknown;
+        }
+        let mut writer = RecordWriter::new(&dir).unwrap();
+        let e = writer.write(&record).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(!recording_path(&dir, 20200102).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
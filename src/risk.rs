@@ -0,0 +1,475 @@
+use crate::portfolio::Side;
+use failure::Fail;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Pre-trade limits, `None` disables a check.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<i64>,
+    pub max_order_notional: Option<f64>,
+    /// Largest position allowed in any single ticker, in shares.
+    pub max_position: Option<i64>,
+    pub max_orders_per_second: Option<usize>,
+    /// Largest allowed distance of a limit price from the last price, as a
+    /// fraction, e.g. 0.05 for 5%.
+    pub price_collar: Option<f64>,
+    /// Reject limit prices outside the daily limit-up/limit-down band.
+    pub check_price_limits: bool,
+    /// Reject quantities that are not a multiple of the instrument's lot,
+    /// and limit prices that are not a multiple of its tick.
+    pub check_lot_size: bool,
+    pub check_tick_size: bool,
+    /// Engage the kill switch once realized plus unrealized PnL falls below
+    /// minus this amount.
+    pub max_loss: Option<f64>,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        RiskLimits {
+            max_order_quantity: None,
+            max_order_notional: None,
+            max_position: None,
+            max_orders_per_second: None,
+            price_collar: None,
+            check_price_limits: true,
+            check_lot_size: true,
+            check_tick_size: true,
+            max_loss: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Fail)]
+pub enum RiskRejection {
+    #[fail(display = "Quantity {} exceeds the limit of {}", quantity, limit)]
+    OrderQuantity { quantity: i64, limit: i64 },
+    #[fail(display = "Notional {} exceeds the limit of {}", notional, limit)]
+    OrderNotional { notional: f64, limit: f64 },
+    #[fail(
+        display = "Position in {} would reach {}, limit is {}",
+        ticker, position, limit
+    )]
+    Position {
+        ticker: String,
+        position: i64,
+        limit: i64,
+    },
+    #[fail(display = "Selling {} with only {} sellable", quantity, sellable)]
+    Sellable { quantity: i64, sellable: i64 },
+    #[fail(display = "More than {} orders per second", limit)]
+    OrderRate { limit: usize },
+    #[fail(
+        display = "Price {} is more than {} away from last {}",
+        price, collar, last
+    )]
+    PriceCollar { price: f64, last: f64, collar: f64 },
+    #[fail(
+        display = "Price {} is outside the limits [{}, {}]",
+        price, lower, upper
+    )]
+    PriceLimit { price: f64, lower: f64, upper: f64 },
+    #[fail(display = "Quantity {} is not a multiple of the lot {}", quantity, lot)]
+    LotSize { quantity: i64, lot: i64 },
+    #[fail(display = "Price {} is not a multiple of the tick {}", price, tick)]
+    TickSize { price: f64, tick: f64 },
+    #[fail(display = "No reference price for {}", ticker)]
+    NoReferencePrice { ticker: String },
+}
+
+/// Everything the risk engine needs to know about an order.
+#[derive(Debug, Clone)]
+pub struct OrderCheck<'a> {
+    pub ticker: &'a str,
+    pub side: Side,
+    /// `None` for market orders.
+    pub price: Option<f64>,
+    pub quantity: i64,
+    pub position: i64,
+    pub sellable: i64,
+    /// Quantity left in the open orders of the ticker on each side, which
+    /// may still trade before this order.
+    pub open_buy_quantity: i64,
+    pub open_sell_quantity: i64,
+    pub last_price: Option<f64>,
+    pub lower_limit_price: Option<f64>,
+    pub upper_limit_price: Option<f64>,
+    /// From the instrument master, `None` when the instrument is unknown.
+    pub lot_size: Option<i64>,
+    pub tick_size: Option<f64>,
+}
+
+pub struct RiskEngine {
+    limits: RiskLimits,
+    recent_orders: Mutex<VecDeque<Instant>>,
+}
+
+impl RiskEngine {
+    pub fn new(limits: RiskLimits) -> Self {
+        RiskEngine {
+            limits,
+            recent_orders: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    pub fn loss_breached(&self, pnl: f64) -> bool {
+        self.limits.max_loss.map_or(false, |max| pnl < -max)
+    }
+
+    /// Check an order against the limits. Only orders reported through
+    /// `order_sent` count towards the order rate.
+    pub fn check(&self, order: &OrderCheck) -> Result<(), RiskRejection> {
+        let limits = &self.limits;
+
+        if let Some(limit) = limits.max_order_quantity {
+            if order.quantity > limit {
+                return Err(RiskRejection::OrderQuantity {
+                    quantity: order.quantity,
+                    limit,
+                });
+            }
+        }
+
+        // What open sells leave to sell.
+        let sellable = order.sellable - order.open_sell_quantity;
+        if let (true, Some(lot)) = (limits.check_lot_size, order.lot_size) {
+            // Odd lots can only be sold, and all at once.
+            let whole_holding = order.side == Side::Sell && order.quantity == sellable;
+            if lot > 0 && order.quantity % lot != 0 && !whole_holding {
+                return Err(RiskRejection::LotSize {
+                    quantity: order.quantity,
+                    lot,
+                });
+            }
+        }
+
+        if order.side == Side::Sell && order.quantity > sellable {
+            return Err(RiskRejection::Sellable {
+                quantity: order.quantity,
+                sellable,
+            });
+        }
+
+        if let Some(limit) = limits.max_position {
+            // Once the open orders on the same side traded as well.
+            let position = match order.side {
+                Side::Buy => order.position + order.open_buy_quantity + order.quantity,
+                Side::Sell => order.position - order.open_sell_quantity - order.quantity,
+            };
+            if position.abs() > limit {
+                return Err(RiskRejection::Position {
+                    ticker: order.ticker.to_string(),
+                    position,
+                    limit,
+                });
+            }
+        }
+
+        let reference_price = order.price.or(order.last_price);
+        if let Some(limit) = limits.max_order_notional {
+            let price = reference_price.ok_or_else(|| RiskRejection::NoReferencePrice {
+                ticker: order.ticker.to_string(),
+            })?;
+            let notional = price * order.quantity as f64;
+            if notional > limit {
+                return Err(RiskRejection::OrderNotional { notional, limit });
+            }
+        }
+
+        if let Some(price) = order.price {
+            if let Some(collar) = limits.price_collar {
+                let last = order
+                    .last_price
+                    .ok_or_else(|| RiskRejection::NoReferencePrice {
+                        ticker: order.ticker.to_string(),
+                    })?;
+                if (price - last).abs() > last * collar {
+                    return Err(RiskRejection::PriceCollar {
+                        price,
+                        last,
+                        collar,
+                    });
+                }
+            }
+
+            if let (true, Some(tick)) = (limits.check_tick_size, order.tick_size) {
+                let ticks = price / tick;
+                if tick > 0. && (ticks - ticks.round()).abs() > 1e-6 {
+                    return Err(RiskRejection::TickSize { price, tick });
+                }
+            }
+
+            if limits.check_price_limits {
+                if let (Some(lower), Some(upper)) =
+                    (order.lower_limit_price, order.upper_limit_price)
+                {
+                    if price < lower || price > upper {
+                        return Err(RiskRejection::PriceLimit {
+                            price,
+                            lower,
+                            upper,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(limit) = limits.max_orders_per_second {
+            let mut recent = self.recent_orders.lock().unwrap();
+            expire(&mut recent, Instant::now());
+            if recent.len() >= limit {
+                return Err(RiskRejection::OrderRate { limit });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count an order that passed `check` towards the order rate, once the
+    /// exchange took it.
+    pub fn order_sent(&self) {
+        if self.limits.max_orders_per_second.is_some() {
+            let now = Instant::now();
+            let mut recent = self.recent_orders.lock().unwrap();
+            expire(&mut recent, now);
+            recent.push_back(now);
+        }
+    }
+}
+
+// Drop the orders sent more than a second before `now`.
+fn expire(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent
+        .front()
+        .map_or(false, |&t| now.duration_since(t) >= Duration::from_secs(1))
+    {
+        recent.pop_front();
+    }
+}
+
+impl Default for RiskEngine {
+    fn default() -> Self {
+        RiskEngine::new(RiskLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: Side, quantity: i64) -> OrderCheck<'static> {
+        OrderCheck {
+            ticker: "600036",
+            side,
+            price: Some(10.),
+            quantity,
+            position: 1000,
+            sellable: 1000,
+            open_buy_quantity: 0,
+            open_sell_quantity: 0,
+            last_price: Some(10.),
+            lower_limit_price: Some(9.),
+            upper_limit_price: Some(11.),
+            lot_size: Some(100),
+            tick_size: Some(0.01),
+        }
+    }
+
+    #[test]
+    fn open_sells_are_not_sellable_again() {
+        let risk = RiskEngine::default();
+        let mut sell = order(Side::Sell, 600);
+        assert!(risk.check(&sell).is_ok());
+
+        sell.open_sell_quantity = 500;
+        match risk.check(&sell) {
+            Err(RiskRejection::Sellable { sellable, .. }) => assert_eq!(sellable, 500),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn open_orders_count_towards_the_position() {
+        let risk = RiskEngine::new(RiskLimits {
+            max_position: Some(1500),
+            ..RiskLimits::default()
+        });
+        let mut buy = order(Side::Buy, 500);
+        assert!(risk.check(&buy).is_ok());
+
+        buy.open_buy_quantity = 100;
+        match risk.check(&buy) {
+            Err(RiskRejection::Position { position, .. }) => assert_eq!(position, 1600),
+            result => panic!("unexpected {:?}", result),
+        }
+        // Open sells do not make room for more buys.
+        buy.open_buy_quantity = 0;
+        buy.open_sell_quantity = 1000;
+        assert!(risk.check(&buy).is_ok());
+    }
+
+    #[test]
+    fn collar_needs_a_last_price() {
+        let risk = RiskEngine::new(RiskLimits {
+            price_collar: Some(0.05),
+            ..RiskLimits::default()
+        });
+        let mut buy = order(Side::Buy, 100);
+        assert!(risk.check(&buy).is_ok());
+
+        buy.price = Some(10.6);
+        match risk.check(&buy) {
+            Err(RiskRejection::PriceCollar { .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+
+        buy.price = Some(10.);
+        buy.last_price = None;
+        match risk.check(&buy) {
+            Err(RiskRejection::NoReferencePrice { ticker }) => assert_eq!(ticker, "600036"),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_large_quantities() {
+        let risk = RiskEngine::new(RiskLimits {
+            max_order_quantity: Some(1000),
+            ..RiskLimits::default()
+        });
+        assert!(risk.check(&order(Side::Buy, 1000)).is_ok());
+        match risk.check(&order(Side::Buy, 1100)) {
+            Err(RiskRejection::OrderQuantity { quantity, limit }) => {
+                assert_eq!((quantity, limit), (1100, 1000))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn market_orders_are_valued_at_the_last_price() {
+        let risk = RiskEngine::new(RiskLimits {
+            max_order_notional: Some(5000.),
+            ..RiskLimits::default()
+        });
+        assert!(risk.check(&order(Side::Buy, 500)).is_ok());
+        match risk.check(&order(Side::Buy, 600)) {
+            Err(RiskRejection::OrderNotional { notional, .. }) => assert_eq!(notional, 6000.),
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let mut buy = order(Side::Buy, 500);
+        buy.price = None;
+        buy.last_price = Some(10.2);
+        match risk.check(&buy) {
+            Err(RiskRejection::OrderNotional { notional, .. }) => assert_eq!(notional, 5100.),
+            result => panic!("unexpected {:?}", result),
+        }
+        buy.last_price = None;
+        match risk.check(&buy) {
+            Err(RiskRejection::NoReferencePrice { .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn only_sent_orders_count_towards_the_rate() {
+        let risk = RiskEngine::new(RiskLimits {
+            max_order_quantity: Some(1000),
+            max_orders_per_second: Some(2),
+            ..RiskLimits::default()
+        });
+        let buy = order(Side::Buy, 100);
+        // Checked but never sent, e.g. refused by the gateway.
+        for _ in 0..3 {
+            assert!(risk.check(&buy).is_ok());
+        }
+        assert!(risk.check(&order(Side::Buy, 1100)).is_err());
+
+        risk.order_sent();
+        assert!(risk.check(&buy).is_ok());
+        risk.order_sent();
+        match risk.check(&buy) {
+            Err(RiskRejection::OrderRate { limit }) => assert_eq!(limit, 2),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn prices_stay_within_the_limits() {
+        let risk = RiskEngine::default();
+        assert!(risk.check(&order(Side::Buy, 100)).is_ok());
+
+        let mut buy = order(Side::Buy, 100);
+        buy.price = Some(11.01);
+        match risk.check(&buy) {
+            Err(RiskRejection::PriceLimit { lower, upper, .. }) => {
+                assert_eq!((lower, upper), (9., 11.))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        // Market orders have no price to check.
+        buy.price = None;
+        assert!(risk.check(&buy).is_ok());
+
+        let risk = RiskEngine::new(RiskLimits {
+            check_price_limits: false,
+            ..RiskLimits::default()
+        });
+        buy.price = Some(11.01);
+        assert!(risk.check(&buy).is_ok());
+    }
+
+    #[test]
+    fn odd_lots_only_sell_the_whole_holding() {
+        let risk = RiskEngine::default();
+        match risk.check(&order(Side::Buy, 150)) {
+            Err(RiskRejection::LotSize { lot, .. }) => assert_eq!(lot, 100),
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let mut sell = order(Side::Sell, 150);
+        sell.position = 1050;
+        sell.sellable = 1050;
+        match risk.check(&sell) {
+            Err(RiskRejection::LotSize { .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        sell.quantity = 1050;
+        assert!(risk.check(&sell).is_ok());
+        // What open sells leave is the whole holding.
+        sell.open_sell_quantity = 900;
+        sell.quantity = 150;
+        assert!(risk.check(&sell).is_ok());
+
+        // Unknown instruments are not checked.
+        let mut buy = order(Side::Buy, 150);
+        buy.lot_size = None;
+        assert!(risk.check(&buy).is_ok());
+    }
+
+    #[test]
+    fn prices_are_on_the_tick() {
+        let risk = RiskEngine::default();
+        let mut buy = order(Side::Buy, 100);
+        buy.price = Some(10.005);
+        match risk.check(&buy) {
+            Err(RiskRejection::TickSize { tick, .. }) => assert_eq!(tick, 0.01),
+            result => panic!("unexpected {:?}", result),
+        }
+        buy.price = Some(10.07);
+        assert!(risk.check(&buy).is_ok());
+
+        buy.price = Some(10.005);
+        buy.tick_size = None;
+        assert!(risk.check(&buy).is_ok());
+    }
+}